@@ -0,0 +1,36 @@
+//! Times visiting every element of an `Array2` in row major order, in column major order, by bands of rows,
+//! and through `get` in each order.
+//!
+//! Run with `cargo bench --bench iteration`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use array2::Array2;
+
+/// Returns the fastest of `runs` timings of `f`
+fn fastest<T>(runs: usize, mut f: impl FnMut() -> T) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    println!("{:>12} {:>12} {:>12} {:>12} {:>12} {:>12}", "array", "row major", "col major", "row bands", "get (rows)", "get (cols)");
+    for size in [256, 1024, 2048] {
+        let arr = Array2::from((0..size * size).map(|n| n as u64).collect(), size, size);
+        let row_major = fastest(5, || arr.iter_row_maj().map(|(_, _, &v)| v).sum::<u64>());
+        let col_major = fastest(5, || arr.iter_col_maj().map(|(_, _, &v)| v).sum::<u64>());
+        let bands = fastest(5, || arr.row_bands(16).map(|band| band.iter().sum::<u64>()).sum::<u64>());
+        let get_rows = fastest(5, || (0..size).flat_map(|r| (0..size).map(move |c| (r, c))).map(|(r, c)| *arr.get(r, c)).sum::<u64>());
+        let get_cols = fastest(5, || (0..size).flat_map(|c| (0..size).map(move |r| (r, c))).map(|(r, c)| *arr.get(r, c)).sum::<u64>());
+        println!(
+            "{:>12} {:>12.2?} {:>12.2?} {:>12.2?} {:>12.2?} {:>12.2?}",
+            format!("{0} x {0}", size), row_major, col_major, bands, get_rows, get_cols
+        );
+    }
+}
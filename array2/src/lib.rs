@@ -1,3 +1,4 @@
+#[derive(Clone, Debug, PartialEq)]
 pub struct Array2<T: Clone> {
     matrix: Vec<T>,
     height: usize,
@@ -33,7 +34,23 @@ impl<T: Clone> Array2<T> {
     /// &T: a reference to an element in the matrix
     pub fn get(&self, row: usize, col: usize) -> &T {
         assert!(row < self.height && col < self.width);
-        &(self.matrix[row * self.height + col])
+        &(self.matrix[row * self.width + col])
+    }
+
+    /// Method that returns the number of rows in the matrix
+    ///
+    /// # Arguments
+    /// * &self: an immutable reference to an instance of Array2
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Method that returns the number of columns in the matrix
+    ///
+    /// # Arguments
+    /// * &self: an immutable reference to an instance of Array2
+    pub fn width(&self) -> usize {
+        self.width
     }
 
     /// Checks to see if matrix is in row major order and
@@ -51,6 +68,20 @@ impl<T: Clone> Array2<T> {
             .map(|(idx, val)| ((idx / self.width), (idx % self.width), val))
     }
 
+    /// Method that splits the matrix into bands of whole rows, returning an iterator over
+    /// the elements of each band as a slice in row major order. The last band holds whatever
+    /// rows are left over, so it may be shorter than the others.
+    ///
+    /// # Arguments
+    /// * &self: an immutable reference to an instance of Array2
+    /// * rows_per_band: the number of rows in each band, which must be at least 1
+    ///
+    /// impl Iterator<Item=&[T]>: an iterator over the bands from top to bottom
+    pub fn row_bands(&self, rows_per_band: usize) -> impl Iterator<Item = &[T]> {
+        assert!(rows_per_band > 0);
+        self.matrix.chunks((rows_per_band * self.width).max(1))
+    }
+
     /// Checks to see if the matrix is not in row major order and
     /// returns an iterator over the elements in column major order
     ///
@@ -111,4 +142,19 @@ mod tests {
         let four = arr.get(1, 0);
         assert_eq!(four, &4);
     }
+
+    #[test]
+    fn get_non_square_test() {
+        let arr = Array2::from((1..=6).collect(), 2, 3);
+        assert_eq!(arr.get(1, 0), &4);
+        assert_eq!(arr.get(1, 2), &6);
+        assert_eq!((arr.height(), arr.width()), (2, 3));
+    }
+
+    #[test]
+    fn row_bands_test() {
+        let arr = Array2::from((1..=10).collect(), 5, 2);
+        let bands: Vec<&[i32]> = arr.row_bands(2).collect();
+        assert_eq!(bands, vec![&[1, 2, 3, 4][..], &[5, 6, 7, 8][..], &[9, 10][..]]);
+    }
 }
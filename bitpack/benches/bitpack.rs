@@ -0,0 +1,62 @@
+//! Times packing fields into words with `newu` and `news` and reading them back out with `getu` and `gets`,
+//! using the layout of an rpeg word.
+//!
+//! Run with `cargo bench --bench bitpack`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use bitpack::bitpack;
+
+/// The number of words packed or unpacked in each run
+const WORDS: u64 = 1_000_000;
+
+/// Returns the fastest of `runs` timings of `f`
+fn fastest<T>(runs: usize, mut f: impl FnMut() -> T) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+/// Prints the time taken to process `WORDS` words, and the time per word
+fn report(name: &str, time: Duration) {
+    println!("{:<24} {:>12.2?} {:>10.2} ns/word", name, time, time.as_nanos() as f64 / WORDS as f64);
+}
+
+fn main() {
+    let words: Vec<u64> = (0..WORDS).map(|n| n.wrapping_mul(0x9e37_79b9) & 0xffff_ffff).collect();
+
+    report("newu (6 fields)", fastest(10, || {
+        (0..WORDS).fold(0, |acc, n| {
+            let mut word = 0;
+            for (width, lsb) in [(9, 23), (5, 18), (5, 13), (5, 8), (4, 4), (4, 0)] {
+                word = bitpack::newu(word, width, lsb, black_box(n) & bitpack::maxu(width)).unwrap();
+            }
+            acc ^ word
+        })
+    }));
+    report("news (3 fields)", fastest(10, || {
+        (0..WORDS).fold(0, |acc, n| {
+            let value = (black_box(n) % 31) as i64 - 15;
+            let mut word = 0;
+            for lsb in [18, 13, 8] {
+                word = bitpack::news(word, 5, lsb, value).unwrap();
+            }
+            acc ^ word
+        })
+    }));
+    report("getu (3 fields)", fastest(10, || {
+        words.iter().fold(0, |acc, &word| {
+            [(9, 23), (4, 4), (4, 0)].iter().fold(acc, |acc, &(width, lsb)| acc ^ bitpack::getu(word, width, lsb))
+        })
+    }));
+    report("gets (3 fields)", fastest(10, || {
+        words.iter().fold(0, |acc, &word| {
+            [18, 13, 8].iter().fold(acc, |acc, &lsb| acc ^ bitpack::gets(word, 5, lsb))
+        })
+    }));
+}
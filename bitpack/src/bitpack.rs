@@ -126,7 +126,7 @@ pub fn maxu(width: u64) -> u64 {
 pub fn maxs(width: u64) -> i64 {
     match width {
         0 => 0,
-        1..=62 => (1_i64 << (width - 1)) -1,
+        1..=63 => (1_i64 << (width - 1)) -1,
         _ => ((1_u64 << 63) - 1) as i64, // all bits on sans signed bit
     }
 }
@@ -251,6 +251,7 @@ mod tests {
         assert_eq!(bitpack::maxs(0), 0);
         assert_eq!(bitpack::maxs(5), 0b1111);
         assert_eq!(bitpack::maxs(48), 0x7fffffffffff);
+        assert_eq!(bitpack::maxs(63), 0x3fffffffffffffff);
         assert_eq!(bitpack::maxs(75), i64::MAX);
     }
 
@@ -305,4 +306,59 @@ mod tests {
         assert_eq!(bitpack::news(0, 45, 20, 6), None); //target area extends outside of word
         assert_eq!(bitpack::news(0b11000000, 3, 5, -3), None) //target area is not empty
     }
+
+    /// A small xorshift generator for the property tests, which gives the same numbers on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Returns a random field as (width, lsb), with a width from 1 to 64 that fits in the word
+        fn field(&mut self) -> (u64, u64) {
+            let width = self.next() % 64 + 1;
+            (width, self.next() % (65 - width))
+        }
+    }
+
+    #[test]
+    fn newu_getu_round_trip() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..100_000 {
+            let (width, lsb) = rng.field();
+            let value = rng.next() & bitpack::maxu(width);
+            // the field must be empty, and every other bit of the word is kept
+            let field_mask = bitpack::maxu(width) << lsb;
+            let word = rng.next() & !field_mask;
+            let packed = bitpack::newu(word, width, lsb, value).unwrap();
+            assert_eq!(bitpack::getu(packed, width, lsb), value, "width {} lsb {}", width, lsb);
+            assert_eq!(packed & !field_mask, word);
+            // values one bit too wide never fit
+            if width < 64 {
+                assert_eq!(bitpack::newu(word, width, lsb, value | (1 << width)), None);
+            }
+        }
+    }
+
+    #[test]
+    fn news_gets_round_trip() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100_000 {
+            let (width, lsb) = rng.field();
+            // any value from -2^(width - 1) to 2^(width - 1) - 1
+            let value = ((rng.next() << (64 - width)) as i64) >> (64 - width);
+            let field_mask = bitpack::maxu(width) << lsb;
+            let word = rng.next() & !field_mask;
+            let packed = bitpack::news(word, width, lsb, value).unwrap();
+            assert_eq!(bitpack::gets(packed, width, lsb), value, "width {} lsb {}", width, lsb);
+            assert_eq!(packed & !field_mask, word);
+            if width < 64 {
+                assert_eq!(bitpack::news(word, width, lsb, bitpack::maxs(width) + 1), None);
+            }
+        }
+    }
 }
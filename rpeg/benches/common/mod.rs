@@ -0,0 +1,16 @@
+//! Helpers shared by the rpeg benchmarks.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Returns the fastest of `runs` timings of `f`
+pub fn fastest<T>(runs: usize, mut f: impl FnMut() -> T) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
@@ -0,0 +1,41 @@
+//! Times `codec::decompress_image`, which writes every decoded pixel straight into its place, against the
+//! approach it replaced: collecting every pixel with its coordinates and sorting them into row major order.
+//!
+//! Run with `cargo bench --bench decode`.
+
+mod common;
+
+use csc411_image::Rgb;
+use rpeg::{codec, testimages};
+use rpeg::container::CompressedImage;
+use rpeg::pixel_block::PixelBlock;
+use common::fastest;
+
+/// Decodes the pixels of `rpeg_img` the way the decoder used to, by gathering and sorting them
+fn collect_and_sort(rpeg_img: &CompressedImage) -> Vec<Rgb> {
+    let (layout, denominator) = (rpeg_img.layout(), rpeg_img.denominator as f64);
+    let mut img = Vec::new();
+    for (word_row, word_col, &word) in rpeg_img.words.iter_row_maj() {
+        let [top_left, top_right, bottom_left, bottom_right] = codec::decode_block(word, denominator, layout);
+        let group = PixelBlock::pack(word_row * 2, word_col * 2, top_left, top_right, bottom_left, bottom_right);
+        img.append(&mut group.unpack());
+    }
+    let mut indexed: Vec<(usize, Rgb)> = img.into_iter()
+        .map(|(r, c, pixel)| (r * rpeg_img.width as usize + c, pixel))
+        .collect();
+    indexed.sort_by_key(|(idx, _)| *idx);
+    indexed.into_iter().map(|(_, pixel)| pixel).collect()
+}
+
+fn main() {
+    println!("{:>12} {:>16} {:>16} {:>8}", "image", "collect + sort", "direct", "speedup");
+    for size in [256, 1024, 2048] {
+        let compressed = codec::compress_image(&testimages::gradient(size, size));
+        let sorted = fastest(5, || collect_and_sort(&compressed));
+        let direct = fastest(5, || codec::decompress_image(&compressed));
+        println!(
+            "{:>12} {:>16.2?} {:>16.2?} {:>7.2}x",
+            format!("{0} x {0}", size), sorted, direct, sorted.as_secs_f64() / direct.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Times every stage of the rpeg pipeline: colour conversion, the block transform and its quantization,
+//! encoding and decoding single blocks, and whole images through `compress_image` and `decompress_image`.
+//!
+//! Run with `cargo bench --bench pipeline`.
+
+mod common;
+
+use std::time::Duration;
+use csc411_image::Rgb;
+use rpeg::{codec, pixel_conversion, quantize, testimages};
+use rpeg::pixel_conversion::{ComponentPlanes, RgbPlanes};
+use rpeg::quantize::{CoefficientPlanes, LumaPlanes, RGB_LAYOUT};
+use common::fastest;
+
+/// The number of pixels or blocks in each run of the stages that work on one at a time
+const ITEMS: usize = 1 << 20;
+
+/// Prints the time taken to process `items` pixels or blocks, and the time per item
+fn report(name: &str, time: Duration, items: usize) {
+    println!("{:<40} {:>12.2?} {:>10.2} ns/item", name, time, time.as_nanos() as f64 / items as f64);
+}
+
+fn main() {
+    let values: Vec<f64> = (0..ITEMS).map(|n| (n * 7919 % 1000) as f64 / 999.0).collect();
+    let pixels: Vec<Rgb> = (0..ITEMS).map(|n| Rgb { red: (n % 256) as u16, green: (n / 256 % 256) as u16, blue: (n * 7 % 256) as u16 }).collect();
+
+    println!("stage, {} items per run", ITEMS);
+    report("rgb_to_float", fastest(10, || {
+        pixels.iter().map(|p| pixel_conversion::rgb_to_float(p, 255.0).0).sum::<f64>()
+    }), ITEMS);
+    // the one-at-a-time functions write into planes too, so that they do the same work as the batch functions, which
+    // also allocate the planes they return
+    let rgb = RgbPlanes { red: values.clone(), green: values.iter().map(|v| 1.0 - v).collect(), blue: values.iter().map(|v| v * 0.5).collect() };
+    let mut comp = ComponentPlanes { y: vec![0.0; ITEMS], pb: vec![0.0; ITEMS], pr: vec![0.0; ITEMS] };
+    report("get_compression_tuple", fastest(10, || {
+        for i in 0..ITEMS {
+            (comp.y[i], comp.pb[i], comp.pr[i]) = pixel_conversion::get_compression_tuple((rgb.red[i], rgb.green[i], rgb.blue[i]));
+        }
+    }), ITEMS);
+    report("get_compression_planes", fastest(10, || pixel_conversion::get_compression_planes(&rgb)), ITEMS);
+    let mut rgb_out = RgbPlanes { red: vec![0.0; ITEMS], green: vec![0.0; ITEMS], blue: vec![0.0; ITEMS] };
+    report("get_decompression_tuple", fastest(10, || {
+        for i in 0..ITEMS {
+            (rgb_out.red[i], rgb_out.green[i], rgb_out.blue[i]) = pixel_conversion::get_decompression_tuple((comp.y[i], comp.pb[i], comp.pr[i]));
+        }
+    }), ITEMS);
+    report("get_decompression_planes", fastest(10, || pixel_conversion::get_decompression_planes(&comp)), ITEMS);
+    report("float_to_rgb", fastest(10, || {
+        values.iter().map(|&v| pixel_conversion::float_to_rgb((v, v, v), 255.0).red as u64).sum::<u64>()
+    }), ITEMS);
+
+    let luma = LumaPlanes { y1: values.clone(), y2: values.iter().map(|v| 1.0 - v).collect(), y3: values.iter().map(|v| v * 0.5).collect(), y4: values.iter().map(|v| v * v).collect() };
+    let mut coefficients = CoefficientPlanes { a: vec![0.0; ITEMS], b: vec![0.0; ITEMS], c: vec![0.0; ITEMS], d: vec![0.0; ITEMS] };
+    report("get_coefficients", fastest(10, || {
+        for i in 0..ITEMS {
+            (coefficients.a[i], coefficients.b[i], coefficients.c[i], coefficients.d[i]) =
+                quantize::get_coefficients(luma.y1[i], luma.y2[i], luma.y3[i], luma.y4[i]);
+        }
+    }), ITEMS);
+    report("get_coefficient_planes", fastest(10, || quantize::get_coefficient_planes(&luma)), ITEMS);
+    let mut luma_out = LumaPlanes { y1: vec![0.0; ITEMS], y2: vec![0.0; ITEMS], y3: vec![0.0; ITEMS], y4: vec![0.0; ITEMS] };
+    report("get_luma", fastest(10, || {
+        for i in 0..ITEMS {
+            (luma_out.y1[i], luma_out.y2[i], luma_out.y3[i], luma_out.y4[i]) =
+                quantize::get_luma(coefficients.a[i], coefficients.b[i], coefficients.c[i], coefficients.d[i]);
+        }
+    }), ITEMS);
+    report("get_luma_planes", fastest(10, || quantize::get_luma_planes(&coefficients)), ITEMS);
+    report("coefficients_to_scaled_ints", fastest(10, || {
+        values.iter().map(|&v| quantize::coefficients_to_scaled_ints(v - 0.5, 0.5 - v, v * 0.1).0).sum::<i64>()
+    }), ITEMS);
+    report("chroma_to_indices (4 bit)", fastest(10, || {
+        values.iter().map(|&v| RGB_LAYOUT.chroma_to_indices(v - 0.5, 0.5 - v).0).sum::<u64>()
+    }), ITEMS);
+
+    let blocks = ITEMS / 4;
+    report("encode_block", fastest(10, || {
+        pixels.chunks_exact(4).map(|p| codec::encode_block([&p[0], &p[1], &p[2], &p[3]], 255.0, RGB_LAYOUT)).sum::<u64>()
+    }), blocks);
+    report("decode_block", fastest(10, || {
+        (0..blocks as u64).map(|word| codec::decode_block((word * 4093) & 0xffff_ffff, 255.0, RGB_LAYOUT)[3].red as u64).sum::<u64>()
+    }), blocks);
+
+    println!();
+    println!("{:>12} {:>12} {:>12} {:>14} {:>14}", "image", "compress", "decompress", "compress MP/s", "decompress MP/s");
+    for size in [256, 1024, 2048] {
+        let img = testimages::gradient(size, size);
+        let compress = fastest(5, || codec::compress_image(&img));
+        let compressed = codec::compress_image(&img);
+        let decompress = fastest(5, || codec::decompress_image(&compressed));
+        let megapixels = (size * size) as f64 / 1e6;
+        println!(
+            "{:>12} {:>12.2?} {:>12.2?} {:>14.1} {:>14.1}",
+            format!("{0} x {0}", size), compress, decompress,
+            megapixels / compress.as_secs_f64(), megapixels / decompress.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,113 @@
+#!/usr/bin/env python3
+"""Writes the PNG fixtures used by the tests in src/png.rs.
+
+Every fixture is built from the same formulas as the tests: sample c of the pixel at (x, y) is
+(x * 37 + y * 59 + c * 101) % (maxval + 1), and palette images use color (x + 2 * y) % len(palette).
+Row y of every image (or of every interlaced pass) uses filter type y % 5, so all five filters are covered.
+"""
+import struct
+import zlib
+
+ADAM7 = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)]
+CHANNELS = {0: 1, 2: 3, 3: 1, 4: 2, 6: 4}
+
+
+def palette(count):
+    return [((i * 40) % 256, (i * 90) % 256, (i * 150) % 256) for i in range(count)]
+
+
+def sample(x, y, c, maxval):
+    return (x * 37 + y * 59 + c * 101) % (maxval + 1)
+
+
+def pixel(x, y, color_type, bit_depth, palette_len):
+    if color_type == 3:
+        return [(x + 2 * y) % palette_len]
+    return [sample(x, y, c, (1 << bit_depth) - 1) for c in range(CHANNELS[color_type])]
+
+
+def scanline(pixels, bit_depth):
+    if bit_depth == 16:
+        return b"".join(struct.pack(">H", v) for p in pixels for v in p)
+    if bit_depth == 8:
+        return bytes(v for p in pixels for v in p)
+    bits = "".join(format(v, "0%db" % bit_depth) for p in pixels for v in p)
+    bits += "0" * (-len(bits) % 8)
+    return bytes(int(bits[i:i + 8], 2) for i in range(0, len(bits), 8))
+
+
+def paeth(a, b, c):
+    p = a + b - c
+    pa, pb, pc = abs(p - a), abs(p - b), abs(p - c)
+    return a if pa <= pb and pa <= pc else b if pb <= pc else c
+
+
+def filter_rows(rows, bpp):
+    out = b""
+    prev = bytes(len(rows[0])) if rows else b""
+    for y, row in enumerate(rows):
+        f = y % 5
+        filtered = bytearray()
+        for i, v in enumerate(row):
+            a = row[i - bpp] if i >= bpp else 0
+            b = prev[i]
+            c = prev[i - bpp] if i >= bpp else 0
+            pred = [0, a, b, (a + b) // 2, paeth(a, b, c)][f]
+            filtered.append((v - pred) % 256)
+        out += bytes([f]) + bytes(filtered)
+        prev = row
+    return out
+
+
+def chunk(kind, data):
+    return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+
+def write(name, width, height, color_type, bit_depth, interlace=0, level=9, palette_len=0, trns=None,
+          idat_size=None):
+    bpp = max(1, CHANNELS[color_type] * bit_depth // 8)
+    passes = ADAM7 if interlace else [(0, 0, 1, 1)]
+    raw = b""
+    for (x0, y0, dx, dy) in passes:
+        rows = []
+        for y in range(y0, height, dy):
+            pixels = [pixel(x, y, color_type, bit_depth, palette_len) for x in range(x0, width, dx)]
+            if pixels:
+                rows.append(scanline(pixels, bit_depth))
+        raw += filter_rows(rows, bpp)
+    data = zlib.compress(raw, level)
+    chunks = chunk(b"IHDR", struct.pack(">IIBBBBB", width, height, bit_depth, color_type, 0, 0, interlace))
+    if palette_len:
+        chunks += chunk(b"PLTE", bytes(v for color in palette(palette_len) for v in color))
+    if trns is not None:
+        chunks += chunk(b"tRNS", trns)
+    # an ancillary chunk that decoders must skip
+    chunks += chunk(b"tEXt", b"Comment\x00rpeg fixture")
+    step = idat_size or len(data)
+    for i in range(0, len(data), step):
+        chunks += chunk(b"IDAT", data[i:i + step])
+    chunks += chunk(b"IEND", b"")
+    with open(name + ".png", "wb") as f:
+        f.write(b"\x89PNG\r\n\x1a\n" + chunks)
+
+
+if __name__ == "__main__":
+    for bits in (1, 2, 4, 8, 16):
+        write("gray%d" % bits, 7, 5, 0, bits)
+    write("rgb8", 7, 5, 2, 8)
+    write("rgb16", 7, 5, 2, 16)
+    write("graya8", 7, 5, 4, 8)
+    write("graya16", 7, 5, 4, 16)
+    write("rgba8", 7, 5, 6, 8)
+    write("rgba16", 7, 5, 6, 16)
+    write("palette1", 7, 5, 3, 1, palette_len=2)
+    write("palette4", 7, 5, 3, 4, palette_len=11)
+    write("palette8_trns", 7, 5, 3, 8, palette_len=11, trns=bytes([0, 128, 255, 64]))
+    write("gray8_trns", 7, 5, 0, 8, trns=struct.pack(">H", 59))
+    write("rgb8_trns", 7, 5, 2, 8, trns=struct.pack(">HHH", 0, 101, 202))
+    write("rgb8_interlaced", 7, 5, 2, 8, interlace=1)
+    write("palette2_interlaced", 7, 5, 3, 2, interlace=1, palette_len=4)
+    write("rgb8_stored", 7, 5, 2, 8, level=0)
+    write("rgb8_split", 7, 5, 2, 8, idat_size=3)
+    # large enough that zlib uses dynamic Huffman blocks
+    write("rgb8_large", 120, 90, 2, 8)
@@ -0,0 +1,17 @@
+//! Feeds arbitrary bytes to the rpeg decoder, both whole-file and streaming. Every input must either be rejected
+//! with an error or decode to an image whose samples match its dimensions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpeg::codec;
+use rpeg::container::CompressedImage;
+use rpeg::stream;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(img) = CompressedImage::from_bytes(data) {
+        let decoded = codec::decompress_image(&img);
+        assert_eq!(decoded.samples.len(), decoded.width as usize * decoded.height as usize * decoded.depth as usize);
+    }
+    let _ = stream::decompress_stream(data, &mut std::io::sink());
+});
@@ -0,0 +1,13 @@
+//! Feeds arbitrary bytes to the rpeg header parser. Every input must either be rejected with an error or give
+//! a header that is written back out as text that parses to the same header.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpeg::container::Header;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = Header::read(&mut &data[..]) {
+        assert_eq!(Header::read(&mut &header.to_bytes()[..]), Ok(header));
+    }
+});
@@ -0,0 +1,16 @@
+//! Feeds arbitrary bytes to the Netpbm readers, both whole-file and row by row. Every input must either be
+//! rejected with an error or give an image whose samples match its header, which then compresses without panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rpeg::{codec, pnm, stream};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(img) = pnm::parse(data) {
+        assert_eq!(img.samples.len(), img.width as usize * img.height as usize * img.depth as usize);
+        assert!(img.samples.iter().all(|&v| v <= img.maxval));
+        codec::compress_image(&img);
+    }
+    let _ = stream::compress_stream(data, &mut std::io::sink());
+});
@@ -0,0 +1,194 @@
+use array2::Array2;
+use crate::container::{self, ColourModel};
+use crate::{fixed_point, quantize};
+
+/// The alpha channel of a compressed image, stored as a plane separate from the color words
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlphaPlane {
+    /// alpha run through the same 2 x 2 transform as luma, with one word per block packed like a grayscale word
+    Transform(Array2<u64>),
+    /// a lossless mask for images whose pixels are all fully transparent or fully opaque, where true is opaque
+    Mask(Array2<bool>),
+}
+
+/// Returns the compressed alpha plane of an image, using a mask when every pixel is fully transparent or fully opaque,
+/// and the 2 x 2 transform otherwise
+///
+/// # Arguments:
+/// * `alpha`: the alpha value of every pixel, in an image with an even width and height
+/// * `denominator`: the largest alpha value, which is fully opaque
+pub fn compress_alpha(alpha: &Array2<u16>, denominator: u16) -> AlphaPlane {
+    if alpha.iter_row_maj().all(|(_, _, &value)| value == 0 || value == denominator) {
+        let mask = alpha.iter_row_maj().map(|(_, _, &value)| value == denominator).collect();
+        return AlphaPlane::Mask(Array2::from(mask, alpha.height(), alpha.width()));
+    }
+
+    // every block of alpha values is treated the same way as a block of luma, using integer arithmetic only
+    // with the `fixed-point` feature
+    let layout = container::layout_for(ColourModel::Gray, denominator);
+    let mut words = Vec::new();
+    let value = |row: usize, col: usize| *alpha.get(row, col) as f64 / denominator as f64;
+    for r in (0..alpha.height()).step_by(2) {
+        for col in (0..alpha.width()).step_by(2) {
+            let block = [(r, col), (r, col + 1), (r + 1, col), (r + 1, col + 1)];
+            if cfg!(feature = "fixed-point") {
+                let luma = block.map(|(row, col)| fixed_point::sample_to_fixed(*alpha.get(row, col), denominator));
+                let (a, b, c, d) = fixed_point::luma_to_fields(luma, layout);
+                words.push(layout.pack(a, b, c, d, 0, 0));
+            } else {
+                let [y1, y2, y3, y4] = block.map(|(row, col)| value(row, col));
+                let (a, b, c, d) = quantize::get_coefficients(y1, y2, y3, y4);
+                let (b_int, c_int, d_int) = quantize::coefficients_to_scaled_ints(b, c, d);
+                words.push(layout.pack(layout.coefficient_to_scaled_int(a), b_int, c_int, d_int, 0, 0));
+            }
+        }
+    }
+    AlphaPlane::Transform(Array2::from(words, alpha.height() / 2, alpha.width() / 2))
+}
+
+/// Returns the alpha value of every pixel held in a compressed alpha plane
+///
+/// # Arguments:
+/// * `plane`: the compressed alpha plane
+/// * `denominator`: the largest alpha value, which is fully opaque
+pub fn decompress_alpha(plane: &AlphaPlane, denominator: u16) -> Array2<u16> {
+    match plane {
+        AlphaPlane::Mask(mask) => {
+            let values = mask.iter_row_maj().map(|(_, _, &opaque)| if opaque { denominator } else { 0 }).collect();
+            Array2::from(values, mask.height(), mask.width())
+        }
+        AlphaPlane::Transform(words) => {
+            let layout = container::layout_for(ColourModel::Gray, denominator);
+            let (height, width) = (words.height() * 2, words.width() * 2);
+            let mut values = vec![0; height * width];
+            for (row, col, &word) in words.iter_row_maj() {
+                let (a, b, c, d, _, _) = layout.unpack(word);
+                let block = if cfg!(feature = "fixed-point") {
+                    fixed_point::fields_to_luma((a, b, c, d), layout).map(|y| fixed_point::fixed_to_sample(y, denominator))
+                } else {
+                    let (b_float, c_float, d_float) = quantize::scaled_ints_to_coefficients(b, c, d);
+                    let (y1, y2, y3, y4) = quantize::get_luma(layout.scaled_int_to_coefficient(a), b_float, c_float, d_float);
+                    [y1, y2, y3, y4].map(|y| (y.clamp(0.0, 1.0) * denominator as f64).round() as u16)
+                };
+
+                // write each value straight into its position in the plane
+                let (r, c) = (row * 2, col * 2);
+                for (idx, value) in [r * width + c, r * width + c + 1, (r + 1) * width + c, (r + 1) * width + c + 1].into_iter().zip(block) {
+                    values[idx] = value;
+                }
+            }
+            Array2::from(values, height, width)
+        }
+    }
+}
+
+/// Returns a mask encoded as the lengths of its runs in row major order, alternating between transparent and opaque
+/// and starting with transparent. Each length is written 7 bits at a time, least significant first, with the top bit
+/// of each byte set when more bytes follow.
+///
+/// # Arguments:
+/// * `mask`: the mask, where true is opaque
+pub fn encode_runs(mask: &Array2<bool>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let (mut current, mut run) = (false, 0_u64);
+    for (_, _, &opaque) in mask.iter_row_maj() {
+        if opaque != current {
+            push_length(&mut bytes, run);
+            (current, run) = (opaque, 0);
+        }
+        run += 1;
+    }
+    push_length(&mut bytes, run);
+    bytes
+}
+
+/// Returns the mask encoded by `encode_runs`, or an error if the runs do not cover exactly `height` x `width` pixels
+///
+/// # Arguments:
+/// * `bytes`: the encoded runs
+/// * `height`: the height of the mask
+/// * `width`: the width of the mask
+pub fn decode_runs(bytes: &[u8], height: usize, width: usize) -> Result<Array2<bool>, String> {
+    let count = height * width;
+    let mut mask = Vec::with_capacity(count);
+    let (mut pos, mut opaque) = (0, false);
+    while pos < bytes.len() {
+        let mut run = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = *bytes.get(pos).ok_or("alpha mask ends in the middle of a run")?;
+            pos += 1;
+            if shift > 56 {
+                return Err("alpha mask run is too long".to_string());
+            }
+            run |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if run > (count - mask.len()) as u64 {
+            return Err(format!("alpha mask covers more than the {} pixels of the image", count));
+        }
+        mask.extend(std::iter::repeat_n(opaque, run as usize));
+        opaque = !opaque;
+    }
+    if mask.len() != count {
+        return Err(format!("alpha mask covers {} of the {} pixels of the image", mask.len(), count));
+    }
+    Ok(Array2::from(mask, height, width))
+}
+
+/// Helper function to append a run length to `bytes`, 7 bits at a time
+///
+/// # Arguments:
+/// * `bytes`: the encoded runs so far
+/// * `run`: the length of the run
+fn push_length(bytes: &mut Vec<u8>, mut run: u64) {
+    while run >= 0x80 {
+        bytes.push((run & 0x7f) as u8 | 0x80);
+        run >>= 7;
+    }
+    bytes.push(run as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use array2::Array2;
+    use crate::alpha::{compress_alpha, decode_runs, decompress_alpha, encode_runs, AlphaPlane};
+
+    #[test]
+    fn test_binary_alpha_is_lossless() {
+        let alpha = Array2::from(vec![0, 255, 255, 255, 0, 0, 255, 0], 2, 4);
+        let plane = compress_alpha(&alpha, 255);
+        assert!(matches!(plane, AlphaPlane::Mask(_)));
+        assert_eq!(decompress_alpha(&plane, 255), alpha);
+    }
+
+    #[test]
+    fn test_smooth_alpha_uses_transform() {
+        let alpha = Array2::from(vec![0, 64, 128, 192, 10, 74, 138, 202], 2, 4);
+        let plane = compress_alpha(&alpha, 255);
+        assert!(matches!(plane, AlphaPlane::Transform(_)));
+        let decoded = decompress_alpha(&plane, 255);
+        for (row, col, &value) in alpha.iter_row_maj() {
+            assert!((*decoded.get(row, col) as i32 - value as i32).abs() <= 8);
+        }
+    }
+
+    #[test]
+    fn test_runs_round_trip() {
+        let mask: Vec<bool> = (0..400).map(|n| (3..300).contains(&n)).collect();
+        let mask = Array2::from(mask, 20, 20);
+        let bytes = encode_runs(&mask);
+        assert_eq!(bytes, vec![3, 0xa9, 0x02, 100]);
+        assert_eq!(decode_runs(&bytes, 20, 20).unwrap(), mask);
+    }
+
+    #[test]
+    fn test_bad_runs() {
+        assert!(decode_runs(&[2, 1], 2, 2).is_err());
+        assert!(decode_runs(&[3, 2], 2, 2).is_err());
+        assert!(decode_runs(&[0x83], 2, 2).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+/// Returns the `width` bits of `bytes` starting at bit `pos`, most significant bit first
+///
+/// # Arguments:
+/// * `bytes`: the bytes holding the bits, which must hold all of them
+/// * `pos`: the index of the first bit
+/// * `width`: the number of bits, at most 64
+pub fn read_bits(bytes: &[u8], pos: u64, width: u64) -> u64 {
+    (pos..pos + width).fold(0, |bits, pos| (bits << 1) | (bytes[pos as usize / 8] >> (7 - pos % 8) & 1) as u64)
+}
+
+/// Writes bits to the end of a Vec, most significant bit first, starting on a fresh byte
+pub struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    /// the number of bits used in the last byte, from 0 to 7
+    used: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    /// Returns a writer that appends to `out`
+    ///
+    /// # Arguments:
+    /// * `out`: the bytes written so far
+    pub fn new(out: &'a mut Vec<u8>) -> Self {
+        BitWriter { out, used: 0 }
+    }
+
+    /// Appends the low `width` bits of `value`, most significant first
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the writer
+    /// * `value`: the bits to append
+    /// * `width`: the number of bits
+    pub fn push(&mut self, value: u64, width: u64) {
+        for bit in (0..width).rev() {
+            if self.used == 0 {
+                self.out.push(0);
+            }
+            *self.out.last_mut().unwrap() |= ((value >> bit & 1) as u8) << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /// Appends `value` as a signed Exp-Golomb code: the value is mapped to 0, -1, 1, -2, 2 and so on, and that
+    /// number plus one is written in binary after one 0 for every bit after its first
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the writer
+    /// * `value`: the value to append, which must not be `i64::MIN`
+    pub fn push_signed(&mut self, value: i64) {
+        let code = ((value << 1) ^ (value >> 63)) as u64 + 1;
+        let width = 64 - code.leading_zeros() as u64;
+        self.push(0, width - 1);
+        self.push(code, width);
+    }
+}
+
+/// Reads bits from the start of a slice, most significant bit first
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// the index of the next bit
+    pos: u64,
+}
+
+impl<'a> BitReader<'a> {
+    /// Returns a reader over `bytes`
+    ///
+    /// # Arguments:
+    /// * `bytes`: the bytes holding the bits
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, pos: 0 }
+    }
+
+    /// Returns the number of bytes that have been read from, counting a byte that was only partly read
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the reader
+    pub fn bytes_read(&self) -> usize {
+        self.pos.div_ceil(8) as usize
+    }
+
+    /// Returns the next `width` bits, or None if there are not that many left
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the reader
+    /// * `width`: the number of bits, at most 64
+    pub fn read(&mut self, width: u64) -> Option<u64> {
+        if self.pos + width > self.bytes.len() as u64 * 8 {
+            return None;
+        }
+        let bits = read_bits(self.bytes, self.pos, width);
+        self.pos += width;
+        Some(bits)
+    }
+
+    /// Returns the next signed Exp-Golomb code, as written by `BitWriter::push_signed`, or an error if the bits run
+    /// out or the code is longer than any value it could hold
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the reader
+    pub fn read_signed(&mut self) -> Result<i64, String> {
+        let mut zeros = 0;
+        loop {
+            match self.read(1) {
+                Some(0) if zeros < 63 => zeros += 1,
+                Some(0) => return Err("Exp-Golomb code is too long".to_string()),
+                Some(_) => break,
+                None => return Err("bits end in the middle of an Exp-Golomb code".to_string()),
+            }
+        }
+        let code = ((1 << zeros) | self.read(zeros).ok_or("bits end in the middle of an Exp-Golomb code")?) - 1;
+        Ok((code >> 1) as i64 ^ -((code & 1) as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bits::{read_bits, BitReader, BitWriter};
+
+    #[test]
+    fn test_bits() {
+        let mut bytes = Vec::new();
+        let mut writer = BitWriter::new(&mut bytes);
+        writer.push(0b101, 3);
+        writer.push(0x1ff, 9);
+        assert_eq!(bytes, vec![0b1011_1111, 0b1111_0000]);
+        assert_eq!(read_bits(&bytes, 2, 4), 0b1111);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read(3), Some(0b101));
+        assert_eq!(reader.bytes_read(), 1);
+        assert_eq!(reader.read(13), Some(0x1ff << 4));
+        assert_eq!(reader.read(1), None);
+    }
+
+    #[test]
+    fn test_exp_golomb() {
+        let values = [0, -1, 1, -2, 2, 255, -256, i64::MAX, i64::MIN + 1];
+        let mut bytes = Vec::new();
+        let mut writer = BitWriter::new(&mut bytes);
+        for value in values {
+            writer.push_signed(value);
+        }
+        // 0 is a single bit, and -1 and 1 take three
+        assert_eq!(bytes[0] >> 1, 0b1_010_011);
+
+        let mut reader = BitReader::new(&bytes);
+        for value in values {
+            assert_eq!(reader.read_signed(), Ok(value));
+        }
+        assert!(BitReader::new(&[0; 9]).read_signed().is_err());
+        assert!(BitReader::new(&[0b0000_0010]).read_signed().is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::pnm::PnmImage;
+
+/// The compression method of a BMP holding uncompressed pixels
+const BI_RGB: u32 = 0;
+/// The compression method of a BMP whose pixels are unpacked with a mask for each channel
+const BI_BITFIELDS: u32 = 3;
+/// The compression method used by some encoders for masks that include an alpha channel
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// Parses an uncompressed BMP file into an image with a maxval of 255. 24 and 32 bit pixels become an `RGB`
+/// image, or `RGB_ALPHA` when the header gives an alpha mask, and 1, 4 and 8 bit palette images become `RGB`,
+/// or `GRAYSCALE` when every color in the palette is gray. Rows may be stored bottom-up or top-down.
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+pub fn parse(bytes: &[u8]) -> Result<PnmImage, String> {
+    if bytes.get(..2) != Some(b"BM") {
+        return Err("not a BMP image: missing magic number BM".to_string());
+    }
+    let offset = read_u32(bytes, 10)? as usize;
+    let header_size = read_u32(bytes, 14)? as usize;
+
+    // the oldest headers store the dimensions as 16 bit values and have no compression method
+    let (width, height, bits, compression, colors_used) = match header_size {
+        12 => (read_u16(bytes, 18)? as i32, read_u16(bytes, 20)? as i32, read_u16(bytes, 24)?, BI_RGB, 0),
+        40 | 52 | 56 | 64 | 108 | 124 => (
+            read_u32(bytes, 18)? as i32,
+            read_u32(bytes, 22)? as i32,
+            read_u16(bytes, 28)?,
+            read_u32(bytes, 30)?,
+            read_u32(bytes, 46)? as usize,
+        ),
+        _ => return Err(format!("unsupported BMP header size {}", header_size)),
+    };
+    if width <= 0 || height == 0 {
+        return Err(format!("image dimensions must be positive, found {} x {}", width, height));
+    }
+    // a negative height means the rows are stored from the top down
+    let (width, height, top_down) = (width as u32, height.unsigned_abs(), height < 0);
+
+    let row_len = (width as usize * bits as usize).div_ceil(32) * 4;
+    let expected = row_len.checked_mul(height as usize).ok_or("image dimensions are too large")?;
+    let raster = bytes.get(offset..).unwrap_or(&[]);
+    if raster.len() < expected {
+        return Err(format!("raster is truncated: expected {} bytes, found {}", expected, raster.len()));
+    }
+    let row = |y: u32| {
+        let stored = if top_down { y } else { height - 1 - y } as usize;
+        &raster[stored * row_len..(stored + 1) * row_len]
+    };
+
+    match (bits, compression) {
+        (1 | 4 | 8, BI_RGB) => {
+            let palette = read_palette(bytes, header_size, bits, colors_used)?;
+            let gray = palette.iter().all(|&[r, g, b]| r == g && g == b);
+            let mut samples = Vec::new();
+            for y in 0..height {
+                let row = row(y);
+                for x in 0..width as usize {
+                    // pixels are packed into each byte starting from the most significant bits
+                    let bit = x * bits as usize;
+                    let idx = (row[bit / 8] >> (8 - bits as usize - bit % 8)) & ((1 << bits) - 1) as u8;
+                    let color = palette.get(idx as usize)
+                        .ok_or(format!("the pixel at row {}, column {} uses color {}, but the palette has {}", y, x, idx, palette.len()))?;
+                    if gray {
+                        samples.push(color[0] as u16);
+                    } else {
+                        samples.extend(color.iter().map(|&c| c as u16));
+                    }
+                }
+            }
+            let (depth, tupltype) = if gray { (1, "GRAYSCALE") } else { (3, "RGB") };
+            Ok(PnmImage { width, height, depth, maxval: 255, tupltype: tupltype.to_string(), samples })
+        }
+        (24, BI_RGB) => {
+            let mut samples = Vec::with_capacity(width as usize * height as usize * 3);
+            for y in 0..height {
+                for bgr in row(y).chunks_exact(3).take(width as usize) {
+                    samples.extend([bgr[2] as u16, bgr[1] as u16, bgr[0] as u16]);
+                }
+            }
+            Ok(PnmImage { width, height, depth: 3, maxval: 255, tupltype: "RGB".to_string(), samples })
+        }
+        (32, BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS) => {
+            let masks = read_masks(bytes, header_size, compression)?;
+            let has_alpha = masks[3] != 0;
+            let mut samples = Vec::new();
+            for y in 0..height {
+                for pixel in row(y).chunks_exact(4).take(width as usize) {
+                    let pixel = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    let channels = if has_alpha { &masks[..] } else { &masks[..3] };
+                    samples.extend(channels.iter().map(|&mask| masked_value(pixel, mask)));
+                }
+            }
+            let (depth, tupltype) = if has_alpha { (4, "RGB_ALPHA") } else { (3, "RGB") };
+            Ok(PnmImage { width, height, depth, maxval: 255, tupltype: tupltype.to_string(), samples })
+        }
+        _ => Err(format!("unsupported BMP pixel format: {} bits per pixel with compression method {}", bits, compression)),
+    }
+}
+
+/// Returns the contents of a BMP file holding `img`, with samples scaled to 8 bits. Grayscale images are written
+/// with an 8 bit gray palette, color images with 24 bit pixels, and images with alpha with 32 bit pixels and a
+/// header that gives the alpha mask.
+///
+/// # Arguments:
+/// * `img`: the image to write
+pub fn encode(img: &PnmImage) -> Result<Vec<u8>, String> {
+    if img.width > i32::MAX as u32 || img.height > i32::MAX as u32 {
+        return Err(format!("a {} x {} image is too large for a BMP", img.width, img.height));
+    }
+    let (bits, header_size, palette_size) = match (img.depth, img.has_alpha()) {
+        (1, false) => (8, 40, 256 * 4),
+        (3, false) => (24, 40, 0),
+        (2, true) | (4, true) => (32, 108, 0),
+        _ => return Err(format!("a BMP cannot hold an image of depth {} with tuple type {}", img.depth, img.tupltype)),
+    };
+    let row_len = (img.width as usize * bits).div_ceil(32) * 4;
+    let offset = 14 + header_size + palette_size;
+    let size = offset + row_len * img.height as usize;
+
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(b"BM");
+    out.extend_from_slice(&(size as u32).to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&(offset as u32).to_le_bytes());
+
+    let compression = if bits == 32 { BI_BITFIELDS } else { BI_RGB };
+    out.extend_from_slice(&(header_size as u32).to_le_bytes());
+    out.extend_from_slice(&img.width.to_le_bytes());
+    out.extend_from_slice(&img.height.to_le_bytes());
+    out.extend_from_slice(&1_u16.to_le_bytes());
+    out.extend_from_slice(&(bits as u16).to_le_bytes());
+    out.extend_from_slice(&compression.to_le_bytes());
+    out.extend_from_slice(&((row_len * img.height as usize) as u32).to_le_bytes());
+    // 2835 pixels per meter is 72 dots per inch
+    out.extend_from_slice(&2835_u32.to_le_bytes());
+    out.extend_from_slice(&2835_u32.to_le_bytes());
+    out.extend_from_slice(&[0; 8]);
+    if header_size == 108 {
+        for mask in [0x00ff_0000_u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+            out.extend_from_slice(&mask.to_le_bytes());
+        }
+        // the sRGB color space leaves the endpoints and gamma unused
+        out.extend_from_slice(b"BGRs");
+        out.extend_from_slice(&[0; 48]);
+    }
+    for gray in 0..palette_size as u32 / 4 {
+        out.extend_from_slice(&[gray as u8, gray as u8, gray as u8, 0]);
+    }
+
+    let depth = img.depth as usize;
+    let scale = |sample: u16| ((sample as u32 * 255 + img.maxval as u32 / 2) / img.maxval as u32) as u8;
+    // rows are stored from the bottom up
+    for tuples in img.samples.chunks_exact(img.width as usize * depth).rev() {
+        let start = out.len();
+        for tuple in tuples.chunks_exact(depth) {
+            match depth {
+                1 => out.push(scale(tuple[0])),
+                2 => out.extend_from_slice(&[scale(tuple[0]), scale(tuple[0]), scale(tuple[0]), scale(tuple[1])]),
+                3 => out.extend_from_slice(&[scale(tuple[2]), scale(tuple[1]), scale(tuple[0])]),
+                _ => out.extend_from_slice(&[scale(tuple[2]), scale(tuple[1]), scale(tuple[0]), scale(tuple[3])]),
+            }
+        }
+        out.resize(start + row_len, 0);
+    }
+    Ok(out)
+}
+
+/// Helper function to read the palette of a 1, 4 or 8 bit image, returning every color as `[red, green, blue]`
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+/// * `header_size`: the size of the header, which the palette follows
+/// * `bits`: the number of bits in each pixel
+/// * `colors_used`: the number of colors given by the header, where 0 means every color the pixels can index
+fn read_palette(bytes: &[u8], header_size: usize, bits: u16, colors_used: usize) -> Result<Vec<[u8; 3]>, String> {
+    let count = if colors_used == 0 { 1 << bits } else { colors_used.min(1 << bits) };
+    // the oldest headers store each color in 3 bytes rather than 4
+    let entry_size = if header_size == 12 { 3 } else { 4 };
+    let start = 14 + header_size;
+    let entries = bytes.get(start..start + count * entry_size)
+        .ok_or(format!("palette is truncated: expected {} colors", count))?;
+    Ok(entries.chunks_exact(entry_size).map(|bgr| [bgr[2], bgr[1], bgr[0]]).collect())
+}
+
+/// Helper function to read the red, green, blue and alpha masks of a 32 bit image. Images without masks use
+/// 8 bits for each color and no alpha.
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+/// * `header_size`: the size of the header
+/// * `compression`: the compression method of the image
+fn read_masks(bytes: &[u8], header_size: usize, compression: u32) -> Result<[u32; 4], String> {
+    if compression == BI_RGB {
+        return Ok([0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0]);
+    }
+    // the masks sit at the end of the larger headers, and directly after the smallest one
+    let count = if header_size > 52 || compression == BI_ALPHABITFIELDS { 4 } else { 3 };
+    let mut masks = [0; 4];
+    for (idx, mask) in masks.iter_mut().take(count).enumerate() {
+        *mask = read_u32(bytes, 54 + idx * 4)?;
+    }
+    if masks[..3].contains(&0) {
+        return Err("every color in a BMP needs a nonzero mask".to_string());
+    }
+    Ok(masks)
+}
+
+/// Helper function that returns the channel selected by `mask` out of `pixel`, scaled to 8 bits
+///
+/// # Arguments:
+/// * `pixel`: a 32 bit pixel
+/// * `mask`: a nonzero mask of contiguous bits
+fn masked_value(pixel: u32, mask: u32) -> u16 {
+    let max = mask >> mask.trailing_zeros();
+    let value = (pixel & mask) >> mask.trailing_zeros();
+    ((value as u64 * 255 + max as u64 / 2) / max as u64) as u16
+}
+
+/// Helper function to read a little-endian 16 bit value at `pos`
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+/// * `pos`: the position of the value
+fn read_u16(bytes: &[u8], pos: usize) -> Result<u16, String> {
+    bytes.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(format!("BMP header is truncated at byte {}", pos))
+}
+
+/// Helper function to read a little-endian 32 bit value at `pos`
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+/// * `pos`: the position of the value
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32, String> {
+    bytes.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(format!("BMP header is truncated at byte {}", pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bmp::{encode, parse};
+    use crate::pnm::PnmImage;
+
+    fn image(depth: u32, tupltype: &str, samples: Vec<u16>) -> PnmImage {
+        PnmImage { width: 3, height: 2, depth, maxval: 255, tupltype: tupltype.to_string(), samples }
+    }
+
+    /// Builds a BMP with a 40 byte header around a raster that is already in stored order
+    fn bmp(height: i32, bits: u16, palette: &[[u8; 4]], raster: &[u8]) -> Vec<u8> {
+        let offset = 54 + palette.len() as u32 * 4;
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&(offset + raster.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&40_u32.to_le_bytes());
+        out.extend_from_slice(&3_i32.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&1_u16.to_le_bytes());
+        out.extend_from_slice(&bits.to_le_bytes());
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend(palette.iter().flatten());
+        out.extend_from_slice(raster);
+        out
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let rgb = image(3, "RGB", (0..18).map(|n| n * 14).collect());
+        assert_eq!(parse(&encode(&rgb).unwrap()).unwrap(), rgb);
+
+        let rgba = image(4, "RGB_ALPHA", (0..24).map(|n| n * 11).collect());
+        assert_eq!(parse(&encode(&rgba).unwrap()).unwrap(), rgba);
+
+        let gray = image(1, "GRAYSCALE", vec![0, 50, 100, 150, 200, 250]);
+        assert_eq!(parse(&encode(&gray).unwrap()).unwrap(), gray);
+    }
+
+    #[test]
+    fn test_row_order() {
+        // two rows of 3 pixels, padded to 12 bytes each, with the first stored row red and the second blue
+        let mut raster = [0_u8; 24];
+        for x in 0..3 {
+            raster[x * 3 + 2] = 255;
+            raster[12 + x * 3] = 255;
+        }
+        let bottom_up = parse(&bmp(2, 24, &[], &raster)).unwrap();
+        assert_eq!(&bottom_up.samples[..3], &[0, 0, 255]);
+        let top_down = parse(&bmp(-2, 24, &[], &raster)).unwrap();
+        assert_eq!(&top_down.samples[..3], &[255, 0, 0]);
+    }
+
+    #[test]
+    fn test_palettes() {
+        // 4 bit pixels 0, 1, 1 and 1, 0, 0 in rows padded to 4 bytes
+        let palette = [[255, 0, 0, 0], [0, 255, 0, 0]];
+        let img = parse(&bmp(-2, 4, &palette, &[0x01, 0x10, 0, 0, 0x10, 0x00, 0, 0])).unwrap();
+        assert_eq!((img.depth, img.tupltype.as_str()), (3, "RGB"));
+        assert_eq!(&img.samples[..9], &[0, 0, 255, 0, 255, 0, 0, 255, 0]);
+
+        // a 1 bit black and white palette reads as grayscale
+        let palette = [[0, 0, 0, 0], [255, 255, 255, 0]];
+        let img = parse(&bmp(-2, 1, &palette, &[0b1010_0000, 0, 0, 0, 0b0100_0000, 0, 0, 0])).unwrap();
+        assert_eq!((img.depth, img.tupltype.as_str()), (1, "GRAYSCALE"));
+        assert_eq!(img.samples, vec![255, 0, 255, 0, 255, 0]);
+    }
+
+    #[test]
+    fn test_bad_files() {
+        assert!(parse(b"P6\n3 2\n255\n").is_err());
+        assert!(parse(&bmp(2, 24, &[], &[0; 23])).is_err());
+        assert!(parse(&bmp(2, 16, &[], &[0; 16])).is_err());
+        assert!(parse(&bmp(0, 24, &[], &[])).is_err());
+        // a pixel that indexes past the end of a two color palette
+        assert!(parse(&bmp(-2, 4, &[[0; 4], [0; 4]], &[0x02, 0, 0, 0, 0, 0, 0, 0])).is_err());
+        assert!(encode(&image(2, "GRAYSCALE", vec![0; 12])).is_err());
+    }
+}
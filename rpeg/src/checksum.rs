@@ -0,0 +1,76 @@
+/// The lookup table for CRC-32, holding the remainder of every byte divided by the reversed polynomial 0xedb88320
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Helper function to build `CRC_TABLE` at compile time
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 of `bytes`, as used by PNG chunks and zip files
+///
+/// # Arguments:
+/// * `bytes`: the bytes to check
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// Returns the CRC-32 of some bytes followed by `bytes`, given the CRC-32 `crc` of the bytes before them,
+/// for checking data that arrives a piece at a time. Starting from 0 gives the same result as `crc32`.
+///
+/// # Arguments:
+/// * `crc`: the CRC-32 of the bytes so far
+/// * `bytes`: the next bytes to check
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// Returns the Adler-32 checksum of `bytes`, as used by zlib streams
+///
+/// # Arguments:
+/// * `bytes`: the bytes to check
+pub fn adler32(bytes: &[u8]) -> u32 {
+    // 5552 is the most bytes that can be summed before the running totals might overflow
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::{adler32, crc32, crc32_update};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // long enough for the running totals to be reduced along the way
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+}
@@ -1,62 +1,202 @@
 use array2::Array2;
-use csc411_image::{self, RgbImage, Read, Rgb, Write};
-use crate::{pixel_block::PixelBlock, pixel_conversion, quantize};
-use csc411_rpegio;
-
-/// Function to handle compressing a `.ppm` file received from 
-/// the user
+use csc411_image::{self, RgbImage, Rgb};
+use crate::{alpha, container::{self, Arrangement, ColourModel, CompressedImage, Header}, fixed_point, pixel_block::PixelBlock, pixel_conversion, pnm};
+use crate::{progressive, quantize, sequence};
+use crate::sequence::SequenceWriter;
+use crate::image_file::{self, FileFormat};
+use crate::limits::DecodeLimits;
+use crate::quantize::WordLayout;
+use crate::tiles::Tiles;
+use std::io::{BufRead, BufReader, Cursor, Seek, SeekFrom, Write};
+use std::time::UNIX_EPOCH;
+use std::ops::Range;
+
+/// Function to handle compressing a Netpbm file (`.pbm`, `.pgm`, `.ppm` or `.pam`), `.bmp` or `.png` file received from 
+/// the user, telling them apart by their magic bytes. Any alpha channel is compressed as a separate plane
 /// 
 /// # Arguments:
 /// * `filename`: the file name received from the user. Input is None iff the user does not enter
 /// a filename as a command line argument
-pub fn compress(filename: Option<&str>){
-    //extract the 
-    let (arr, height, width, denom) = read_uncompressed_image(filename);
+/// * `threads`: the number of threads to encode on
+/// * `arrangement`: how to lay out the words in the file
+///
+/// Returns an error if the file cannot be read, is not a valid image, or the compressed image cannot be written
+pub fn compress(filename: Option<&str>, threads: usize, arrangement: Arrangement) -> Result<(), String> {
+    // write the image to standard output, with each word in Big-Endian order
+    let pnm_img = image_file::read(filename)?;
+    let mut rpeg_img = compress_image_with_threads(&pnm_img, threads);
+    rpeg_img.arrangement = arrangement;
+    rpeg_img.write()
+}
 
-    //cast denominator as f64 for use later
-    let denominator = denom as f64;
+/// Function to handle packing the Netpbm files (`.ppm`, `.pgm`, `.pnm` or `.pam`) in a directory into an rpeg
+/// sequence written to standard output, compressing each frame as it is read. The frames are in order of file name,
+/// and each is stamped with the time its file was last modified, or with its position times `interval`.
+///
+/// # Arguments:
+/// * `directory`: the name of the directory holding the frames
+/// * `threads`: the number of threads to encode each frame on
+/// * `interval`: the number of milliseconds between frames, or None to use the modification times of the files
+///
+/// Returns the number of frames packed, or an error if the directory cannot be read, holds no frames, holds
+/// frames of different sizes, or the sequence cannot be written
+pub fn pack(directory: &str, threads: usize, interval: Option<u64>) -> Result<usize, String> {
+    let mut names = std::fs::read_dir(directory).map_err(|e| format!("{}: {}", directory, e))?
+        .map(|entry| entry.map(|entry| entry.path()).map_err(|e| format!("{}: {}", directory, e)))
+        .collect::<Result<Vec<_>, String>>()?;
+    names.retain(|path| {
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase());
+        matches!(extension.as_deref(), Some("ppm" | "pgm" | "pnm" | "pam")) && path.is_file()
+    });
+    names.sort();
+
+    let mut writer: Option<SequenceWriter> = None;
+    for (k, path) in names.iter().enumerate() {
+        let name = path.to_string_lossy();
+        let rpeg_img = compress_image_with_threads(&image_file::read(Some(&name))?, threads);
+        let timestamp = match interval {
+            Some(interval) => (k as u64).checked_mul(interval).ok_or("--interval is too large for this many frames")?,
+            None => std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map(|time| time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64))
+                .map_err(|e| format!("{}: {}", name, e))?,
+        };
+        writer.get_or_insert_with(|| SequenceWriter::new(rpeg_img.width, rpeg_img.height))
+            .push(timestamp, &rpeg_img)
+            .map_err(|e| format!("{}: {}", name, e))?;
+    }
+    let writer = writer.ok_or_else(|| format!("{}: no .ppm, .pgm, .pnm or .pam files to pack", directory))?;
+    std::io::stdout().lock().write_all(&writer.to_bytes()).map_err(|e| e.to_string())?;
+    Ok(names.len())
+}
 
-    let mut compressed_img: Vec<u32> = Vec::new();
-    for (_, _, group) in arr.iter_row_maj() {
-        // unpack each block of pixels into the underlying Rgb
-        let pixel_vec = group.clone().unpack();
+/// Function to handle decompressing frame `n` of an rpeg sequence received from the user, reading only that frame
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+/// * `n`: the index of the frame, counting from 0
+/// * `format`: the file format to write the frame in
+/// * `threads`: the number of threads to decode on
+/// * `limits`: the largest frame to accept
+///
+/// Returns the timestamp of the frame, or an error if the file cannot be read, has no valid frame `n`, or the frame
+/// cannot be written
+pub fn extract(filename: Option<&str>, n: usize, format: FileFormat, threads: usize, limits: DecodeLimits) -> Result<u64, String> {
+    let (timestamp, rpeg_img) = match filename {
+        Some(name) => {
+            let file = std::fs::File::open(name).map_err(|e| format!("{}: {}", name, e))?;
+            sequence::read_frame(&mut BufReader::new(file), n, limits)?
+        }
+        None => sequence::read_frame(&mut Cursor::new(image_file::read_bytes(None)?), n, limits)?,
+    };
+    image_file::write(&decompress_image_with_threads(&rpeg_img, threads), format, None)?;
+    Ok(timestamp)
+}
 
-        // set up accumulator values to store the pb, pr, and luma values
-        let (mut pb_avg, mut pr_avg) = (0.0,0.0);
-        let mut luma = Vec::new();
+/// Returns the compressed form of an image held in memory
+///
+/// # Arguments:
+/// * `pnm_img`: the image, read in any supported format
+pub fn compress_image(pnm_img: &pnm::PnmImage) -> CompressedImage {
+    compress_image_with_threads(pnm_img, 1)
+}
 
-        for (_, _, pixel) in pixel_vec.iter() {
-            // convert each pixel in the group into the floating point representation and then into component video
-            let float_point_pixel = pixel_conversion::rgb_to_float(pixel, denominator);
-            let (y, pb, pr) = pixel_conversion::get_compression_tuple(float_point_pixel);
+/// Returns the compressed form of an image held in memory, encoding bands of rows on `threads` threads.
+/// Every word depends only on its own block, so the result is the same for any number of threads.
+///
+/// # Arguments:
+/// * `pnm_img`: the image, read in any supported format
+/// * `threads`: the number of threads to encode on
+pub fn compress_image_with_threads(pnm_img: &pnm::PnmImage, threads: usize) -> CompressedImage {
+    //extract the blocks of pixels, the dimensions, the denominator and the colour model out of the image
+    let (arr, height, width, denom, model) = read_uncompressed_image(pnm_img);
 
-            // increment the pb and pr, then push the luma into the vector
-            pb_avg += pb;
-            pr_avg += pr;
-            luma.push(y);
-        }
+    //cast denominator as f64 for use later, and pick the widths of the fields in each word
+    let denominator = denom as f64;
+    let layout = container::layout_for(model, denom);
+
+    // each thread encodes a band of rows, and the words of the bands are joined back together in order
+    let rows_per_band = arr.height().div_ceil(threads.max(1)).max(1);
+    let compressed_img: Vec<u64> = std::thread::scope(|scope| {
+        let handles: Vec<_> = arr.row_bands(rows_per_band)
+            .map(|band| scope.spawn(move || {
+                // unpack each block of pixels into the underlying Rgb, and turn it into a word
+                band.iter()
+                    .map(|group| {
+                        let pixel_vec = group.clone().unpack();
+                        encode_block([&pixel_vec[0].2, &pixel_vec[1].2, &pixel_vec[2].2, &pixel_vec[3].2], denominator, layout)
+                    })
+                    .collect::<Vec<u64>>()
+            }))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+
+    // compress the alpha channel of the trimmed image on its own
+    let alpha_plane = pnm_img.alpha().map(|values| {
+        let trimmed: Vec<u16> = values.chunks_exact(pnm_img.width as usize)
+            .take(height)
+            .flat_map(|row| row[..width].iter().copied())
+            .collect();
+        alpha::compress_alpha(&Array2::from(trimmed, height, width), denom)
+    });
+
+    let mut rpeg_img = CompressedImage::from_words(compressed_img, width as u32, height as u32, model, denom);
+    rpeg_img.alpha = alpha_plane;
+    rpeg_img
+}
 
-        //extract the luma out and compute the coefficients
-        let (a, b, c, d) = quantize::get_coefficients(luma[0], luma[1], luma[2], luma[3]);
-        //average pb and pr
-        pb_avg /= 4.0;
-        pr_avg /= 4.0;
+/// Returns the word describing a 2 x 2 block of pixels. With the `fixed-point` feature, the word is computed
+/// with integer arithmetic only by `fixed_point::encode_block`.
+///
+/// # Arguments:
+/// * `pixels`: the top left, top right, bottom left and bottom right pixels of the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout to pack the word with
+pub fn encode_block(pixels: [&Rgb; 4], denominator: f64, layout: WordLayout) -> u64 {
+    if cfg!(feature = "fixed-point") {
+        fixed_point::encode_block(pixels, denominator as u16, layout)
+    } else {
+        encode_block_float(pixels, denominator, layout)
+    }
+}
 
-        //turn each floating point value into a scaled integer or index
-        let a_int = quantize::singular_coefficient_to_scaled_int(a);
-        let (b_int, c_int, d_int) = quantize::coefficients_to_scaled_ints(b, c, d);
-        let (pb_idx, pr_idx) = quantize::chroma_to_indices(pb_avg, pr_avg);
+/// Returns the word describing a 2 x 2 block of pixels, computed with floating point arithmetic
+///
+/// # Arguments:
+/// * `pixels`: the top left, top right, bottom left and bottom right pixels of the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout to pack the word with
+pub fn encode_block_float(pixels: [&Rgb; 4], denominator: f64, layout: WordLayout) -> u64 {
+    // set up accumulator values to store the pb, pr, and luma values
+    let (mut pb_avg, mut pr_avg) = (0.0,0.0);
+    let mut luma = Vec::new();
+
+    for pixel in pixels {
+        // convert each pixel in the group into the floating point representation and then into component video
+        let float_point_pixel = pixel_conversion::rgb_to_float(pixel, denominator);
+        let (y, pb, pr) = pixel_conversion::get_compression_tuple(float_point_pixel);
+
+        // increment the pb and pr, then push the luma into the vector
+        pb_avg += pb;
+        pr_avg += pr;
+        luma.push(y);
+    }
 
-        //push each of these values into a word
-        let word = quantize::construct_word(a_int, b_int, c_int, d_int, pb_idx, pr_idx);
+    //extract the luma out and compute the coefficients
+    let (a, b, c, d) = quantize::get_coefficients(luma[0], luma[1], luma[2], luma[3]);
+    //average pb and pr
+    pb_avg /= 4.0;
+    pr_avg /= 4.0;
 
-        //push that word into the compressed image
-        compressed_img.push(word);
-    }
+    //turn each floating point value into a scaled integer or index
+    let a_int = layout.coefficient_to_scaled_int(a);
+    let (b_int, c_int, d_int) = quantize::coefficients_to_scaled_ints(b, c, d);
+    let (pb_idx, pr_idx) = layout.chroma_to_indices(pb_avg, pr_avg);
 
-    // turn each 32 bit word into 4 bytes in Big-Endian order, then write the image to standard output
-    let compressed_arr: Vec<[u8; 4]> = compressed_img.into_iter().map(|x| x.to_be_bytes()).collect();
-    write_compressed_image(&compressed_arr, width as u32, height as u32);
+    //push each of these values into a word, grayscale layouts leave the chroma out
+    layout.pack(a_int, b_int, c_int, d_int, pb_idx, pr_idx)
 }
 
 
@@ -66,65 +206,247 @@ pub fn compress(filename: Option<&str>){
 /// # Arguments:
 /// * `filename`: the file name received from the user. Input is None iff the user does not enter
 /// a filename as a command line argument
-pub fn decompress(filename: Option<&str>){
-    // extract the image data and dimensions from the image
-    let (rpeg_img, width, height) = read_compressed_image(filename);
-
-    let mut img = Vec::new();
-    for (row, col, word) in rpeg_img.into_iter() {
-        // extract all values from each of the words
-        let (a, b, c, d, pb, pr) = quantize::parse_word(word);
-
-        // convert each of the integer values into a floating point representation
-        let a_float = quantize::singular_scaled_int_to_coefficient(a);
-        let (b_float, c_float, d_float) = quantize::scaled_ints_to_coefficients(b, c, d);
-        let (pb_chroma, pr_chroma) = quantize::indices_to_chroma(pb, pr);
-
-        // convert the coefficients back into luma
-        let (y1, y2, y3, y4) = quantize::get_luma(a_float, b_float, c_float, d_float);
-        let (top_left, top_right, bottom_left, bottom_right) = (
-            /* 
-                Collect each of the luma with the pb and pr chroma values and decompress each of them with a denominator of
-                255.0, and then convert each of the floating point Rgb values back into Rgb pixels.
-                These Rgb pixels are then stored into the associated parentheses.
-            */
-            pixel_conversion::float_to_rgb(pixel_conversion::get_decompression_tuple((y1, pb_chroma, pr_chroma)), 255.0),
-            pixel_conversion::float_to_rgb(pixel_conversion::get_decompression_tuple((y2, pb_chroma, pr_chroma)), 255.0),
-            pixel_conversion::float_to_rgb(pixel_conversion::get_decompression_tuple((y3, pb_chroma, pr_chroma)), 255.0),
-            pixel_conversion::float_to_rgb(pixel_conversion::get_decompression_tuple((y4, pb_chroma, pr_chroma)), 255.0),
-        );
-        // each of the pixels are packed into a PixelBlock, then unpacked out and the vector is appended to an accumulator
-        let group = PixelBlock::pack(row, col, top_left, top_right, bottom_left, bottom_right);
-        img.append(&mut group.unpack());
-    }
-
-    // each of the coordinates of the pixels are turned into a row major index, which are then sorted
-    let mut indexed_img: Vec<(usize, Rgb)> = img.into_iter().map(|(r, c, pixel)| ((r * width as usize + c), pixel)).collect();
-    indexed_img.sort_by_key(|(idx, _)| *idx);
-
-    // the indicies are stripped from the image and a new RgbImage is created with the pixels
-    let stripped_img: Vec<Rgb> = indexed_img.into_iter().map(|(_, pixel)| pixel).collect();
+/// * `format`: the file format to write the decompressed image in
+/// * `threads`: the number of threads to decode on
+/// * `limits`: the largest image to accept
+/// * `conceal`: whether to conceal the damaged segments of a file with restart markers instead of rejecting it
+///
+/// Returns the rows of pixels that were concealed, or an error if the file cannot be read, is not a valid
+/// rpeg file, exceeds `limits`, or the image cannot be written
+pub fn decompress(filename: Option<&str>, format: FileFormat, threads: usize, limits: DecodeLimits, conceal: bool)
+    -> Result<Vec<Range<u32>>, String> {
+    // decompress the image and write it to standard out
+    let (rpeg_img, concealed) = read_compressed_image(filename, limits, conceal)?;
+    image_file::write(&decompress_image_with_threads(&rpeg_img, threads), format, None)?;
+    Ok(concealed)
+}
+
+/// Function to handle decompressing as much of a progressive `.rpeg` file received from the user as has arrived
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+/// * `format`: the file format to write the decompressed image in
+/// * `threads`: the number of threads to decode on
+/// * `limits`: the largest image to accept
+///
+/// Returns the number of passes that had arrived and the number in the whole image, or an error if the file cannot
+/// be read, is not a progressive rpeg file with a complete header, or the image cannot be written
+pub fn decompress_prefix(filename: Option<&str>, format: FileFormat, threads: usize, limits: DecodeLimits)
+    -> Result<(usize, usize), String> {
+    let (rpeg_img, passes) = CompressedImage::from_prefix(&image_file::read_bytes(filename)?, limits)?;
+    image_file::write(&decompress_image_with_threads(&rpeg_img, threads), format, None)?;
+    Ok((passes, progressive::pass_count(rpeg_img.layout())))
+}
+
+/// Function to handle decompressing a region of a tiled `.rpeg` file received from the user
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+/// * `format`: the file format to write the region in
+/// * `region`: the left edge, top edge, width and height of the region, in pixels
+/// * `limits`: the largest image to accept
+///
+/// Returns an error if the file cannot be read, the region cannot be decoded from it, or the region cannot be written
+pub fn decompress_region(filename: Option<&str>, format: FileFormat, region: (u32, u32, u32, u32), limits: DecodeLimits)
+    -> Result<(), String> {
+    let (x, y, w, h) = region;
+    let img = match filename {
+        Some(name) => {
+            let file = std::fs::File::open(name).map_err(|e| format!("{}: {}", name, e))?;
+            decode_region(&mut BufReader::new(file), x, y, w, h, limits)?
+        }
+        None => decode_region(&mut Cursor::new(image_file::read_bytes(None)?), x, y, w, h, limits)?,
+    };
+    image_file::write(&img, format, None)
+}
+
+/// Returns the `w` x `h` region of a tiled rpeg file whose top left pixel is at (`x`, `y`), reading and decoding
+/// only the tiles the region covers. The pixels are the same as those of the whole image decoded at once. The
+/// CRC-32 of the file covers all of it, so it is not checked.
+///
+/// # Arguments:
+/// * `input`: the reader holding the rpeg file, positioned at its start
+/// * `x`, `y`: the position of the top left pixel of the region
+/// * `w`, `h`: the dimensions of the region in pixels
+/// * `limits`: the largest image to accept
+///
+/// Returns an error if the file is not a tiled rpeg file, has an alpha plane, exceeds `limits`, is cut short,
+/// or does not hold the whole region
+pub fn decode_region<R: BufRead + Seek>(input: &mut R, x: u32, y: u32, w: u32, h: u32, limits: DecodeLimits)
+    -> Result<pnm::PnmImage, String> {
+    let header = Header::read(input)?;
+    limits.check_dimensions(header.width, header.height)?;
+    let (width, height) = (header.width, header.height);
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(format!("compressed image dimensions must be even, found {} x {}", width, height));
+    }
+    if x as u64 + w as u64 > width as u64 || y as u64 + h as u64 > height as u64 {
+        return Err(format!("the {} x {} region at ({}, {}) lies outside the {} x {} image", w, h, x, y, width, height));
+    }
+    if w == 0 || h == 0 {
+        return Err(format!("the region must be at least 1 x 1 pixels, found {} x {}", w, h));
+    }
+    if header.alpha.is_some() {
+        return Err("images with an alpha plane cannot be decoded by region".to_string());
+    }
+    let (layout, denominator) = (header.layout(), header.denominator as f64);
+    let word_bytes = layout.bytes();
+    let tiles = match &header.tiles {
+        Some(index) => Tiles::from_index(index, width, height, word_bytes)?,
+        None => return Err("only tiled images can be decoded by region".to_string()),
+    };
+    let start = input.stream_position().map_err(|e| e.to_string())?;
+
+    // only the blocks the region overlaps are decoded, and only their pixels inside the region are kept
+    let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+    let mut pixels = vec![Rgb { red: 0, green: 0, blue: 0 }; w * h];
+    let (block_rows, block_cols) = (y / 2..(y + h).div_ceil(2), x / 2..(x + w).div_ceil(2));
+    let offsets = tiles.offsets(word_bytes);
+    for k in tiles.covering(block_rows.clone(), block_cols.clone()) {
+        let (rows, cols) = tiles.tile(k);
+        let mut bytes = vec![0; rows.len() * cols.len() * word_bytes];
+        input.seek(SeekFrom::Start(start + offsets[k] as u64)).and_then(|_| input.read_exact(&mut bytes))
+            .map_err(|_| format!("compressed image is truncated in tile {}", k))?;
+        for (idx, word) in container::parse_words(&bytes, word_bytes).into_iter().enumerate() {
+            let (row, col) = (rows.start + idx / cols.len(), cols.start + idx % cols.len());
+            if !block_rows.contains(&row) || !block_cols.contains(&col) {
+                continue;
+            }
+            for (corner, pixel) in decode_block(word, denominator, layout).into_iter().enumerate() {
+                let (pixel_row, pixel_col) = (row * 2 + corner / 2, col * 2 + corner % 2);
+                if (y..y + h).contains(&pixel_row) && (x..x + w).contains(&pixel_col) {
+                    pixels[(pixel_row - y) * w + pixel_col - x] = pixel;
+                }
+            }
+        }
+    }
+
+    let img = RgbImage { pixels, width: w as u32, height: h as u32, denominator: header.denominator };
+    Ok(to_uncompressed_image(img, header.model, None))
+}
+
+/// Returns the image held in a compressed image. Grayscale images become a `GRAYSCALE` image holding the red
+/// value of each pixel, since all three colors are equal, and images with an alpha plane get an alpha channel.
+///
+/// # Arguments:
+/// * `rpeg_img`: the compressed image
+pub fn decompress_image(rpeg_img: &CompressedImage) -> pnm::PnmImage {
+    decompress_image_with_threads(rpeg_img, 1)
+}
+
+/// Returns the image held in a compressed image, decoding bands of rows on `threads` threads.
+/// The result is the same for any number of threads.
+///
+/// # Arguments:
+/// * `rpeg_img`: the compressed image
+/// * `threads`: the number of threads to decode on
+pub fn decompress_image_with_threads(rpeg_img: &CompressedImage, threads: usize) -> pnm::PnmImage {
+    // extract the dimensions and denominator from the image
+    let (width, height, denom) = (rpeg_img.width, rpeg_img.height, rpeg_img.denominator);
+    let layout = rpeg_img.layout();
+    let denominator = denom as f64;
+
+    // every pixel is written straight into its position in a buffer holding the whole image, with each thread
+    // owning the part of the buffer covered by its band of words
+    let width_px = width as usize;
+    let mut pixels = vec![Rgb { red: 0, green: 0, blue: 0 }; width_px * height as usize];
+    let rows_per_band = rpeg_img.words.height().div_ceil(threads.max(1)).max(1);
+    std::thread::scope(|scope| {
+        let bands = rpeg_img.words.row_bands(rows_per_band).zip(pixels.chunks_mut((rows_per_band * 2 * width_px).max(1)));
+        for (words, band_pixels) in bands {
+            scope.spawn(move || decode_band(words, band_pixels, width_px, denominator, layout));
+        }
+    });
+
     let new_img = RgbImage {
-        pixels: stripped_img,
+        pixels,
         width,
         height,
-        denominator: 255
+        denominator: denom
     };
 
-    // write the image to standard out
-    write_uncompressed_image(new_img);
+    // decompress the alpha plane, if there is one
+    let alpha_values = rpeg_img.alpha.as_ref().map(|plane| alpha::decompress_alpha(plane, denom));
+    to_uncompressed_image(new_img, rpeg_img.model, alpha_values)
+}
+
+/// Helper function to decode a band of words into the pixels they cover
+///
+/// # Arguments:
+/// * `words`: the words of whole rows of blocks, in row major order
+/// * `pixels`: the pixels of the rows the words cover, in row major order
+/// * `width_px`: the width of the image in pixels
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout the words were packed with
+fn decode_band(words: &[u64], pixels: &mut [Rgb], width_px: usize, denominator: f64, layout: WordLayout) {
+    for (idx, &word) in words.iter().enumerate() {
+        // scale the coordinates of the word up to the top left pixel of its block, and decode its pixels
+        let (word_row, word_col) = (idx / (width_px / 2), idx % (width_px / 2));
+        let top = word_row * 2 * width_px + word_col * 2;
+        let bottom = top + width_px;
+        let [top_left, top_right, bottom_left, bottom_right] = decode_block(word, denominator, layout);
+        pixels[top] = top_left;
+        pixels[top + 1] = top_right;
+        pixels[bottom] = bottom_left;
+        pixels[bottom + 1] = bottom_right;
+    }
+}
+
+/// Returns the top left, top right, bottom left and bottom right pixels of the block described by `word`.
+/// With the `fixed-point` feature, the pixels are computed with integer arithmetic only by `fixed_point::decode_block`.
+///
+/// # Arguments:
+/// * `word`: the word describing the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout the word was packed with
+pub fn decode_block(word: u64, denominator: f64, layout: WordLayout) -> [Rgb; 4] {
+    if cfg!(feature = "fixed-point") {
+        fixed_point::decode_block(word, denominator as u16, layout)
+    } else {
+        decode_block_float(word, denominator, layout)
+    }
 }
 
-/// Helper function to read an image that is not compressed. Returns an Array2 of PixelBlocks,
-/// coordinates for height and width, and the denominator. The order of the tuple is as follows:
-/// (`Array of pixel groups`, `height`, `width`, `denominator`)
+/// Returns the top left, top right, bottom left and bottom right pixels of the block described by `word`,
+/// computed with floating point arithmetic
+///
+/// # Arguments:
+/// * `word`: the word describing the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout the word was packed with
+pub fn decode_block_float(word: u64, denominator: f64, layout: WordLayout) -> [Rgb; 4] {
+    let (a, b, c, d, pb, pr) = layout.unpack(word);
+
+    // convert each of the integer values into a floating point representation. Grayscale words carry no chroma,
+    // so their pixels have none
+    let a_float = layout.scaled_int_to_coefficient(a);
+    let (b_float, c_float, d_float) = quantize::scaled_ints_to_coefficients(b, c, d);
+    let (pb_chroma, pr_chroma) = layout.indices_to_chroma(pb, pr);
+
+    // convert the coefficients back into luma
+    let (y1, y2, y3, y4) = quantize::get_luma(a_float, b_float, c_float, d_float);
+    /* 
+        Collect each of the luma with the pb and pr chroma values and decompress each of them with the denominator
+        of the source image, and then convert each of the floating point Rgb values back into Rgb pixels.
+    */
+    [y1, y2, y3, y4].map(|y| {
+        pixel_conversion::float_to_rgb(pixel_conversion::get_decompression_tuple((y, pb_chroma, pr_chroma)), denominator)
+    })
+}
+
+/// Helper function to read the pixels of an image that is not compressed. Returns an Array2 of PixelBlocks,
+/// coordinates for height and width, the denominator, and the colour model. The order of the tuple is as follows:
+/// (`Array of pixel groups`, `height`, `width`, `denominator`, `colour model`)
 /// 
 /// # Arguments
-/// * `filename`: the file name received from the user. Input is None iff the user does not enter
-/// a filename as a command line argument
-fn read_uncompressed_image(filename: Option<&str>) -> (Array2<PixelBlock>, usize, usize, u16) {
-    // read the image and store it as an RgbImage
-    let img = RgbImage::read(filename).unwrap();
+/// * `pnm_img`: the image, read in any supported format
+fn read_uncompressed_image(pnm_img: &pnm::PnmImage) -> (Array2<PixelBlock>, usize, usize, u16, ColourModel) {
+    // bitmaps and graymaps are grayscale, and are stored as an RgbImage with each gray value copied into all
+    // three colors
+    let model = if pnm_img.depth < 3 { ColourModel::Gray } else { ColourModel::Rgb };
+    let img = pnm_img.to_rgb_image();
 
     let mut arr_contents = Vec::new();
     //trim the width and height prior so that they are rounded down to the nearest even number
@@ -154,79 +476,196 @@ fn read_uncompressed_image(filename: Option<&str>) -> (Array2<PixelBlock>, usize
         Array2::from(arr_contents, trimmed_h as usize / 2, trimmed_w as usize / 2),
         trimmed_h as usize,
         trimmed_w as usize,
-        img.denominator
+        img.denominator,
+        model
     )
 
 }
 
-/// Helper function to read an image that is compressed. Returns a Vec<(usize, usize, u32)>,
-/// and the dimensions of the image. The tuple returned is in the following format:
-/// (`Vec of words`, `width`, `height`)
+/// Helper function to read an image that is compressed. Returns a CompressedImage holding a grid of words
+/// along with the dimensions, colour model, and denominator of the image, or an error if the file is not
+/// a valid rpeg file or exceeds `limits`, along with the rows of pixels that were concealed
 /// 
 /// # Arguments:
 /// * filename: the file name received from the user. Input is None iff the user does not enter
-/// a filename as a command line argument
-fn read_compressed_image(filename: Option<&str>) -> (Vec<(usize, usize, u32)>, u32, u32) {
-    //read image in using rpegio crate
-    let (img, width, height) = csc411_rpegio::read_in_rpeg_data(filename).unwrap();
-
-    // map row major coordinates over each word and convert each collection of Big-Endian bytes into a 32 bit word
-    let rpeg_img: Vec<(usize, usize, u32)> = img.into_iter()
-        .enumerate()
-        .map(|(idx, bytes)| (
-            (idx / (width / 2) as usize) * 2,
-            (idx % (width / 2) as usize) * 2,
-            u32::from_be_bytes(bytes)
-        ))
-        .collect();
-    
-    // return the image and dimensions
-    (
-        rpeg_img,
-        width,
-        height
-    )
-
+///   a filename as a command line argument
+/// * `limits`: the largest image to accept
+/// * `conceal`: whether to conceal the damaged segments of a file with restart markers
+fn read_compressed_image(filename: Option<&str>, limits: DecodeLimits, conceal: bool)
+    -> Result<(CompressedImage, Vec<Range<u32>>), String> {
+    if conceal {
+        CompressedImage::from_bytes_concealing(&image_file::read_bytes(filename)?, limits)
+    } else {
+        CompressedImage::read_with_limits(filename, limits).map(|img| (img, Vec::new()))
+    }
 }
 
-/// Helper function to write an uncompressed image to standard out.
+/// Helper function to turn a decompressed image into the image that is written out
 /// 
 /// # Arguments:
 /// * `img`: an RgbImage containing the decompressed output of the image
-fn write_uncompressed_image(img: RgbImage) {
-    let _ = img.write(None);
-}
-
-/// Helper function to write an compressed image to standard out.
-/// 
-/// # Arguments:
-/// * `compressed_img`: a Vec of 32 bit words represented in 4 Big-Endian bytes
-/// * `width`: the width of the image
-/// * `height`: the height of the image
-fn write_compressed_image(compressed_arr: &Vec<[u8; 4]>, width: u32, height: u32) {
-    csc411_rpegio::output_rpeg_data(compressed_arr.as_slice(), width, height);
+/// * `model`: the colour model of the image. Grayscale images keep only the red value of each pixel,
+///   since all three colors are equal
+/// * `alpha_values`: the alpha value of every pixel, for images with transparency
+fn to_uncompressed_image(img: RgbImage, model: ColourModel, alpha_values: Option<Array2<u16>>) -> pnm::PnmImage {
+    let pnm_img = match model {
+        ColourModel::Rgb => pnm::PnmImage::from_rgb_image(&img),
+        ColourModel::Gray => pnm::PnmImage::gray_from_rgb_image(&img),
+    };
+    match alpha_values {
+        Some(values) => {
+            let values: Vec<u16> = values.iter_row_maj().map(|(_, _, &value)| value).collect();
+            pnm_img.with_alpha(&values)
+        }
+        None => pnm_img,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use csc411_image::*;
+    use crate::testimages;
 
     use super::*;
 
     #[test]
     fn test_read_uncompressed() {
-        let (arr, height, width, denominator) = read_uncompressed_image(Some("Connor.ppm"));
-        let new_arr: Vec<(usize, usize, Rgb)> = arr.iter_row_maj().map(|(_, _, group)| group.clone().unpack()).flatten().collect();
-        let mut new_img: Vec<(usize, Rgb)> = new_arr.into_iter().map(|(r, c, pixel)| (r*width + c, pixel)).collect();
-        new_img.sort_by_key(|(idx, _)| *idx);
-        let cleaned_img: Vec<Rgb> = new_img.into_iter().map(|(_, pixel)| pixel).collect();
-        let dest_img = RgbImage {
-            pixels: cleaned_img,
-            width: width as u32,
-            height: height as u32,
-            denominator
-        };
-        let _ = dest_img.write(Some("SameConnor.ppm"));
-        assert!(true);
+        // an odd width and height are trimmed to the nearest even number
+        let img = testimages::gradient(37, 23);
+        let (arr, height, width, denominator, model) = read_uncompressed_image(&img);
+        assert_eq!((arr.height(), arr.width(), height, width, denominator, model), (11, 18, 22, 36, 255, ColourModel::Rgb));
+        for (r, c, pixel) in arr.iter_row_maj().flat_map(|(_, _, group)| group.clone().unpack()) {
+            let pos = (r * 37 + c) * 3;
+            assert_eq!([pixel.red, pixel.green, pixel.blue], img.samples[pos..pos + 3]);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_test_images() {
+        for (name, img) in testimages::all(32, 24) {
+            let compressed = compress_image(&img);
+            let reread = CompressedImage::from_bytes(&compressed.to_bytes()).unwrap();
+            assert_eq!(reread.to_bytes(), compressed.to_bytes(), "{}", name);
+            let decoded = decompress_image(&reread);
+            assert_eq!((decoded.width, decoded.height, decoded.depth, decoded.maxval), (32, 24, img.depth, img.maxval), "{}", name);
+
+            // smooth images come back close to the original, with a mean error under 4% of the range
+            if name.starts_with("gradient") {
+                let error: u64 = img.samples.iter().zip(&decoded.samples).map(|(&a, &b)| a.abs_diff(b) as u64).sum();
+                assert!(error * 25 < img.samples.len() as u64 * img.maxval as u64, "{}: total error {}", name, error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompress_places_blocks() {
+        let words = vec![0x0123_4567, 0x89ab_cdef, 0x1357_9bdf, 0x2468_ace0];
+        let rpeg_img = CompressedImage::from_words(words.clone(), 4, 4, ColourModel::Rgb, 255);
+        let decoded = decompress_image(&rpeg_img);
+        for (idx, &word) in words.iter().enumerate() {
+            let (row, col) = (idx / 2 * 2, idx % 2 * 2);
+            let block = decode_block(word, 255.0, rpeg_img.layout());
+            for (pixel, (r, c)) in block.iter().zip([(row, col), (row, col + 1), (row + 1, col), (row + 1, col + 1)]) {
+                let pos = (r * 4 + c) * 3;
+                assert_eq!(&decoded.samples[pos..pos + 3], &[pixel.red, pixel.green, pixel.blue]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_threads_match_single_thread() {
+        let img = testimages::noise(38, 22, 5);
+        let single = compress_image(&img);
+        let decoded = decompress_image(&single);
+        for threads in [2, 3, 8, 64] {
+            let threaded = compress_image_with_threads(&img, threads);
+            assert_eq!(threaded.to_bytes(), single.to_bytes());
+            assert_eq!(decompress_image_with_threads(&threaded, threads), decoded);
+        }
+    }
+
+    #[test]
+    fn test_decode_region() {
+        for img in [testimages::zone_plate(30, 22), testimages::rescale(&testimages::gradient(30, 22), 1000)] {
+            let mut compressed = compress_image(&img);
+            compressed.arrangement = Arrangement::Tiles(8, 6);
+            let bytes = compressed.to_bytes();
+            let whole = decompress_image(&CompressedImage::from_bytes(&bytes).unwrap());
+            assert_eq!(whole, decompress_image(&compress_image(&img)));
+
+            // regions on and off the block and tile boundaries hold the same pixels as the whole image
+            for (x, y, w, h) in [(0, 0, 30, 22), (3, 5, 11, 9), (8, 6, 8, 6), (29, 21, 1, 1)] {
+                let region = decode_region(&mut Cursor::new(&bytes), x, y, w, h, DecodeLimits::default()).unwrap();
+                let depth = whole.depth as usize;
+                let expected: Vec<u16> = (y..y + h).flat_map(|row| {
+                    let start = (row * whole.width + x) as usize * depth;
+                    whole.samples[start..start + w as usize * depth].to_vec()
+                }).collect();
+                assert_eq!((region.width, region.height, region.depth), (w, h, whole.depth));
+                assert_eq!(region.samples, expected, "{} x {} region at ({}, {})", w, h, x, y);
+            }
+        }
+
+        let err = |bytes: &[u8], x, y| decode_region(&mut Cursor::new(bytes), x, y, 4, 4, DecodeLimits::default()).err().unwrap();
+        let mut compressed = compress_image(&testimages::gradient(8, 6));
+        assert_eq!(err(&compressed.to_bytes(), 0, 0), "only tiled images can be decoded by region");
+        compressed.arrangement = Arrangement::Tiles(4, 4);
+        let bytes = compressed.to_bytes();
+        assert_eq!(err(&bytes, 6, 0), "the 4 x 4 region at (6, 0) lies outside the 8 x 6 image");
+        assert_eq!(err(&bytes[..bytes.len() - 10], 4, 2), "compressed image is truncated in tile 3");
+        for (w, h) in [(0, 5), (3, 0), (0, 0)] {
+            assert_eq!(
+                decode_region(&mut Cursor::new(&bytes), 3, 0, w, h, DecodeLimits::default()).err().unwrap(),
+                format!("the region must be at least 1 x 1 pixels, found {} x {}", w, h)
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_images_psnr() {
+        // the lowest PSNR allowed for each kind of image: noise has no structure for the blocks to capture, while
+        // gradients keep most of their detail, and zone plates lose their finest rings
+        const FLOORS: [f64; 3] = [10.0, 25.0, 20.0];
+        let mut rng = testimages::Rng::new(42);
+        for _ in 0..200 {
+            let (width, height) = (16 + rng.below(49) as u32, 16 + rng.below(49) as u32);
+            let kind = rng.below(3) as usize;
+            let img = match kind {
+                0 => testimages::noise(width, height, rng.next_u64()),
+                1 => testimages::gradient(width, height),
+                _ => testimages::rescale(&testimages::zone_plate(width, height), 1 + rng.below(65535) as u16),
+            };
+            let decoded = decompress_image(&compress_image(&img));
+            let psnr = testimages::psnr(&img, &decoded);
+            assert!(psnr > FLOORS[kind], "{} x {} {} image with maxval {}: {:.2} dB", width, height, img.tupltype, img.maxval, psnr);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_corrupt_files() {
+        // a cheap stand-in for the fuzz targets: valid files with a few bytes overwritten, inserted or cut off must
+        // either be rejected or decode to an image, without panicking
+        let mask: Vec<u16> = (0..48).map(|n| if n % 5 == 0 { 0 } else { 255 }).collect();
+        let originals: Vec<Vec<u8>> = [
+            testimages::gradient(8, 6),
+            testimages::rescale(&testimages::checkerboard(8, 6, 2), 1000),
+            testimages::gradient(8, 6).with_alpha(&mask),
+            testimages::gradient(8, 6).with_alpha(&[200; 48]),
+        ].iter().map(|img| compress_image(img).to_bytes()).collect();
+        let mut rng = testimages::Rng::new(43);
+        for _ in 0..5000 {
+            let mut bytes = originals[rng.below(originals.len() as u64) as usize].clone();
+            for _ in 0..1 + rng.below(3) {
+                let pos = rng.below(bytes.len() as u64) as usize;
+                match rng.below(3) {
+                    0 => bytes[pos] = rng.below(256) as u8,
+                    1 => bytes.insert(pos, b"0123456789 \n"[rng.below(12) as usize]),
+                    _ => bytes.truncate(pos.max(1)),
+                }
+            }
+            if let Ok(img) = CompressedImage::from_bytes(&bytes) {
+                let decoded = decompress_image(&img);
+                assert_eq!(decoded.samples.len(), (decoded.width * decoded.height * decoded.depth) as usize);
+            }
+        }
+    }
+}
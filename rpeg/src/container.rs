@@ -0,0 +1,857 @@
+use array2::Array2;
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use crate::alpha::{self, AlphaPlane};
+use crate::checksum;
+use crate::image_file;
+use crate::limits::DecodeLimits;
+use crate::progressive;
+use crate::pyramid::{self, Pyramid};
+use crate::restart::Segments;
+use crate::tiles::{TileIndex, Tiles};
+use crate::quantize::{WordLayout, GRAY_LAYOUT, RGB_LAYOUT, WIDE_GRAY_LAYOUT, WIDE_RGB_LAYOUT};
+
+/// The colour model of a compressed image, which decides which fields are packed into each word
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColourModel {
+    /// luma coefficients and averaged chroma
+    Rgb,
+    /// luma coefficients only
+    Gray,
+}
+
+/// Returns the layout of the words used for images with the colour model `model` and denominator `denominator`.
+/// Images with samples wider than 8 bits get wider fields, so that their extra precision is not thrown away.
+///
+/// # Arguments:
+/// * `model`: the colour model of the image
+/// * `denominator`: the largest sample value of the source image
+pub fn layout_for(model: ColourModel, denominator: u16) -> WordLayout {
+    match (model, denominator > 255) {
+        (ColourModel::Rgb, false) => RGB_LAYOUT,
+        (ColourModel::Gray, false) => GRAY_LAYOUT,
+        (ColourModel::Rgb, true) => WIDE_RGB_LAYOUT,
+        (ColourModel::Gray, true) => WIDE_GRAY_LAYOUT,
+    }
+}
+
+/// How the alpha plane of a compressed image is stored, as named by its header
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlphaField {
+    /// `alpha transform`: one grayscale word for every block
+    Transform,
+    /// `alpha mask length`: `length` bytes of runs
+    Mask(usize),
+}
+
+/// The header of an rpeg file, which describes the words that follow it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub model: ColourModel,
+    pub denominator: u16,
+    pub alpha: Option<AlphaField>,
+    /// `restart n`: the words are split into segments of `n` block rows, each starting with a restart marker
+    pub restart: Option<usize>,
+    /// `tiles w h` and `index offsets`: the words are stored tile by tile, starting at the offsets in the index
+    pub tiles: Option<TileIndex>,
+    /// `scan progressive`: the words are split into passes, the DC of every block first and then each bit of the
+    /// other coefficients, so that any prefix of them is an image
+    pub progressive: bool,
+    /// `pyramid n`: the `a` fields are held in a pyramid of `n` levels, ahead of the other fields of every word
+    pub pyramid: Option<u32>,
+    /// `length n`: the number of bytes of words and alpha plane that follow the header
+    pub length: Option<usize>,
+    /// `checksum crc32`: the words are followed by the Big-Endian CRC-32 of everything before it in the file
+    pub checksum: bool,
+}
+
+impl Header {
+    /// Reads a header from `reader`, leaving it positioned at the first word.
+    ///
+    /// Files in the original format start with `Compressed image format 2` followed by the dimensions, and hold
+    /// a color image with a denominator of 255. Any other file uses `Compressed image format 3`, where the dimensions
+    /// are followed by `key value` lines describing the stream and then a blank line.
+    ///
+    /// # Arguments:
+    /// * `reader`: the reader positioned at the start of the file
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<Self, String> {
+        let format = read_line(reader)?;
+        let dimensions = read_line(reader)?;
+        let (width, height) = match dimensions.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [w, h] => (
+                w.parse::<u32>().map_err(|_| format!("invalid width: {}", w))?,
+                h.parse::<u32>().map_err(|_| format!("invalid height: {}", h))?,
+            ),
+            _ => return Err(format!("invalid dimensions: {}", dimensions)),
+        };
+
+        let mut header = Header {
+            width, height, model: ColourModel::Rgb, denominator: 255, alpha: None, restart: None, tiles: None, progressive: false, pyramid: None,
+            length: None, checksum: false
+        };
+        match format.as_str() {
+            "Compressed image format 2" => (),
+            "Compressed image format 3" => loop {
+                let line = read_line(reader)?;
+                match line.split_once(' ') {
+                    None if line.is_empty() => break,
+                    Some(("model", "rgb")) => header.model = ColourModel::Rgb,
+                    Some(("model", "gray")) => header.model = ColourModel::Gray,
+                    Some(("alpha", "transform")) => header.alpha = Some(AlphaField::Transform),
+                    Some(("alpha", value)) if value.starts_with("mask ") => {
+                        header.alpha = match value["mask ".len()..].parse::<usize>() {
+                            Ok(n) => Some(AlphaField::Mask(n)),
+                            Err(_) => return Err(format!("invalid alpha mask length: {}", value)),
+                        };
+                    }
+                    Some(("denominator", value)) => header.denominator = match value.parse::<u16>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid denominator: {}", value)),
+                    },
+                    Some(("restart", value)) => header.restart = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid restart interval: {}", value)),
+                    },
+                    Some(("tiles", value)) => header.tiles = match value.split_once(' ').map(|(w, h)| (w.parse::<u32>(), h.parse::<u32>())) {
+                        Some((Ok(w), Ok(h))) if w > 0 && h > 0 && w % 2 == 0 && h % 2 == 0 => {
+                            Some(TileIndex { width: w, height: h, offsets: Vec::new() })
+                        }
+                        _ => return Err(format!("invalid tile size: {}", value)),
+                    },
+                    // an image without any tiles has an empty index
+                    None if line == "index" && header.tiles.is_some() => (),
+                    Some(("index", value)) => match header.tiles.as_mut() {
+                        Some(tiles) => tiles.offsets = value.split(' ').map(|offset| offset.parse::<usize>()).collect::<Result<_, _>>()
+                            .map_err(|_| format!("invalid tile index: {}", value))?,
+                        None => return Err("tile index comes before the tile size".to_string()),
+                    },
+                    Some(("length", value)) => header.length = match value.parse::<usize>() {
+                        Ok(n) => Some(n),
+                        Err(_) => return Err(format!("invalid payload length: {}", value)),
+                    },
+                    Some(("scan", "progressive")) => header.progressive = true,
+                    Some(("pyramid", value)) => header.pyramid = match value.parse::<u32>() {
+                        Ok(n) if (1..=pyramid::MAX_LEVELS).contains(&n) => Some(n),
+                        _ => return Err(format!("invalid pyramid levels: {}", value)),
+                    },
+                    Some(("checksum", "crc32")) => header.checksum = true,
+                    _ => return Err(format!("unknown header field: {}", line)),
+                }
+            },
+            _ => return Err(format!("not an rpeg file: {}", format)),
+        }
+        if header.restart.is_some() && header.tiles.is_some() {
+            return Err("restart markers and tiles cannot be used together".to_string());
+        }
+        if header.progressive && (header.restart.is_some() || header.tiles.is_some()) {
+            return Err("progressive images cannot have restart markers or tiles".to_string());
+        }
+        if header.pyramid.is_some() && (header.restart.is_some() || header.tiles.is_some() || header.progressive) {
+            return Err("a pyramid cannot be combined with restart markers, tiles or progressive passes".to_string());
+        }
+        Ok(header)
+    }
+
+    /// Returns the text of the header, using the original format for color images with a denominator of 255
+    /// when there is no length or checksum to record
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let arrangement = (self.restart, &self.tiles, self.progressive, self.pyramid);
+        match (self.model, self.denominator, self.alpha, arrangement, self.length, self.checksum) {
+            (ColourModel::Rgb, 255, None, (None, None, false, None), None, false) => {
+                format!("Compressed image format 2\n{} {}\n", self.width, self.height)
+            }
+            (model, denominator, alpha, (restart, tiles, progressive, pyramid), length, checksum) => format!(
+                "Compressed image format 3\n{} {}\nmodel {}\ndenominator {}\n{}{}{}{}{}{}{}\n",
+                self.width, self.height,
+                if model == ColourModel::Gray { "gray" } else { "rgb" },
+                denominator,
+                match alpha {
+                    Some(AlphaField::Transform) => "alpha transform\n".to_string(),
+                    Some(AlphaField::Mask(len)) => format!("alpha mask {}\n", len),
+                    None => String::new(),
+                },
+                restart.map_or(String::new(), |n| format!("restart {}\n", n)),
+                tiles.as_ref().map_or(String::new(), |tiles| format!(
+                    "tiles {} {}\nindex{}\n", tiles.width, tiles.height,
+                    tiles.offsets.iter().map(|offset| format!(" {}", offset)).collect::<String>()
+                )),
+                if progressive { "scan progressive\n" } else { "" },
+                pyramid.map_or(String::new(), |n| format!("pyramid {}\n", n)),
+                length.map_or(String::new(), |n| format!("length {}\n", n)),
+                if checksum { "checksum crc32\n" } else { "" }
+            ),
+        }.into_bytes()
+    }
+
+    /// Returns the layout of the color words that follow the header
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the header
+    pub fn layout(&self) -> WordLayout {
+        layout_for(self.model, self.denominator)
+    }
+}
+
+/// How `CompressedImage::parse` treats a file that is damaged or cut short
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    /// reject it
+    Strict,
+    /// conceal the damaged segments of a file with restart markers
+    Conceal,
+    /// decode as much of a progressive image as has arrived
+    Prefix,
+}
+
+/// How the words of a compressed image are laid out in its file. Each arrangement takes the place of the others, so
+/// an image has exactly one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arrangement {
+    /// one word after another, in row major order
+    Rows,
+    /// in segments of this many block rows, each starting with a restart marker
+    Restart(usize),
+    /// tile by tile, in tiles of this width and height in pixels, both positive and even
+    Tiles(u32, u32),
+    /// in progressive passes, the DC of every block first and then each bit of the other coefficients
+    Progressive,
+    /// with the `a` fields held in a pyramid of this many levels, ahead of the other fields of every word
+    Pyramid(u32),
+}
+
+/// The number of bytes in the CRC-32 at the end of files whose header has a `checksum`
+pub const CHECKSUM_BYTES: usize = 4;
+
+/// A compressed image held in memory: a grid of code words, one for every 2 x 2 block of pixels,
+/// along with the dimensions of the image they describe, the colour model of the words, the
+/// denominator of the source image, the alpha plane of images with transparency, and how the words are arranged
+/// in the file
+pub struct CompressedImage {
+    pub words: Array2<u64>,
+    pub width: u32,
+    pub height: u32,
+    pub model: ColourModel,
+    pub denominator: u16,
+    pub alpha: Option<AlphaPlane>,
+    pub arrangement: Arrangement,
+}
+
+impl CompressedImage {
+    /// Builds a compressed image without an alpha plane from a Vec of code words in row major order
+    ///
+    /// # Arguments:
+    /// * `words`: the code words of the image, one for every 2 x 2 block of pixels
+    /// * `width`: the width of the image in pixels
+    /// * `height`: the height of the image in pixels
+    /// * `model`: the colour model the words were packed with
+    /// * `denominator`: the denominator of the source image
+    pub fn from_words(words: Vec<u64>, width: u32, height: u32, model: ColourModel, denominator: u16) -> Self {
+        CompressedImage {
+            words: Array2::from(words, height as usize / 2, width as usize / 2),
+            width,
+            height,
+            model,
+            denominator,
+            alpha: None,
+            arrangement: Arrangement::Rows,
+        }
+    }
+
+    /// Returns the layout the words of the image were packed with
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the compressed image
+    pub fn layout(&self) -> WordLayout {
+        layout_for(self.model, self.denominator)
+    }
+
+    /// Reads a compressed image from the file named `filename`, or standard input
+    ///
+    /// # Arguments:
+    /// * `filename`: the file name received from the user. Input is None iff the user does not enter
+    ///   a filename as a command line argument
+    pub fn read(filename: Option<&str>) -> Result<Self, String> {
+        CompressedImage::from_bytes(&image_file::read_bytes(filename)?)
+    }
+
+    /// Reads a compressed image from the file named `filename`, or standard input, rejecting it if it exceeds `limits`
+    ///
+    /// # Arguments:
+    /// * `filename`: the file name received from the user. Input is None iff the user does not enter
+    ///   a filename as a command line argument
+    /// * `limits`: the largest image to accept
+    pub fn read_with_limits(filename: Option<&str>, limits: DecodeLimits) -> Result<Self, String> {
+        CompressedImage::from_bytes_with_limits(&image_file::read_bytes(filename)?, limits)
+    }
+
+    /// Parses a compressed image out of the contents of an rpeg file: a `Header`, the color words, and then
+    /// the alpha plane, as one grayscale word per block for `alpha transform`, or as `length` bytes of runs
+    /// for `alpha mask length`. Files whose header has a `checksum` end with a CRC-32, and a file that is cut short
+    /// or whose CRC-32 does not match its contents is rejected with an error naming where it ends or what was found.
+    ///
+    /// # Arguments:
+    /// * `bytes`: the full contents of the file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        CompressedImage::from_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    /// Parses a compressed image out of the contents of an rpeg file like `from_bytes`, rejecting it before anything
+    /// is allocated if its header exceeds `limits`
+    ///
+    /// # Arguments:
+    /// * `bytes`: the full contents of the file
+    /// * `limits`: the largest image to accept
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: DecodeLimits) -> Result<Self, String> {
+        CompressedImage::parse(bytes, limits, Mode::Strict).map(|(img, _)| img)
+    }
+
+    /// Parses a compressed image like `from_bytes_with_limits`, but conceals damage to files written with restart
+    /// markers instead of rejecting them: the segments whose words do not match their CRC-32, or that cannot be found,
+    /// are filled in from the rows around them. Returns the image along with the rows of pixels that were concealed,
+    /// which is empty for an intact file. Files without restart markers must be intact.
+    ///
+    /// # Arguments:
+    /// * `bytes`: the full contents of the file
+    /// * `limits`: the largest image to accept
+    pub fn from_bytes_concealing(bytes: &[u8], limits: DecodeLimits) -> Result<(Self, Vec<Range<u32>>), String> {
+        match CompressedImage::parse(bytes, limits, Mode::Strict) {
+            Ok((img, _)) => Ok((img, Vec::new())),
+            Err(e) => match Header::read(&mut &bytes[..]) {
+                Ok(header) if header.restart.is_some() => CompressedImage::parse(bytes, limits, Mode::Conceal),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Parses as much of a progressive image as `bytes` holds, for showing a preview of a file that is still
+    /// arriving. The header must be complete, but the passes may stop anywhere, with the bits that have not arrived
+    /// filled in by `progressive::read`. The alpha plane follows the passes, so it is left out until all of it has
+    /// arrived, and the CRC-32 is only checked once the whole file has. Returns the image along with the number of
+    /// passes that are complete.
+    ///
+    /// # Arguments:
+    /// * `bytes`: the start of the file
+    /// * `limits`: the largest image to accept
+    pub fn from_prefix(bytes: &[u8], limits: DecodeLimits) -> Result<(Self, usize), String> {
+        let mut payload = bytes;
+        let header = Header::read(&mut payload)?;
+        if !header.progressive {
+            return Err("only progressive images can be decoded from a prefix".to_string());
+        }
+        let (img, _) = CompressedImage::parse(bytes, limits, Mode::Prefix)?;
+        let passes = progressive::complete_passes(payload.len(), img.words.height() * img.words.width(), header.layout());
+        Ok((img, passes))
+    }
+
+    /// Helper function that parses a compressed image out of the contents of an rpeg file, along with the rows of
+    /// pixels that were concealed
+    ///
+    /// # Arguments:
+    /// * `bytes`: the full contents of the file
+    /// * `limits`: the largest image to accept
+    /// * `mode`: how to treat a file that is damaged or cut short
+    fn parse(bytes: &[u8], limits: DecodeLimits, mode: Mode) -> Result<(Self, Vec<Range<u32>>), String> {
+        let mut payload = bytes;
+        let header = Header::read(&mut payload)?;
+        limits.check(&header)?;
+        let layout = header.layout();
+        let word_bytes = layout.bytes();
+        let Header { width, height, model, denominator, alpha, restart, tiles, progressive, pyramid, length, checksum } = header;
+
+        // compressed images always cover whole blocks, so odd dimensions could only describe pixels that no
+        // word holds, which would be allocated without any bytes in the file to back them
+        if width % 2 != 0 || height % 2 != 0 {
+            return Err(format!("compressed image dimensions must be even, found {} x {}", width, height));
+        }
+
+        // each word is stored as a fixed number of Big-Endian bytes, in segments when there are restart markers,
+        // or is split across the passes of a progressive image, followed by the alpha plane. The sizes come from the
+        // header, so they are worked out with checked arithmetic before anything is allocated. The codes of a pyramid
+        // vary in length, so only the recorded length says where they end.
+        let too_large = || format!("image dimensions are too large: {} x {}", width, height);
+        let blocks = (width as usize / 2).checked_mul(height as usize / 2).ok_or_else(too_large)?;
+        let tiles = tiles.map(|index| Tiles::from_index(&index, width, height, word_bytes)).transpose()?;
+        let alpha_bytes = match alpha {
+            None => 0,
+            Some(AlphaField::Mask(len)) => len,
+            Some(AlphaField::Transform) => {
+                blocks.checked_mul(layout_for(ColourModel::Gray, denominator).bytes()).ok_or_else(too_large)?
+            }
+        };
+        let segments = match restart {
+            Some(interval) => {
+                let row_bytes = (width as usize / 2).checked_mul(word_bytes).ok_or_else(too_large)?;
+                Some(Segments::new(interval, height as usize / 2, row_bytes))
+            }
+            None => None,
+        };
+        let color_bytes = match segments {
+            Some(segments) => segments.encoded_len(),
+            None if progressive => progressive::encoded_len(blocks, layout),
+            None if pyramid.is_some() => match length {
+                Some(n) if n >= alpha_bytes => Some(n - alpha_bytes),
+                Some(n) => return Err(format!("header records {} bytes of words, but the alpha plane needs {}", n, alpha_bytes)),
+                None => return Err("pyramid images must record their length".to_string()),
+            },
+            None => blocks.checked_mul(word_bytes),
+        }.ok_or_else(too_large)?;
+        let expected = color_bytes.checked_add(alpha_bytes).ok_or("alpha plane is too large")?;
+        if let Some(n) = length.filter(|&n| n != expected) {
+            return Err(format!("header records {} bytes of words, but the image needs {}", n, expected));
+        }
+        let trailer = if checksum { CHECKSUM_BYTES } else { 0 };
+
+        let (color_payload, alpha_payload, concealed) = match segments {
+            _ if mode == Mode::Prefix && progressive && payload.len() < expected + trailer => {
+                // the alpha plane is only used once all of it has arrived
+                let color_payload = &payload[..payload.len().min(color_bytes)];
+                (Cow::Borrowed(color_payload), payload.get(color_bytes..expected), Vec::new())
+            }
+            Some(segments) if mode == Mode::Conceal => {
+                // damage may have changed the length of the segments, so the alpha plane is found from the end
+                let end = payload.len().checked_sub(alpha_bytes + trailer)
+                    .ok_or("compressed image is truncated in its alpha plane")?;
+                let (words, damaged) = segments.recover(&payload[..end])?;
+                let concealed = damaged.into_iter().map(|rows| rows.start as u32 * 2..rows.end as u32 * 2).collect();
+                (Cow::Owned(words), Some(&payload[end..end + alpha_bytes]), concealed)
+            }
+            _ => {
+                // the length of the file is checked before its checksum, so that a file that was cut short says where
+                if payload.len() < color_bytes && progressive {
+                    let pass = progressive::complete_passes(payload.len(), blocks, layout) + 1;
+                    return Err(format!("compressed image is truncated in pass {} of {}", pass, progressive::pass_count(layout)));
+                } else if payload.len() < color_bytes && pyramid.is_some() {
+                    return Err(format!("compressed image is truncated in its pyramid, after {} of {} bytes", payload.len(), color_bytes));
+                } else if payload.len() < color_bytes {
+                    let word = segments.map_or(payload.len() / word_bytes, |s| s.word_at(payload.len(), word_bytes));
+                    return Err(format!("compressed image is truncated at word {} of {}", word, blocks));
+                } else if payload.len() < expected {
+                    let found = payload.len() - color_bytes;
+                    return Err(format!("compressed image is truncated in its alpha plane, after {} of {} bytes", found, alpha_bytes));
+                } else if payload.len() < expected + trailer {
+                    return Err("compressed image is truncated in its checksum".to_string());
+                } else if payload.len() != expected + trailer {
+                    return Err(format!("expected {} bytes of words, found {}", expected + trailer, payload.len()));
+                }
+                // the segments are checked first, since their CRC-32s say which rows are damaged
+                let (color_payload, alpha_payload) = payload[..expected].split_at(color_bytes);
+                let color_payload = match segments {
+                    Some(segments) => Cow::Owned(segments.read(color_payload)?),
+                    None => Cow::Borrowed(color_payload),
+                };
+                if checksum {
+                    let (contents, trailer) = bytes.split_at(bytes.len() - CHECKSUM_BYTES);
+                    let recorded = u32::from_be_bytes(trailer.try_into().unwrap());
+                    let computed = checksum::crc32(contents);
+                    if recorded != computed {
+                        return Err(format!("checksum mismatch: the file records {:08x}, but its contents give {:08x}", recorded, computed));
+                    }
+                }
+                (color_payload, Some(alpha_payload), Vec::new())
+            }
+        };
+
+        let words = match (pyramid, tiles) {
+            (Some(levels), _) => Pyramid { rows: height as usize / 2, cols: width as usize / 2, levels }.read(&color_payload, layout)?,
+            _ if progressive => progressive::read(&color_payload, blocks, layout),
+            (None, Some(tiles)) => tiles.untile(&parse_words(&color_payload, word_bytes)),
+            (None, None) => parse_words(&color_payload, word_bytes),
+        };
+        let mut img = CompressedImage::from_words(words, width, height, model, denominator);
+        // `Header::read` has already rejected files with more than one arrangement
+        img.arrangement = match (restart, tiles, pyramid) {
+            (_, _, Some(levels)) => Arrangement::Pyramid(levels),
+            _ if progressive => Arrangement::Progressive,
+            (_, Some(tiles), _) => Arrangement::Tiles(tiles.tile_columns as u32 * 2, tiles.tile_rows as u32 * 2),
+            (Some(interval), _, _) => Arrangement::Restart(interval),
+            (None, None, None) => Arrangement::Rows,
+        };
+        img.alpha = match (alpha, alpha_payload) {
+            (None, _) | (_, None) => None,
+            (Some(AlphaField::Mask(_)), Some(alpha_payload)) => Some(AlphaPlane::Mask(alpha::decode_runs(
+                alpha_payload, (height & !1) as usize, (width & !1) as usize
+            )?)),
+            (Some(AlphaField::Transform), Some(alpha_payload)) => {
+                let alpha_words = parse_words(alpha_payload, layout_for(ColourModel::Gray, denominator).bytes());
+                Some(AlphaPlane::Transform(Array2::from(alpha_words, height as usize / 2, width as usize / 2)))
+            }
+        };
+        Ok((img, concealed))
+    }
+
+    /// Returns the contents of an rpeg file holding the compressed image, with the length of the words in the header
+    /// and a CRC-32 of the whole file at the end
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the compressed image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let alpha_runs = match &self.alpha {
+            Some(AlphaPlane::Mask(mask)) => Some(alpha::encode_runs(mask)),
+            _ => None,
+        };
+
+        // keep only the low bytes of each word that the layout uses, stored tile by tile for a tiled image, or split
+        // into segments when there are restart markers
+        let word_bytes = self.layout().bytes();
+        let tiles = match self.arrangement {
+            Arrangement::Tiles(width, height) => Some(Tiles {
+                tile_columns: width as usize / 2,
+                tile_rows: height as usize / 2,
+                columns: self.words.width(),
+                rows: self.words.height(),
+            }),
+            _ => None,
+        };
+        let mut payload = Vec::new();
+        match (self.arrangement, tiles) {
+            (Arrangement::Pyramid(levels), _) => {
+                let words: Vec<u64> = self.words.iter_row_maj().map(|(_, _, &word)| word).collect();
+                Pyramid { rows: self.words.height(), cols: self.words.width(), levels }.write(&words, self.layout(), &mut payload);
+            }
+            (Arrangement::Progressive, _) => {
+                let words: Vec<u64> = self.words.iter_row_maj().map(|(_, _, &word)| word).collect();
+                progressive::write(&words, self.layout(), &mut payload);
+            }
+            (_, Some(tiles)) => tiles.write(&self.words, word_bytes, &mut payload),
+            (Arrangement::Restart(interval), _) => {
+                let mut words = Vec::new();
+                push_words(&mut words, &self.words, word_bytes);
+                Segments::new(interval, self.words.height(), self.words.width() * word_bytes).write(&words, &mut payload);
+            }
+            _ => push_words(&mut payload, &self.words, word_bytes),
+        }
+        match (&self.alpha, &alpha_runs) {
+            (Some(AlphaPlane::Transform(words)), _) => {
+                push_words(&mut payload, words, layout_for(ColourModel::Gray, self.denominator).bytes())
+            }
+            (_, Some(runs)) => payload.extend_from_slice(runs),
+            _ => (),
+        }
+
+        let header = Header {
+            width: self.width,
+            height: self.height,
+            model: self.model,
+            denominator: self.denominator,
+            alpha: match (&self.alpha, &alpha_runs) {
+                (Some(AlphaPlane::Transform(_)), _) => Some(AlphaField::Transform),
+                (_, Some(runs)) => Some(AlphaField::Mask(runs.len())),
+                _ => None,
+            },
+            restart: match self.arrangement {
+                Arrangement::Restart(interval) => Some(interval),
+                _ => None,
+            },
+            tiles: tiles.map(|tiles| TileIndex {
+                width: tiles.tile_columns as u32 * 2,
+                height: tiles.tile_rows as u32 * 2,
+                offsets: tiles.offsets(word_bytes),
+            }),
+            progressive: self.arrangement == Arrangement::Progressive,
+            pyramid: match self.arrangement {
+                Arrangement::Pyramid(levels) => Some(levels),
+                _ => None,
+            },
+            length: Some(payload.len()),
+            checksum: true,
+        };
+        let mut bytes = header.to_bytes();
+        bytes.extend_from_slice(&payload);
+        bytes.extend_from_slice(&checksum::crc32(&bytes).to_be_bytes());
+        bytes
+    }
+
+    /// Writes the compressed image to standard output, returning an error if it cannot be written
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the compressed image
+    pub fn write(&self) -> Result<(), String> {
+        std::io::stdout().lock().write_all(&self.to_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+/// Helper function that returns the words stored in `payload`, each as `word_bytes` Big-Endian bytes
+///
+/// # Arguments:
+/// * `payload`: the bytes holding the words
+/// * `word_bytes`: the number of bytes in each word
+pub(crate) fn parse_words(payload: &[u8], word_bytes: usize) -> Vec<u64> {
+    payload.chunks_exact(word_bytes)
+        .map(|chunk| chunk.iter().fold(0_u64, |word, &byte| (word << 8) | byte as u64))
+        .collect()
+}
+
+/// Helper function that appends the low `word_bytes` bytes of every word in `words` to `bytes`, in Big-Endian order
+///
+/// # Arguments:
+/// * `bytes`: the contents of the file so far
+/// * `words`: the grid of words to append, in row major order
+/// * `word_bytes`: the number of bytes in each word
+fn push_words(bytes: &mut Vec<u8>, words: &Array2<u64>, word_bytes: usize) {
+    for (_, _, word) in words.iter_row_maj() {
+        bytes.extend_from_slice(&word.to_be_bytes()[8 - word_bytes..]);
+    }
+}
+
+/// Helper function that returns the text of the next line of a header, without its newline
+///
+/// # Arguments:
+/// * `reader`: the reader positioned at the start of the line
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line).map_err(|e| e.to_string())?;
+    if line.pop() != Some(b'\n') {
+        return Err("unexpected end of header".to_string());
+    }
+    String::from_utf8(line).map_err(|_| "header is not valid text".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use array2::Array2;
+    use crate::alpha::AlphaPlane;
+    use crate::container::{Arrangement, ColourModel, CompressedImage};
+    use crate::limits::DecodeLimits;
+    use crate::quantize::RGB_LAYOUT;
+
+    #[test]
+    fn test_rgb_round_trip() {
+        let img = CompressedImage::from_words(vec![0x01020304, 0xa0b0c0d0], 4, 2, ColourModel::Rgb, 255);
+        let bytes = img.to_bytes();
+        assert!(bytes.starts_with(b"Compressed image format 3\n4 2\nmodel rgb\ndenominator 255\nlength 8\nchecksum crc32\n\n"));
+        let parsed = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.model, ColourModel::Rgb);
+        assert_eq!(*parsed.words.get(0, 1), 0xa0b0c0d0);
+
+        // files in the original format, without a length or checksum, are still read
+        let original = CompressedImage::from_bytes(b"Compressed image format 2\n4 2\n\x01\x02\x03\x04\xa0\xb0\xc0\xd0").unwrap();
+        assert_eq!(original.words, img.words);
+    }
+
+    #[test]
+    fn test_gray_round_trip() {
+        let img = CompressedImage::from_words(vec![0x00abcdef, 0x00123456], 2, 4, ColourModel::Gray, 255);
+        let bytes = img.to_bytes();
+        assert_eq!(bytes.len(), "Compressed image format 3\n2 4\nmodel gray\ndenominator 255\nlength 6\nchecksum crc32\n\n".len() + 6 + 4);
+        let parsed = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.model, ColourModel::Gray);
+        assert_eq!((*parsed.words.get(0, 0), *parsed.words.get(1, 0)), (0x00abcdef, 0x00123456));
+    }
+
+    #[test]
+    fn test_wide_round_trip() {
+        let img = CompressedImage::from_words(vec![0x7fff_ffff_ffff], 2, 2, ColourModel::Rgb, 65535);
+        let bytes = img.to_bytes();
+        assert!(bytes.starts_with(b"Compressed image format 3\n2 2\nmodel rgb\ndenominator 65535\nlength 6\nchecksum crc32\n\n"));
+        let parsed = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!((parsed.model, parsed.denominator), (ColourModel::Rgb, 65535));
+        assert_eq!(*parsed.words.get(0, 0), 0x7fff_ffff_ffff);
+    }
+
+    #[test]
+    fn test_alpha_round_trip() {
+        let mut img = CompressedImage::from_words(vec![1, 2], 4, 2, ColourModel::Rgb, 255);
+        img.alpha = Some(AlphaPlane::Mask(Array2::from(vec![true, true, false, true, false, false, false, false], 2, 4)));
+        let parsed = CompressedImage::from_bytes(&img.to_bytes()).unwrap();
+        assert_eq!(parsed.alpha, img.alpha);
+
+        img.alpha = Some(AlphaPlane::Transform(Array2::from(vec![0x123456, 0xabcdef], 1, 2)));
+        let bytes = img.to_bytes();
+        assert!(bytes.starts_with(b"Compressed image format 3\n4 2\nmodel rgb\ndenominator 255\nalpha transform\nlength 14\nchecksum crc32\n\n"));
+        assert_eq!(CompressedImage::from_bytes(&bytes).unwrap().alpha, img.alpha);
+    }
+
+    #[test]
+    fn test_malformed_headers() {
+        assert!(CompressedImage::from_bytes(b"P6\n2 2\n255\n").is_err());
+        assert!(CompressedImage::from_bytes(b"Compressed image format 2\n2 2\n\x01\x02").is_err());
+        assert!(CompressedImage::from_bytes(b"Compressed image format 3\n2 2\nmodel cmyk\n\n").is_err());
+        assert!(CompressedImage::from_bytes(b"Compressed image format 3\n2 2\ndenominator 0\n\n").is_err());
+    }
+
+    #[test]
+    fn test_integrity() {
+        let mut img = CompressedImage::from_words((0..12).collect(), 8, 6, ColourModel::Rgb, 255);
+        let bytes = img.to_bytes();
+        let err = |bytes: &[u8]| CompressedImage::from_bytes(bytes).err().unwrap();
+        // 12 words of 4 bytes and a 4 byte checksum follow the header
+        let header = bytes.len() - 52;
+        assert_eq!(err(&bytes[..header + 21]), "compressed image is truncated at word 5 of 12");
+        assert_eq!(err(&bytes[..bytes.len() - 1]), "compressed image is truncated in its checksum");
+
+        let mut flipped = bytes.clone();
+        flipped[header + 10] ^= 0x04;
+        assert!(err(&flipped).starts_with("checksum mismatch: the file records"));
+        let mut wrong_length = bytes.clone();
+        assert_eq!(&bytes[header - 26..header - 16], b"length 48\n");
+        wrong_length[header - 19] = b'9';
+        assert_eq!(err(&wrong_length), "header records 98 bytes of words, but the image needs 48");
+
+        img.alpha = Some(AlphaPlane::Transform(Array2::from(vec![7; 12], 3, 4)));
+        let bytes = img.to_bytes();
+        assert_eq!(err(&bytes[..bytes.len() - 10]), "compressed image is truncated in its alpha plane, after 30 of 36 bytes");
+    }
+
+    #[test]
+    fn test_restart() {
+        // 3 block rows of 4 words, with a restart marker before every row
+        let mut img = CompressedImage::from_words((0..12).collect(), 8, 6, ColourModel::Rgb, 255);
+        img.arrangement = Arrangement::Restart(1);
+        let bytes = img.to_bytes();
+        let reread = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(reread.arrangement, Arrangement::Restart(1));
+        assert_eq!(reread.to_bytes(), bytes);
+        assert_eq!(CompressedImage::from_bytes_concealing(&bytes, DecodeLimits::default()).unwrap().1, vec![]);
+
+        // each segment is a 4 byte marker, 16 bytes of words and a 4 byte checksum
+        let header = bytes.len() - 76;
+        assert_eq!(&bytes[header - 36..header - 26], b"restart 1\n");
+        let mut damaged = bytes.clone();
+        damaged[header + 24 + 10] ^= 0x04;
+        assert_eq!(
+            CompressedImage::from_bytes(&damaged).err().unwrap(),
+            "checksum mismatch in segment 1, covering pixel rows 2 to 3"
+        );
+        let (concealed, rows) = CompressedImage::from_bytes_concealing(&damaged, DecodeLimits::default()).unwrap();
+        assert_eq!(rows, vec![2..4]);
+        assert_eq!(concealed.words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), vec![0, 1, 2, 3, 0, 1, 2, 3, 8, 9, 10, 11]);
+
+        // a lost byte shifts the segments after it, which are found again by their markers
+        let mut shortened = bytes.clone();
+        shortened.remove(header + 5);
+        let (concealed, rows) = CompressedImage::from_bytes_concealing(&shortened, DecodeLimits::default()).unwrap();
+        assert_eq!(rows, vec![0..2]);
+        assert_eq!(concealed.words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), vec![4, 5, 6, 7, 4, 5, 6, 7, 8, 9, 10, 11]);
+
+        // files without restart markers cannot be concealed
+        let mut plain = CompressedImage::from_words((0..12).collect(), 8, 6, ColourModel::Rgb, 255).to_bytes();
+        let len = plain.len();
+        plain[len - 10] ^= 0x04;
+        assert!(CompressedImage::from_bytes_concealing(&plain, DecodeLimits::default()).err().unwrap().starts_with("checksum mismatch"));
+
+        // an interval longer than the image is written and read as a single segment
+        img.arrangement = Arrangement::Restart(usize::MAX);
+        let bytes = img.to_bytes();
+        assert_eq!(CompressedImage::from_bytes(&bytes).unwrap().words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), (0..12).collect::<Vec<_>>());
+        let huge = format!("Compressed image format 3\n4 4\nrestart {}\n\nab", usize::MAX);
+        assert_eq!(CompressedImage::from_bytes(huge.as_bytes()).err().unwrap(), "compressed image is truncated at word 0 of 4");
+        assert!(CompressedImage::from_bytes_concealing(huge.as_bytes(), DecodeLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_tiles() {
+        // 3 block rows of 4 words in tiles of 3 x 2 blocks, the last column of tiles one block wide
+        let mut img = CompressedImage::from_words((0..12).collect(), 8, 6, ColourModel::Rgb, 255);
+        img.arrangement = Arrangement::Tiles(6, 4);
+        let bytes = img.to_bytes();
+        let reread = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(reread.arrangement, Arrangement::Tiles(6, 4));
+        assert_eq!(reread.words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), (0..12).collect::<Vec<_>>());
+        assert_eq!(reread.to_bytes(), bytes);
+
+        let header = bytes.len() - 52;
+        assert_eq!(&bytes[header - 53..header - 26], b"tiles 6 4\nindex 0 24 32 44\n");
+        assert_eq!(&bytes[header..header + 8], &[0, 0, 0, 0, 0, 0, 0, 1]);
+        let mut wrong_index = bytes.clone();
+        wrong_index[header - 34] = b'5';
+        assert_eq!(CompressedImage::from_bytes(&wrong_index).err().unwrap(), "tile 1 is recorded at byte 25, but starts at byte 24");
+        assert_eq!(
+            CompressedImage::from_bytes(b"Compressed image format 3\n2 2\nrestart 1\ntiles 2 2\nindex 0\n\n").err().unwrap(),
+            "restart markers and tiles cannot be used together"
+        );
+        assert_eq!(CompressedImage::from_bytes(b"Compressed image format 3\n2 2\ntiles 3 2\n\n").err().unwrap(), "invalid tile size: 3 2");
+    }
+
+    #[test]
+    fn test_progressive() {
+        // 12 words in a DC pass of 26 bytes and 5 planes of 5 bytes, followed by an alpha plane of 36 bytes
+        let words: Vec<u64> = (0..12).map(|n| RGB_LAYOUT.pack(n * 40, n as i64 - 6, 5 - n as i64, 3, n, 15 - n)).collect();
+        let mut img = CompressedImage::from_words(words.clone(), 8, 6, ColourModel::Rgb, 255);
+        img.arrangement = Arrangement::Progressive;
+        img.alpha = Some(AlphaPlane::Transform(Array2::from(vec![7; 12], 3, 4)));
+        let bytes = img.to_bytes();
+        let reread = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(reread.arrangement, Arrangement::Progressive);
+        assert_eq!(reread.words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), words);
+        assert_eq!(reread.to_bytes(), bytes);
+
+        let header = bytes.len() - 91;
+        assert_eq!(&bytes[header - 43..header - 26], b"scan progressive\n");
+        assert_eq!(CompressedImage::from_bytes(&bytes[..header + 30]).err().unwrap(), "compressed image is truncated in pass 2 of 6");
+
+        // a prefix is an image with as many passes as have arrived, and its alpha plane once all of it has
+        let prefix = |len: usize| CompressedImage::from_prefix(&bytes[..len], DecodeLimits::default()).unwrap();
+        let (dc, passes) = prefix(header + 26);
+        assert_eq!(passes, 1);
+        assert!(dc.alpha.is_none());
+        assert!(dc.words.iter_row_maj().all(|(_, _, &word)| RGB_LAYOUT.unpack(word).1 == 0));
+        assert_eq!(prefix(header + 51).1, 6);
+        assert!(prefix(header + 87).0.alpha.is_some());
+        assert_eq!(prefix(bytes.len()).1, 6);
+        let plain = CompressedImage::from_words(words, 8, 6, ColourModel::Rgb, 255).to_bytes();
+        assert_eq!(
+            CompressedImage::from_prefix(&plain, DecodeLimits::default()).err().unwrap(),
+            "only progressive images can be decoded from a prefix"
+        );
+    }
+
+    #[test]
+    fn test_pyramid() {
+        // 12 words whose a fields are held in a pyramid of 2 levels, followed by an alpha plane of 36 bytes
+        let words: Vec<u64> = (0..12).map(|n| RGB_LAYOUT.pack(200 + n / 4, n as i64 - 6, 5 - n as i64, 3, n, 15 - n)).collect();
+        let mut img = CompressedImage::from_words(words.clone(), 8, 6, ColourModel::Rgb, 255);
+        img.arrangement = Arrangement::Pyramid(2);
+        img.alpha = Some(AlphaPlane::Transform(Array2::from(vec![7; 12], 3, 4)));
+        let bytes = img.to_bytes();
+        let reread = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(reread.arrangement, Arrangement::Pyramid(2));
+        assert_eq!(reread.words.iter_row_maj().map(|(_, _, &w)| w).collect::<Vec<_>>(), words);
+        assert_eq!(reread.to_bytes(), bytes);
+
+        let header = bytes.windows(2).position(|pair| pair == b"\n\n").unwrap() + 2;
+        assert!(String::from_utf8_lossy(&bytes[..header]).contains("\npyramid 2\nlength "));
+        assert!(CompressedImage::from_bytes(&bytes[..header + 5]).err().unwrap().starts_with("compressed image is truncated in its pyramid"));
+
+        let err = |bytes: &[u8]| CompressedImage::from_bytes(bytes).err().unwrap();
+        assert_eq!(err(b"Compressed image format 3\n2 2\npyramid 1\n\n"), "pyramid images must record their length");
+        assert_eq!(err(b"Compressed image format 3\n2 2\npyramid 33\n\n"), "invalid pyramid levels: 33");
+        assert_eq!(
+            err(b"Compressed image format 3\n2 2\nscan progressive\npyramid 1\n\n"),
+            "a pyramid cannot be combined with restart markers, tiles or progressive passes"
+        );
+    }
+
+    #[test]
+    fn test_crafted_dimensions() {
+        // odd dimensions would allocate pixels that no word backs, and the largest ones overflow the sizes,
+        // which must be caught even without any limits
+        let err = |bytes: &[u8]| CompressedImage::from_bytes_with_limits(bytes, DecodeLimits::unlimited()).err().unwrap();
+        assert_eq!(err(b"Compressed image format 2\n1 4000000000\n"), "compressed image dimensions must be even, found 1 x 4000000000");
+        assert_eq!(
+            err(b"Compressed image format 3\n4294967294 4294967294\ndenominator 65535\n\n"),
+            "image dimensions are too large: 4294967294 x 4294967294"
+        );
+        assert_eq!(err(b"Compressed image format 3\n2 2\nalpha mask 18446744073709551615\n\n"), "alpha plane is too large");
+        assert_eq!(err(b"Compressed image format 2\n0 0\n\x00"), "expected 0 bytes of words, found 1");
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = CompressedImage::from_words(vec![0; 6], 6, 4, ColourModel::Rgb, 255).to_bytes();
+        assert!(CompressedImage::from_bytes(&bytes).is_ok());
+        let narrow = DecodeLimits { max_width: 4, ..DecodeLimits::default() };
+        assert_eq!(CompressedImage::from_bytes_with_limits(&bytes, narrow).err().unwrap(), "image width 6 exceeds the limit of 4");
+        let small = DecodeLimits { max_memory: 100, ..DecodeLimits::default() };
+        assert!(CompressedImage::from_bytes_with_limits(&bytes, small).is_err());
+
+        // the limits are checked before the payload, so a file claiming huge dimensions is rejected by them
+        let err = CompressedImage::from_bytes(b"Compressed image format 2\n60000 60000\n").err().unwrap();
+        assert_eq!(err, "image has 3600000000 pixels, which exceeds the limit of 268435456");
+    }
+}
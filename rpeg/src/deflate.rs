@@ -0,0 +1,482 @@
+use crate::checksum;
+
+/// The shortest length of each length code, 257 through 285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+/// The number of extra bits following each length code
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+/// The shortest distance of each distance code, 0 through 29
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+/// The number of extra bits following each distance code
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The farthest back a match may reach
+const WINDOW: usize = 32768;
+/// The longest match deflate can describe
+const MAX_MATCH: usize = 258;
+/// The number of earlier positions checked for a match before giving up
+const MAX_CHAIN: usize = 64;
+
+/// Returns `data` compressed as a raw deflate stream made of a single block of fixed Huffman codes,
+/// with repeated strings replaced by matches found through a hash chain
+///
+/// # Arguments:
+/// * `data`: the bytes to compress
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::default();
+    // a final block using the fixed codes
+    out.write(1, 1);
+    out.write(1, 2);
+
+    // `head` holds the latest position of every 3 byte hash, and `prev` links each position to the one before it
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if pos + 2 < data.len() {
+            let mut candidate = head[hash(data, pos)];
+            let limit = MAX_MATCH.min(data.len() - pos);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW {
+                    break;
+                }
+                let len = (0..limit).take_while(|&i| data[candidate + i] == data[pos + i]).count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, pos - candidate);
+                    if len == limit {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+            }
+        }
+
+        if best_len >= 3 {
+            write_match(&mut out, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut out, data[pos] as u16);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    write_literal(&mut out, 256);
+    out.finish()
+}
+
+/// Returns `data` compressed as a zlib stream: a two byte header, a deflate stream, and the Adler-32 of `data`
+///
+/// # Arguments:
+/// * `data`: the bytes to compress
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, and check bits making the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend_from_slice(&checksum::adler32(data).to_be_bytes());
+    out
+}
+
+/// Returns the bytes held in a raw deflate stream, made of stored, fixed Huffman or dynamic Huffman blocks.
+/// Returns an error if the stream is malformed, ends early, or holds more than `limit` bytes, which is checked as the
+/// bytes are produced so that a small stream cannot expand into a huge allocation.
+///
+/// # Arguments:
+/// * `data`: the deflate stream
+/// * `limit`: the largest number of bytes the stream may hold
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut input = BitReader { bytes: data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = input.read(1)? == 1;
+        match input.read(2)? {
+            0 => {
+                // stored blocks start on a byte boundary with their length and its complement
+                input.align();
+                let len = input.read(16)? as usize;
+                if input.read(16)? as usize != !len & 0xffff {
+                    return Err("stored block length does not match its complement".to_string());
+                }
+                let bytes = data.get(input.pos..input.pos + len).ok_or("stored block is truncated")?;
+                check_limit(out.len() + len, limit)?;
+                out.extend_from_slice(bytes);
+                input.pos += len;
+            }
+            1 => {
+                let mut lengths = [0_u8; 288 + 30];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                let (literals, distances) = lengths.split_at(288);
+                inflate_block(&mut input, &mut out, &Huffman::new(literals)?, &Huffman::new(distances)?, limit)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return Err("invalid deflate block type 3".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Returns the bytes held in a zlib stream, checking its header and the Adler-32 of the result
+///
+/// # Arguments:
+/// * `data`: the zlib stream
+/// * `limit`: the largest number of bytes the stream may hold
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 6 {
+        return Err("zlib stream is truncated".to_string());
+    }
+    if data[0] & 0x0f != 8 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".to_string());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("zlib streams with a preset dictionary are not supported".to_string());
+    }
+    let out = inflate(&data[2..], limit)?;
+    let expected = data.get(data.len() - 4..).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    if expected != Some(checksum::adler32(&out)) {
+        return Err("zlib checksum does not match the decompressed data".to_string());
+    }
+    Ok(out)
+}
+
+/// Helper function to decode the symbols of one Huffman block into `out`, until the end of block symbol
+///
+/// # Arguments:
+/// * `input`: the stream, positioned after the block header
+/// * `out`: the bytes decoded so far, which matches may copy from
+/// * `literals`: the code for literals, lengths and the end of the block
+/// * `distances`: the code for distances
+/// * `limit`: the largest number of bytes the stream may hold
+fn inflate_block(input: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman, limit: usize)
+    -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(input)? as usize;
+        match symbol {
+            0..=255 => {
+                check_limit(out.len() + 1, limit)?;
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let code = symbol - 257;
+                let len = LENGTH_BASE[code] as usize + input.read(LENGTH_EXTRA[code] as u32)? as usize;
+                let code = distances.decode(input)? as usize;
+                if code >= 30 {
+                    return Err(format!("invalid distance code {}", code));
+                }
+                let dist = DIST_BASE[code] as usize + input.read(DIST_EXTRA[code] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(format!("match reaches {} bytes back, before the start of the data", dist));
+                }
+                check_limit(out.len() + len, limit)?;
+                // matches may overlap the bytes they produce, so they are copied one at a time
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err(format!("invalid length code {}", symbol)),
+        }
+    }
+}
+
+/// Helper function that returns an error if `len` bytes of output would be more than `limit`
+///
+/// # Arguments:
+/// * `len`: the number of bytes the output would hold
+/// * `limit`: the largest number of bytes the stream may hold
+fn check_limit(len: usize, limit: usize) -> Result<(), String> {
+    if len > limit {
+        return Err(format!("deflate stream holds more than the {} bytes expected", limit));
+    }
+    Ok(())
+}
+
+/// Helper function to read the code lengths at the start of a dynamic Huffman block, which are themselves
+/// compressed with a Huffman code. Returns the literal and length code, and the distance code.
+///
+/// # Arguments:
+/// * `input`: the stream, positioned after the block type
+fn read_dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    // the order the code length code lengths are stored in
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let literal_count = input.read(5)? as usize + 257;
+    let distance_count = input.read(5)? as usize + 1;
+    let length_count = input.read(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("dynamic block has too many codes".to_string());
+    }
+
+    let mut length_lengths = [0_u8; 19];
+    for &idx in ORDER.iter().take(length_count) {
+        length_lengths[idx] = input.read(3)? as u8;
+    }
+    let length_code = Huffman::new(&length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match length_code.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("code lengths start with a repeat")?, 3 + input.read(2)?),
+            17 => (0, 3 + input.read(3)?),
+            _ => (0, 11 + input.read(7)?),
+        };
+        if lengths.len() + repeat as usize > literal_count + distance_count {
+            return Err("code lengths repeat past the end of the codes".to_string());
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err("dynamic block has no end of block code".to_string());
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+/// Helper struct holding a canonical Huffman code as the number of codes of each length
+/// and the symbols in order of their codes
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the canonical code given the length of every symbol's code, where 0 means the symbol is unused.
+    /// Returns an error if the lengths describe more codes than fit.
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0_u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1_i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("Huffman code lengths are oversubscribed".to_string());
+            }
+        }
+
+        let mut offsets = [0_u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Reads one symbol from `input`, a bit at a time
+    fn decode(&self, input: &mut BitReader) -> Result<u16, String> {
+        // `first` is the first code of the current length, and `index` the position of its symbol
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for len in 1..16 {
+            code |= input.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+/// Helper struct for reading a stream of bits, starting from the least significant bit of each byte
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    /// Reads `len` bits, least significant first
+    fn read(&mut self, len: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..len {
+            let byte = *self.bytes.get(self.pos).ok_or("deflate stream ends early")?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                (self.pos, self.bit) = (self.pos + 1, 0);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skips to the start of the next byte
+    fn align(&mut self) {
+        if self.bit != 0 {
+            (self.pos, self.bit) = (self.pos + 1, 0);
+        }
+    }
+}
+
+/// Helper function that returns a 15 bit hash of the 3 bytes starting at `pos`
+///
+/// # Arguments:
+/// * `data`: the bytes being compressed
+/// * `pos`: a position at least 3 bytes before the end of `data`
+fn hash(data: &[u8], pos: usize) -> usize {
+    ((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize) & ((1 << 15) - 1)
+}
+
+/// Helper function that records `pos` as the latest position with its hash, linking it to the one before
+///
+/// # Arguments:
+/// * `data`: the bytes being compressed
+/// * `pos`: the position to record
+/// * `head`: the latest position of every hash
+/// * `prev`: the previous position with the same hash as each position
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + 2 < data.len() {
+        let h = hash(data, pos);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// Helper function to write the fixed Huffman code of a literal byte or length code
+///
+/// # Arguments:
+/// * `out`: the stream to write to
+/// * `symbol`: a symbol between 0 and 287
+fn write_literal(out: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    out.write_code(code as u32, len);
+}
+
+/// Helper function to write a match of `len` bytes starting `dist` bytes back
+///
+/// # Arguments:
+/// * `out`: the stream to write to
+/// * `len`: the length of the match, between 3 and 258
+/// * `dist`: the distance back to the match, between 1 and 32768
+fn write_match(out: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal(out, 257 + code as u16);
+    out.write((len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+
+    // distance codes are all 5 bits long
+    let code = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    out.write_code(code as u32, 5);
+    out.write((dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+}
+
+/// Helper struct for writing a stream of bits, filling each byte from its least significant bit
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `len` bits of `value`, least significant first
+    fn write(&mut self, value: u32, len: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code of `len` bits, which are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    /// Returns the bytes written, padding the last one with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deflate::{deflate, inflate, zlib_compress, zlib_decompress};
+
+    #[test]
+    fn test_fixed_codes() {
+        // these match the output of zlib with its fixed Huffman strategy
+        assert_eq!(deflate(b""), vec![0x03, 0x00]);
+        assert_eq!(deflate(b"a"), vec![0x4b, 0x04, 0x00]);
+        // a literal followed by a match of length 9 at distance 1
+        assert_eq!(deflate(b"aaaaaaaaaa"), vec![0x4b, 0x84, 0x03, 0x00]);
+    }
+
+    #[test]
+    fn test_zlib_stream() {
+        let out = zlib_compress(b"Wikipedia");
+        assert_eq!((out[0] as u16 * 256 + out[1] as u16) % 31, 0);
+        assert_eq!(&out[out.len() - 4..], &[0x11, 0xe6, 0x03, 0x98]);
+    }
+
+    #[test]
+    fn test_inflate_blocks() {
+        // a stored block, and a fixed block made by zlib
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'], 5).unwrap(), b"hello");
+        assert_eq!(inflate(&[0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00], 17).unwrap(), b"hello hello hello");
+        assert!(inflate(&[0x07], 5).is_err());
+        assert!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h'], 5).is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        // stored, literal and match bytes are all checked against the limit as they are produced
+        let expected = "deflate stream holds more than the 4 bytes expected";
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'], 4).unwrap_err(), expected);
+        assert_eq!(inflate(&deflate(b"hello"), 4).unwrap_err(), expected);
+        assert_eq!(inflate(&deflate(b"aaaaaaaaaa"), 4).unwrap_err(), expected);
+        // a megabyte of zeros compresses to a few kilobytes
+        let zeros = zlib_compress(&vec![0; 1 << 20]);
+        assert!(zeros.len() < 20_000);
+        assert_eq!(zlib_decompress(&zeros, 1000).unwrap_err(), "deflate stream holds more than the 1000 bytes expected");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..50_000_u32).map(|n| ((n * n) >> 7) as u8 % 13).collect();
+        let compressed = zlib_compress(&data);
+        assert!(compressed.len() < data.len() / 2);
+        assert_eq!(zlib_decompress(&compressed, data.len()).unwrap(), data);
+
+        let mut corrupted = compressed.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert!(zlib_decompress(&corrupted, data.len()).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use csc411_image::Rgb;
+use bitpack::bitpack;
+use crate::quantize::WordLayout;
+
+/// The number of fractional bits in every fixed point value
+pub const FRACTION_BITS: u32 = 24;
+
+/// 1.0 in fixed point
+pub const ONE: i64 = 1 << FRACTION_BITS;
+
+/// The chroma values of the 4 bit quantization in `csc411_arith`, in fixed point
+const CHROMA_TABLE: [i64; 16] = [
+    -5872026, -3355443, -2516582, -1677722, -1291846, -922747, -553648, -184549,
+    184549, 553648, 922747, 1291846, 1677722, 2516582, 3355443, 5872026,
+];
+
+/// Helper function that returns the product of two fixed point values scaled back down to fixed point,
+/// rounded to the nearest step
+///
+/// # Arguments:
+/// * `product`: the product of two fixed point values
+fn round_shift(product: i64) -> i64 {
+    (product + ONE / 2) >> FRACTION_BITS
+}
+
+/// Returns `sample` as a fixed point value on a scale of 0 to `ONE`
+///
+/// # Arguments:
+/// * `sample`: the sample value
+/// * `denominator`: the largest sample value of the image
+pub fn sample_to_fixed(sample: u16, denominator: u16) -> i64 {
+    ((sample as i64) * ONE + denominator as i64 / 2) / denominator as i64
+}
+
+/// Returns the sample value closest to the fixed point `value`, which is clamped to a scale of 0 to `ONE`
+///
+/// # Arguments:
+/// * `value`: the fixed point value
+/// * `denominator`: the largest sample value of the image
+pub fn fixed_to_sample(value: i64, denominator: u16) -> u16 {
+    round_shift(value.clamp(0, ONE) * denominator as i64) as u16
+}
+
+/// Returns the luma and chroma of a pixel, stored as (y, pb, pr), the fixed point form of
+/// `pixel_conversion::get_compression_tuple`
+///
+/// # Arguments:
+/// * `rgb`: the red, green and blue of the pixel in fixed point, stored as (red, green, blue)
+pub fn get_compression_tuple(rgb: (i64, i64, i64)) -> (i64, i64, i64) {
+    let (r, g, b) = rgb;
+    (
+        round_shift(5016388 * r + 9848225 * g + 1912603 * b),
+        round_shift(-2830920 * r - 5557688 * g + 8388608 * b),
+        round_shift(8388608 * r - 7024419 * g - 1364189 * b)
+    )
+}
+
+/// Returns the red, green and blue of a pixel, stored as (red, green, blue), the fixed point form of
+/// `pixel_conversion::get_decompression_tuple`
+///
+/// # Arguments:
+/// * `comp_vid`: the luma and chroma of the pixel in fixed point, stored as (y, pb, pr)
+pub fn get_decompression_tuple(comp_vid: (i64, i64, i64)) -> (i64, i64, i64) {
+    let (y, pb, pr) = comp_vid;
+    (
+        y + round_shift(23521657 * pr),
+        y - round_shift(5773644 * pb + 11981214 * pr),
+        y + round_shift(29729227 * pb)
+    )
+}
+
+/// Returns the fields `a`, `b`, `c` and `d` of a word holding the 2 x 2 block of luma `y`, the fixed point form of
+/// `quantize::get_coefficients` followed by scaling each coefficient to an integer
+///
+/// # Arguments:
+/// * `y`: the top left, top right, bottom left and bottom right luma of the block, in fixed point
+/// * `layout`: the layout of the word
+pub fn luma_to_fields(y: [i64; 4], layout: WordLayout) -> (u64, i64, i64, i64) {
+    let [y1, y2, y3, y4] = y;
+    let a = (y4 + y3 + y2 + y1) / 4;
+    let scaled = |coefficient: i64| (coefficient * 50 / 4 / ONE).clamp(-15, 15);
+    (
+        round_shift(a.clamp(0, ONE) * bitpack::maxu(layout.a_bits) as i64) as u64,
+        scaled(y4 + y3 - y2 - y1),
+        scaled(y4 - y3 + y2 - y1),
+        scaled(y4 - y3 - y2 + y1)
+    )
+}
+
+/// Returns the top left, top right, bottom left and bottom right luma of the block described by the fields
+/// `a`, `b`, `c` and `d` of a word, in fixed point. The inverse of `luma_to_fields`.
+///
+/// # Arguments:
+/// * `fields`: the fields of the word, stored as (a, b, c, d)
+/// * `layout`: the layout of the word
+pub fn fields_to_luma(fields: (u64, i64, i64, i64), layout: WordLayout) -> [i64; 4] {
+    let (a, b, c, d) = fields;
+    let steps = bitpack::maxu(layout.a_bits) as i64;
+    let a = (a as i64 * ONE + steps / 2) / steps;
+    let (b, c, d) = (b * ONE / 50, c * ONE / 50, d * ONE / 50);
+    [a - b - c + d, a - b + c - d, a + b - c - d, a + b + c + d]
+}
+
+/// Returns the indices of the chroma `pb` and `pr`, stored as (pbidx, pridx), the fixed point form of
+/// `WordLayout::chroma_to_indices`
+///
+/// # Arguments:
+/// * `layout`: the layout of the word
+/// * `pb`, `pr`: the average chroma of the block, in fixed point
+pub fn chroma_to_indices(layout: WordLayout, pb: i64, pr: i64) -> (u64, u64) {
+    let index = |chroma: i64| match layout.chroma_bits {
+        0 => 0,
+        // the first of the closest values, as `csc411_arith` picks
+        4 => (0..CHROMA_TABLE.len()).min_by_key(|&i| (chroma - CHROMA_TABLE[i]).abs()).unwrap() as u64,
+        bits => round_shift((chroma.clamp(-ONE / 2, ONE / 2) + ONE / 2) * bitpack::maxu(bits) as i64) as u64,
+    };
+    (index(pb), index(pr))
+}
+
+/// Returns the chroma held by the indices `pbidx` and `pridx` in fixed point, stored as (pb, pr), the fixed point
+/// form of `WordLayout::indices_to_chroma`
+///
+/// # Arguments:
+/// * `layout`: the layout of the word
+/// * `pbidx`, `pridx`: the chroma indices of the word
+pub fn indices_to_chroma(layout: WordLayout, pbidx: u64, pridx: u64) -> (i64, i64) {
+    let chroma = |idx: u64| match layout.chroma_bits {
+        0 => 0,
+        4 => CHROMA_TABLE[idx as usize],
+        bits => {
+            let steps = bitpack::maxu(bits) as i64;
+            (idx as i64 * ONE + steps / 2) / steps - ONE / 2
+        }
+    };
+    (chroma(pbidx), chroma(pridx))
+}
+
+/// Returns the word describing a 2 x 2 block of pixels using only integer arithmetic, so that the word is
+/// the same on every platform. Matches `codec::encode_block_float` to within one step in each field.
+///
+/// # Arguments:
+/// * `pixels`: the top left, top right, bottom left and bottom right pixels of the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout to pack the word with
+pub fn encode_block(pixels: [&Rgb; 4], denominator: u16, layout: WordLayout) -> u64 {
+    let (mut pb_sum, mut pr_sum) = (0, 0);
+    let luma = pixels.map(|pixel| {
+        let fixed = |sample: u16| sample_to_fixed(sample, denominator);
+        let (y, pb, pr) = get_compression_tuple((fixed(pixel.red), fixed(pixel.green), fixed(pixel.blue)));
+        pb_sum += pb;
+        pr_sum += pr;
+        y
+    });
+    let (a, b, c, d) = luma_to_fields(luma, layout);
+    let (pb_idx, pr_idx) = chroma_to_indices(layout, pb_sum / 4, pr_sum / 4);
+    layout.pack(a, b, c, d, pb_idx, pr_idx)
+}
+
+/// Returns the top left, top right, bottom left and bottom right pixels of the block described by `word` using only
+/// integer arithmetic, so that the pixels are the same on every platform
+///
+/// # Arguments:
+/// * `word`: the word describing the block
+/// * `denominator`: the denominator of the image
+/// * `layout`: the layout the word was packed with
+pub fn decode_block(word: u64, denominator: u16, layout: WordLayout) -> [Rgb; 4] {
+    let (a, b, c, d, pb, pr) = layout.unpack(word);
+    let (pb, pr) = indices_to_chroma(layout, pb, pr);
+    fields_to_luma((a, b, c, d), layout).map(|y| {
+        let (r, g, b) = get_decompression_tuple((y, pb, pr));
+        Rgb { red: fixed_to_sample(r, denominator), green: fixed_to_sample(g, denominator), blue: fixed_to_sample(b, denominator) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use csc411_image::Rgb;
+    use crate::codec::{decode_block_float, encode_block_float};
+    use crate::fixed_point::{decode_block, encode_block, get_compression_tuple, get_decompression_tuple, ONE};
+    use crate::quantize::{WordLayout, RGB_LAYOUT, GRAY_LAYOUT, WIDE_RGB_LAYOUT, WIDE_GRAY_LAYOUT};
+
+    const LAYOUTS: [(WordLayout, u16); 4] = [(RGB_LAYOUT, 255), (GRAY_LAYOUT, 255), (WIDE_RGB_LAYOUT, 65535), (WIDE_GRAY_LAYOUT, 65535)];
+
+    /// Returns `count` pseudo random numbers below `limit`, the same on every run
+    fn numbers(count: usize, limit: u64) -> Vec<u64> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..count).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % limit
+        }).collect()
+    }
+
+    #[test]
+    fn test_conversion_tuples() {
+        assert_eq!(get_compression_tuple((ONE, ONE, ONE)), (ONE, 0, 0));
+        assert_eq!(get_compression_tuple((ONE, 0, 0)), (5016388, -2830920, 8388608));
+        assert_eq!(get_decompression_tuple((ONE, 0, 0)), (ONE, ONE, ONE));
+        let (r, g, b) = get_decompression_tuple(get_compression_tuple((ONE, 0, 0)));
+        assert!((r - ONE).abs() <= 2 && g.abs() <= 2 && b.abs() <= 2);
+    }
+
+    #[test]
+    fn test_encode_within_one_step_of_float() {
+        for (layout, denom) in LAYOUTS {
+            let gray = layout.chroma_bits == 0;
+            for sample in numbers(4000 * 3, denom as u64 + 1).chunks_exact(12) {
+                let pixels: Vec<Rgb> = sample.chunks_exact(3).map(|p| {
+                    let (r, g, b) = if gray { (p[0], p[0], p[0]) } else { (p[0], p[1], p[2]) };
+                    Rgb { red: r as u16, green: g as u16, blue: b as u16 }
+                }).collect();
+                let pixels = [&pixels[0], &pixels[1], &pixels[2], &pixels[3]];
+                let fixed = layout.unpack(encode_block(pixels, denom, layout));
+                let float = layout.unpack(encode_block_float(pixels, denom as f64, layout));
+                assert!(fixed.0.abs_diff(float.0) <= 1, "{:?} and {:?}", fixed, float);
+                for (x, y) in [(fixed.1, float.1), (fixed.2, float.2), (fixed.3, float.3)] {
+                    assert!(x.abs_diff(y) <= 1, "{:?} and {:?}", fixed, float);
+                }
+                assert!(fixed.4.abs_diff(float.4) <= 1 && fixed.5.abs_diff(float.5) <= 1, "{:?} and {:?}", fixed, float);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_within_one_of_float() {
+        for (layout, denom) in LAYOUTS {
+            // the largest difference is one sample value, and a sixty-fourth of a percent for 16 bit samples
+            let tolerance = (denom as u32 / 6400).max(1);
+            let bits = layout.a_bits + 3 * layout.bcd_bits + 2 * layout.chroma_bits;
+            for word in numbers(20_000, 1 << bits) {
+                let fixed = decode_block(word, denom, layout);
+                let float = decode_block_float(word, denom as f64, layout);
+                for (x, y) in fixed.iter().zip(float.iter()) {
+                    for (p, q) in [(x.red, y.red), (x.green, y.green), (x.blue, y.blue)] {
+                        assert!((p as u32).abs_diff(q as u32) <= tolerance, "word {:x}: {} and {}", word, p, q);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_exact_words() {
+        // these words only use integer arithmetic, so they are the same on every platform
+        let pixels = [
+            Rgb { red: 200, green: 30, blue: 90 }, Rgb { red: 10, green: 250, blue: 0 },
+            Rgb { red: 128, green: 128, blue: 128 }, Rgb { red: 255, green: 255, blue: 255 },
+        ];
+        let pixels = [&pixels[0], &pixels[1], &pixels[2], &pixels[3]];
+        assert_eq!(encode_block(pixels, 255, RGB_LAYOUT), 0x9b9d_2347);
+        assert_eq!(encode_block(pixels, 255, WIDE_RGB_LAYOUT), 0x4dda_9d23_6b7b);
+    }
+}
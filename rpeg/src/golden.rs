@@ -0,0 +1,95 @@
+//! Golden-file regression tests for the rpeg bitstream. Every generated image in `cases` is compressed and the bytes
+//! are compared against a checked-in `.rpeg` file, and that file is decompressed and compared against a checked-in
+//! Netpbm file, so that any change to the bytes `compress` emits or to the pixels `decompress` produces fails review.
+//!
+//! When a change to the format is intended, run the tests with `RPEG_BLESS=1` to rewrite the expected files,
+//! and check the new files in with the change:
+//!
+//! `RPEG_BLESS=1 cargo test golden`
+//!
+//! The `fixed-point` feature produces different bytes, so it keeps its own set of expected files.
+
+use std::path::PathBuf;
+use crate::pnm::{self, Format, PnmImage};
+use crate::{codec, testimages};
+use crate::container::CompressedImage;
+
+/// The environment variable that switches the tests from comparing against the expected files to rewriting them
+const BLESS: &str = "RPEG_BLESS";
+
+/// Returns the directory holding the expected files, found from the path of this source file so that it works
+/// wherever the crate is built
+fn golden_dir() -> PathBuf {
+    let set = if cfg!(feature = "fixed-point") { "golden-fixed-point" } else { "golden" };
+    PathBuf::from(file!()).parent().unwrap().join("../fixtures").join(set)
+}
+
+/// Returns the images pinned by the tests, with a name for each
+fn cases() -> Vec<(String, PnmImage)> {
+    let mut cases: Vec<(String, PnmImage)> = testimages::all(32, 24).into_iter()
+        .map(|(name, img)| (name.to_string(), img))
+        .collect();
+    // odd dimensions are trimmed, and alpha is stored as a transform or as a mask
+    cases.push(("gradient_odd".to_string(), testimages::gradient(33, 25)));
+    let ramp: Vec<u16> = (0..32 * 24).map(|n| (n % 32 * 8) as u16).collect();
+    cases.push(("alpha_transform".to_string(), testimages::gradient(32, 24).with_alpha(&ramp)));
+    let mask: Vec<u16> = (0..32 * 24).map(|n| if n % 32 < 16 { 0 } else { 255 }).collect();
+    cases.push(("alpha_mask".to_string(), testimages::colour_bars(32, 24).with_alpha(&mask)));
+    cases
+}
+
+/// Returns the extension of Netpbm files in `format`
+///
+/// # Arguments:
+/// * `format`: the format of the file
+fn extension(format: Format) -> &'static str {
+    match format {
+        Format::PlainPbm | Format::RawPbm => "pbm",
+        Format::PlainPgm | Format::RawPgm => "pgm",
+        Format::PlainPpm | Format::RawPpm => "ppm",
+        Format::Pam => "pam",
+    }
+}
+
+/// Compares `actual` against the expected file `name`, or rewrites the file in bless mode.
+/// Returns a description of the problem if they differ.
+///
+/// # Arguments:
+/// * `name`: the file name of the expected file in the golden directory
+/// * `actual`: the bytes produced now
+fn check(name: &str, actual: &[u8]) -> Option<String> {
+    let path = golden_dir().join(name);
+    if std::env::var_os(BLESS).is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return None;
+    }
+    match std::fs::read(&path) {
+        Err(e) => Some(format!("{}: {}", path.display(), e)),
+        Ok(expected) if expected == actual => None,
+        Ok(expected) => {
+            let first = expected.iter().zip(actual).position(|(a, b)| a != b).unwrap_or(expected.len().min(actual.len()));
+            Some(format!("{}: {} bytes expected and {} produced, first differing at byte {}", name, expected.len(), actual.len(), first))
+        }
+    }
+}
+
+#[test]
+fn test_golden_files() {
+    let mut failures = Vec::new();
+    for (name, img) in cases() {
+        let compressed = codec::compress_image(&img).to_bytes();
+        failures.extend(check(&format!("{}.rpeg", name), &compressed));
+
+        // decode the expected file rather than the fresh output, so that the decoder is pinned on its own
+        let expected = std::fs::read(golden_dir().join(format!("{}.rpeg", name))).unwrap_or(compressed);
+        let decoded = codec::decompress_image(&CompressedImage::from_bytes(&expected).unwrap());
+        let format = Format::for_image(&decoded);
+        failures.extend(check(&format!("{}.{}", name, extension(format)), &pnm::encode(&decoded, format).unwrap()));
+    }
+    assert!(
+        failures.is_empty(),
+        "output differs from the golden files:\n{}\nif the change is intended, rerun with {}=1 and check in the new files",
+        failures.join("\n"), BLESS
+    );
+}
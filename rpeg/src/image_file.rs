@@ -0,0 +1,141 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use crate::{bmp, png, pnm};
+use crate::pnm::PnmImage;
+
+/// The file formats that uncompressed images can be read from and written to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileFormat {
+    /// any of the Netpbm formats, `P1` through `P7`
+    Pnm,
+    /// Windows bitmaps
+    Bmp,
+    /// Portable Network Graphics
+    Png,
+}
+
+impl FileFormat {
+    /// Returns the format of a file judging by the magic bytes at its start, or None if they are not recognized
+    ///
+    /// # Arguments:
+    /// * `bytes`: the contents of the file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes.get(..2)? {
+            [b'P', b'1'..=b'7'] => Some(FileFormat::Pnm),
+            b"BM" => Some(FileFormat::Bmp),
+            _ if bytes.starts_with(&png::SIGNATURE) => Some(FileFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// Returns the format called `name` on the command line, or None if there is no such format
+    ///
+    /// # Arguments:
+    /// * `name`: the name of the format, such as `pnm`, `bmp` or `png`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pnm" => Some(FileFormat::Pnm),
+            "bmp" => Some(FileFormat::Bmp),
+            "png" => Some(FileFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the contents of the file named `filename`, or of standard input
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+pub fn read_bytes(filename: Option<&str>) -> Result<Vec<u8>, String> {
+    match filename {
+        Some(name) => std::fs::read(name).map_err(|e| format!("{}: {}", name, e)),
+        None => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf).map_err(|e| e.to_string())?;
+            Ok(buf)
+        }
+    }
+}
+
+/// Returns a buffered reader over the file named `filename`, or standard input, for reading a file
+/// without loading all of it into memory
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+pub fn open(filename: Option<&str>) -> Result<Box<dyn BufRead>, String> {
+    match filename {
+        Some(name) => {
+            let file = std::fs::File::open(name).map_err(|e| format!("{}: {}", name, e))?;
+            Ok(Box::new(BufReader::new(file)))
+        }
+        None => Ok(Box::new(BufReader::new(std::io::stdin()))),
+    }
+}
+
+/// Reads an image in any supported format from the file named `filename`, or standard input,
+/// picking the format from the magic bytes at the start of the file
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+pub fn read(filename: Option<&str>) -> Result<PnmImage, String> {
+    parse(&read_bytes(filename)?)
+}
+
+/// Parses an image in any supported format out of the contents of a file
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+pub fn parse(bytes: &[u8]) -> Result<PnmImage, String> {
+    match FileFormat::detect(bytes) {
+        Some(FileFormat::Pnm) => pnm::parse(bytes),
+        Some(FileFormat::Bmp) => bmp::parse(bytes),
+        Some(FileFormat::Png) => png::parse(bytes),
+        None => Err("unrecognized image format: expected a Netpbm, BMP or PNG file".to_string()),
+    }
+}
+
+/// Writes `img` in the format `format` to the file named `filename`, or standard output. Netpbm images
+/// are written in the binary format best suited to the image.
+///
+/// # Arguments:
+/// * `img`: the image to write
+/// * `format`: the file format to write it in
+/// * `filename`: the file to write to, or None for standard output
+pub fn write(img: &PnmImage, format: FileFormat, filename: Option<&str>) -> Result<(), String> {
+    let bytes = match format {
+        FileFormat::Pnm => pnm::encode(img, pnm::Format::for_image(img))?,
+        FileFormat::Bmp => bmp::encode(img)?,
+        FileFormat::Png => png::encode(img)?,
+    };
+    match filename {
+        Some(name) => std::fs::write(name, bytes).map_err(|e| format!("{}: {}", name, e)),
+        None => std::io::stdout().lock().write_all(&bytes).map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image_file::{parse, FileFormat};
+    use crate::{bmp, png, pnm};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(FileFormat::detect(b"P6\n1 1\n255\n"), Some(FileFormat::Pnm));
+        assert_eq!(FileFormat::detect(b"P7\n"), Some(FileFormat::Pnm));
+        assert_eq!(FileFormat::detect(b"BM"), Some(FileFormat::Bmp));
+        assert_eq!(FileFormat::detect(b"\x89PNG\r\n\x1a\n"), Some(FileFormat::Png));
+        assert_eq!(FileFormat::detect(b"P8"), None);
+        assert_eq!(FileFormat::detect(b"B"), None);
+    }
+
+    #[test]
+    fn test_parse_any() {
+        let pgm = pnm::parse(b"P2\n2 2\n255\n0 64 128 255\n").unwrap();
+        assert_eq!(parse(&bmp::encode(&pgm).unwrap()).unwrap(), pgm);
+        assert_eq!(parse(&pnm::encode(&pgm, pnm::Format::RawPgm).unwrap()).unwrap(), pgm);
+        assert_eq!(parse(&png::encode(&pgm).unwrap()).unwrap(), pgm);
+        assert!(parse(b"GIF89a").is_err());
+    }
+}
@@ -1,4 +1,26 @@
+pub mod alpha;
+pub mod bits;
+pub mod bmp;
+pub mod checksum;
 pub mod codec;
+pub mod container;
+pub mod deflate;
+pub mod fixed_point;
+#[cfg(test)]
+mod golden;
+pub mod image_file;
+pub mod limits;
+pub mod mosaic;
 pub mod pixel_conversion;
 pub mod pixel_block;
-pub mod quantize;
\ No newline at end of file
+pub mod png;
+pub mod pnm;
+pub mod progressive;
+pub mod pyramid;
+pub mod quantize;
+pub mod restart;
+pub mod sequence;
+pub mod simd;
+pub mod stream;
+pub mod testimages;
+pub mod tiles;
@@ -0,0 +1,135 @@
+use crate::container::{AlphaField, ColourModel, Header};
+
+/// Limits on the rpeg files the decoder accepts. They are checked against the header before anything is allocated,
+/// so that a file claiming enormous dimensions is rejected with an error instead of exhausting memory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DecodeLimits {
+    /// the widest image accepted, in pixels
+    pub max_width: u32,
+    /// the tallest image accepted, in pixels
+    pub max_height: u32,
+    /// the most pixels accepted, however they are arranged
+    pub max_pixels: u64,
+    /// the most bytes the decoder may allocate for one image
+    pub max_memory: u64,
+}
+
+impl Default for DecodeLimits {
+    /// Returns limits that admit any image a camera or scanner produces, up to 65535 pixels on a side and
+    /// 256 megapixels, decoded in at most 4 GiB
+    fn default() -> Self {
+        DecodeLimits { max_width: 65535, max_height: 65535, max_pixels: 1 << 28, max_memory: 1 << 32 }
+    }
+}
+
+impl DecodeLimits {
+    /// Returns limits that accept every file, for callers that trust their input
+    pub fn unlimited() -> Self {
+        DecodeLimits { max_width: u32::MAX, max_height: u32::MAX, max_pixels: u64::MAX, max_memory: u64::MAX }
+    }
+
+    /// Returns an error describing the first limit that an image of `width` x `height` pixels exceeds
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the limits
+    /// * `width`, `height`: the dimensions of the image, as read from its header
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), String> {
+        if width > self.max_width {
+            return Err(format!("image width {} exceeds the limit of {}", width, self.max_width));
+        }
+        if height > self.max_height {
+            return Err(format!("image height {} exceeds the limit of {}", height, self.max_height));
+        }
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(format!("image has {} pixels, which exceeds the limit of {}", pixels, self.max_pixels));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if decoding needs more than `max_memory` bytes
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the limits
+    /// * `bytes`: the number of bytes the decoder would allocate
+    pub fn check_memory(&self, bytes: u64) -> Result<(), String> {
+        if bytes > self.max_memory {
+            return Err(format!("decoding needs {} bytes of memory, which exceeds the limit of {}", bytes, self.max_memory));
+        }
+        Ok(())
+    }
+
+    /// Returns an error describing the first limit that the image described by `header` exceeds when it is
+    /// decoded all at once
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the limits
+    /// * `header`: the header of the rpeg file
+    pub fn check(&self, header: &Header) -> Result<(), String> {
+        self.check_dimensions(header.width, header.height)?;
+        self.check_memory(decode_memory(header))
+    }
+}
+
+/// Returns the number of bytes `codec::decompress_image` allocates for the image described by `header`:
+/// the grid of words, the decoded pixels, the samples of the output image, and the alpha plane if there is one.
+/// The result saturates rather than overflowing, so it is safe for any header.
+///
+/// # Arguments:
+/// * `header`: the header of the rpeg file
+pub fn decode_memory(header: &Header) -> u64 {
+    let pixels = header.width as u64 * header.height as u64;
+    let blocks = pixels / 4;
+    let channels = match header.model {
+        ColourModel::Rgb => 3,
+        ColourModel::Gray => 1,
+    } + header.alpha.is_some() as u64;
+    let alpha = match header.alpha {
+        None => 0,
+        // the mask is expanded to one bool per pixel, and both forms become one alpha value per pixel
+        Some(AlphaField::Mask(_)) => pixels.saturating_mul(size_of::<bool>() as u64 + 2),
+        Some(AlphaField::Transform) => {
+            blocks.saturating_mul(size_of::<u64>() as u64).saturating_add(pixels.saturating_mul(2))
+        }
+    };
+    blocks.saturating_mul(size_of::<u64>() as u64)
+        .saturating_add(pixels.saturating_mul(size_of::<csc411_image::Rgb>() as u64))
+        .saturating_add(pixels.saturating_mul(channels * 2))
+        .saturating_add(alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::container::{AlphaField, ColourModel, Header};
+    use crate::limits::{decode_memory, DecodeLimits};
+
+    #[test]
+    fn test_dimensions() {
+        let limits = DecodeLimits { max_width: 100, max_height: 50, max_pixels: 2000, max_memory: u64::MAX };
+        assert!(limits.check_dimensions(100, 20).is_ok());
+        assert_eq!(limits.check_dimensions(101, 2).unwrap_err(), "image width 101 exceeds the limit of 100");
+        assert_eq!(limits.check_dimensions(2, 51).unwrap_err(), "image height 51 exceeds the limit of 50");
+        assert_eq!(limits.check_dimensions(100, 21).unwrap_err(), "image has 2100 pixels, which exceeds the limit of 2000");
+        assert!(DecodeLimits::unlimited().check_dimensions(u32::MAX, u32::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_memory() {
+        let header = Header {
+            width: 4, height: 2, model: ColourModel::Rgb, denominator: 255, alpha: None, restart: None, tiles: None,
+            progressive: false, pyramid: None, length: None, checksum: false
+        };
+        // 2 words, 8 pixels, and 24 samples
+        assert_eq!(decode_memory(&header), 2 * 8 + 8 * 6 + 24 * 2);
+        let gray = Header { model: ColourModel::Gray, alpha: Some(AlphaField::Mask(3)), ..header.clone() };
+        assert_eq!(decode_memory(&gray), 2 * 8 + 8 * 6 + 16 * 2 + 8 * 3);
+
+        let limits = DecodeLimits { max_memory: 100, ..DecodeLimits::default() };
+        assert!(limits.check(&header).is_err());
+        assert!(DecodeLimits::default().check(&header).is_ok());
+        let huge = Header { width: u32::MAX, height: u32::MAX, ..header };
+        assert_eq!(decode_memory(&huge), u64::MAX);
+        assert!(DecodeLimits { max_width: u32::MAX, max_height: u32::MAX, max_pixels: u64::MAX, ..DecodeLimits::default() }
+            .check(&huge).is_err());
+    }
+}
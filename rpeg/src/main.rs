@@ -1,16 +1,317 @@
 use std::env;
-use rpeg::codec::{compress, decompress};
+use std::io::BufWriter;
+use rpeg::codec::{compress, decompress, decompress_prefix, decompress_region, extract, pack};
+use rpeg::container::{Arrangement, CompressedImage};
+use rpeg::image_file::{self, FileFormat};
+use rpeg::limits::DecodeLimits;
+use rpeg::{mosaic, pyramid, stream};
+
+const USAGE: &str = "Usage: rpeg -d [--format pnm|bmp|png] [--stream | --threads n] [--conceal | --region x,y,w,h | --partial] [--max-width n] [--max-height n] [--max-pixels n] [--max-memory bytes[K|M|G]] [filename]\nrpeg -c [--stream | --threads n] [--restart rows | --tile size | --progressive | --pyramid levels] [filename]\nrpeg mosaic (-h | -v | -g columns) filename...\nrpeg pack [--threads n] [--interval ms] directory\nrpeg extract frame [--format pnm|bmp|png] [--threads n] [--max-width n] [--max-height n] [--max-pixels n] [--max-memory bytes[K|M|G]] [filename]";
 
 fn main() {
   let args: Vec<String> = env::args().collect();
-  let argnum = args.len();
-  assert!(argnum == 2 || argnum == 3);
-  let filename = args.iter().nth(2).unwrap();
-  match args[1].as_str() {
-    "-c" => compress(Some(filename)),
-    "-d" => decompress(Some(filename)),
+  match args.get(1).map(|s| s.as_str()) {
+    Some("-c") => run_compress(&args[2..]),
+    Some("-d") => run_decompress(&args[2..]),
+    Some("mosaic") => run_mosaic(&args[2..]),
+    Some("pack") => run_pack(&args[2..]),
+    Some("extract") => run_extract(&args[2..]),
     _ => {
-      eprintln!("Usage: rpeg -d [filename]\nrpeg -c [filename]")
+      eprintln!("{}", USAGE)
+    }
+  }
+}
+
+/// The flags and file name that follow `-c` or `-d`
+struct Options {
+  format: Option<FileFormat>,
+  stream: bool,
+  threads: usize,
+  limits: Option<DecodeLimits>,
+  restart: Option<usize>,
+  conceal: bool,
+  tile: Option<u32>,
+  region: Option<(u32, u32, u32, u32)>,
+  progressive: bool,
+  partial: bool,
+  pyramid: Option<u32>,
+  interval: Option<u64>,
+  filename: Option<String>,
+}
+
+/// Parses the flags and file name that follow `-c` or `-d`, exiting with the usage message if they are invalid
+///
+/// # Arguments:
+/// * `args`: the arguments after `-c` or `-d`
+fn parse_options(args: &[String]) -> Options {
+  let mut options = Options { format: None, stream: false, threads: 1, limits: None, restart: None, conceal: false, tile: None, region: None,
+    progressive: false, partial: false, pyramid: None, interval: None,
+    filename: None };
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--stream" => options.stream = true,
+      "--threads" => match args.next().and_then(|n| n.parse().ok()) {
+        Some(threads) if threads > 0 => options.threads = threads,
+        _ => exit_with("--threads expects a positive number of threads"),
+      },
+      "--restart" => match args.next().and_then(|n| n.parse().ok()) {
+        Some(rows) if rows > 0 => options.restart = Some(rows),
+        _ => exit_with("--restart expects a positive number of block rows"),
+      },
+      "--conceal" => options.conceal = true,
+      "--progressive" => options.progressive = true,
+      "--partial" => options.partial = true,
+      "--tile" => match args.next().and_then(|n| n.parse().ok()) {
+        Some(size) if size > 0 && size % 2 == 0 => options.tile = Some(size),
+        _ => exit_with("--tile expects a positive even number of pixels"),
+      },
+      "--pyramid" => match args.next().and_then(|n| n.parse().ok()) {
+        Some(levels) if (1..=pyramid::MAX_LEVELS).contains(&levels) => options.pyramid = Some(levels),
+        _ => exit_with(&format!("--pyramid expects a number of levels from 1 to {}", pyramid::MAX_LEVELS)),
+      },
+      "--interval" => match args.next().and_then(|n| n.parse().ok()) {
+        Some(ms) => options.interval = Some(ms),
+        _ => exit_with("--interval expects a number of milliseconds"),
+      },
+      "--region" => match args.next().map(|region| region.split(',').map(|n| n.parse().ok()).collect::<Option<Vec<u32>>>()) {
+        Some(Some(region)) if region.len() == 4 => options.region = Some((region[0], region[1], region[2], region[3])),
+        _ => exit_with("--region expects x,y,width,height"),
+      },
+      "--format" => match args.next().and_then(|name| FileFormat::from_name(name)) {
+        Some(format) => options.format = Some(format),
+        None => exit_with("unknown output format, expected pnm, bmp or png"),
+      },
+      "--max-width" | "--max-height" | "--max-pixels" | "--max-memory" => {
+        let limits = options.limits.get_or_insert_with(DecodeLimits::default);
+        let value = args.next().and_then(|n| if arg == "--max-memory" { parse_size(n) } else { n.parse().ok() });
+        match (arg.as_str(), value) {
+          ("--max-width", Some(n)) if n <= u32::MAX as u64 => limits.max_width = n as u32,
+          ("--max-height", Some(n)) if n <= u32::MAX as u64 => limits.max_height = n as u32,
+          ("--max-pixels", Some(n)) => limits.max_pixels = n,
+          ("--max-memory", Some(n)) => limits.max_memory = n,
+          _ => exit_with(&format!("{} expects a number", arg)),
+        }
+      }
+      _ if options.filename.is_none() && !arg.starts_with("--") => options.filename = Some(arg.clone()),
+      _ => exit_with(&format!("unexpected argument {}", arg)),
+    }
+  }
+  if options.stream && options.threads > 1 {
+    exit_with("--stream and --threads cannot be used together");
+  }
+  options
+}
+
+/// Compresses the image named in `args`, or standard input, writing the rpeg file to standard output.
+/// With `--stream`, a binary PGM or PPM is compressed two rows at a time, and with `--threads`, bands of
+/// rows are compressed in parallel. With `--restart`, a restart marker is written every that many block rows, and
+/// with `--tile`, the words are stored in square tiles of that many pixels on a side. With `--progressive`, the words
+/// are written in passes that each refine the image, and with `--pyramid`, the `a` fields are held in a pyramid of that
+/// many levels, which takes less space on smooth images.
+///
+/// # Arguments:
+/// * `args`: optional `--stream`, `--threads`, `--restart`, `--tile`, `--progressive` and `--pyramid` flags, and an
+///   optional file name
+fn run_compress(args: &[String]) {
+  let options = parse_options(args);
+  if options.format.is_some() {
+    exit_with("--format only applies to -d");
+  }
+  if options.conceal || options.region.is_some() || options.partial {
+    exit_with("--conceal, --region and --partial only apply to -d");
+  }
+  let arrangements = [options.restart.is_some(), options.tile.is_some(), options.progressive, options.pyramid.is_some()];
+  if options.stream && arrangements.contains(&true) {
+    exit_with("--stream cannot be used with --restart, --tile, --progressive or --pyramid");
+  }
+  if arrangements.iter().filter(|&&flag| flag).count() > 1 {
+    exit_with("only one of --restart, --tile, --progressive and --pyramid can be used");
+  }
+  if options.limits.is_some() {
+    exit_with("--max-width, --max-height, --max-pixels and --max-memory only apply to -d");
+  }
+  if options.interval.is_some() {
+    exit_with("--interval only applies to pack");
+  }
+  if options.stream {
+    let result = image_file::open(options.filename.as_deref())
+      .and_then(|input| stream::compress_stream(input, &mut BufWriter::new(std::io::stdout().lock())));
+    if let Err(e) = result {
+      exit_with(&e);
     }
+  } else {
+    let arrangement = match (options.restart, options.tile, options.pyramid) {
+      (Some(interval), _, _) => Arrangement::Restart(interval),
+      (_, Some(size), _) => Arrangement::Tiles(size, size),
+      (_, _, Some(levels)) => Arrangement::Pyramid(levels),
+      _ if options.progressive => Arrangement::Progressive,
+      _ => Arrangement::Rows,
+    };
+    if let Err(e) = compress(options.filename.as_deref(), options.threads, arrangement) {
+      eprintln!("rpeg: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Decompresses the rpeg file named in `args`, or standard input, writing the image to standard output
+/// in the format given by `--format`, which defaults to Netpbm. With `--stream`, the image is decompressed
+/// one row of words at a time into a binary PGM or PPM, and with `--threads`, bands of rows are decompressed
+/// in parallel. Files whose header exceeds the `DecodeLimits`, which the `--max-*` flags override, are rejected
+/// before anything is allocated. With `--conceal`, the damaged segments of a file with restart markers are concealed
+/// and reported on standard error instead of failing the whole file. With `--region`, only the tiles of a tiled file
+/// that the region covers are read and decoded, and with `--partial`, as much of a progressive file as has arrived
+/// is decoded, reporting on standard error how many of its passes were complete.
+///
+/// # Arguments:
+/// * `args`: optional `--format`, `--stream`, `--threads`, `--conceal`, `--region`, `--partial` and `--max-*` flags,
+///   and an optional file name
+fn run_decompress(args: &[String]) {
+  let options = parse_options(args);
+  if options.restart.is_some() || options.tile.is_some() || options.progressive || options.pyramid.is_some() {
+    exit_with("--restart, --tile, --progressive and --pyramid only apply to -c");
+  }
+  if options.interval.is_some() {
+    exit_with("--interval only applies to pack");
+  }
+  if [options.stream, options.conceal, options.region.is_some(), options.partial].iter().filter(|&&flag| flag).count() > 1 {
+    exit_with("only one of --stream, --conceal, --region and --partial can be used");
+  }
+  let format = options.format.unwrap_or(FileFormat::Pnm);
+  let limits = options.limits.unwrap_or_default();
+  if options.stream {
+    if format != FileFormat::Pnm {
+      exit_with("--stream can only write Netpbm images");
+    }
+    let result = image_file::open(options.filename.as_deref())
+      .and_then(|input| stream::decompress_stream_with_limits(input, &mut BufWriter::new(std::io::stdout().lock()), limits));
+    if let Err(e) = result {
+      exit_with(&e);
+    }
+  } else if options.partial {
+    match decompress_prefix(options.filename.as_deref(), format, options.threads, limits) {
+      Ok((passes, total)) if passes < total => eprintln!("rpeg: decoded {} of {} passes", passes, total),
+      Ok(_) => (),
+      Err(e) => {
+        eprintln!("rpeg: {}", e);
+        std::process::exit(1);
+      }
+    }
+  } else if let Some(region) = options.region {
+    if let Err(e) = decompress_region(options.filename.as_deref(), format, region, limits) {
+      eprintln!("rpeg: {}", e);
+      std::process::exit(1);
+    }
+  } else {
+    match decompress(options.filename.as_deref(), format, options.threads, limits, options.conceal) {
+      Ok(concealed) => for rows in concealed {
+        eprintln!("rpeg: concealed damaged pixel rows {} to {}", rows.start, rows.end - 1);
+      },
+      Err(e) => {
+        eprintln!("rpeg: {}", e);
+        std::process::exit(1);
+      }
+    }
+  }
+}
+
+/// Packs the Netpbm files in the directory named in `args` into an rpeg sequence written to standard output,
+/// reporting on standard error how many frames it holds. With `--interval`, the frames are stamped that many
+/// milliseconds apart instead of with the modification times of their files.
+///
+/// # Arguments:
+/// * `args`: optional `--threads` and `--interval` flags, and the name of the directory
+fn run_pack(args: &[String]) {
+  let options = parse_options(args);
+  let directory = match options.filename.as_deref() {
+    Some(directory) => directory,
+    None => exit_with("pack expects a directory"),
+  };
+  let encoding = options.restart.is_some() || options.tile.is_some() || options.progressive || options.pyramid.is_some();
+  let decoding = options.format.is_some() || options.conceal || options.region.is_some() || options.partial || options.limits.is_some();
+  if options.stream || encoding || decoding {
+    exit_with("pack only takes --threads and --interval");
+  }
+  match pack(directory, options.threads, options.interval) {
+    Ok(frames) => eprintln!("rpeg: packed {} frames", frames),
+    Err(e) => {
+      eprintln!("rpeg: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Decompresses one frame of the rpeg sequence named in `args`, or standard input, writing the image to standard
+/// output in the format given by `--format` and its timestamp to standard error. Only that frame is read from a file.
+///
+/// # Arguments:
+/// * `args`: the index of the frame, counting from 0, then optional `--format`, `--threads` and `--max-*` flags,
+///   and an optional file name
+fn run_extract(args: &[String]) {
+  let n = match args.first().and_then(|n| n.parse::<usize>().ok()) {
+    Some(n) => n,
+    None => exit_with("extract expects the index of a frame"),
+  };
+  let options = parse_options(&args[1..]);
+  let encoding = options.restart.is_some() || options.tile.is_some() || options.progressive || options.pyramid.is_some();
+  if options.stream || encoding || options.conceal || options.region.is_some() || options.partial || options.interval.is_some() {
+    exit_with("extract only takes --format, --threads and --max-* flags");
+  }
+  let format = options.format.unwrap_or(FileFormat::Pnm);
+  match extract(options.filename.as_deref(), n, format, options.threads, options.limits.unwrap_or_default()) {
+    Ok(timestamp) => eprintln!("rpeg: frame {} was captured at {} ms", n, timestamp),
+    Err(e) => {
+      eprintln!("rpeg: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Returns the number of bytes in `size`, a number with an optional `K`, `M` or `G` suffix for KiB, MiB or GiB,
+/// or None if it is not a valid size
+///
+/// # Arguments:
+/// * `size`: the size given on the command line
+fn parse_size(size: &str) -> Option<u64> {
+  let (digits, shift) = match size.as_bytes().last()? {
+    b'K' | b'k' => (&size[..size.len() - 1], 10),
+    b'M' | b'm' => (&size[..size.len() - 1], 20),
+    b'G' | b'g' => (&size[..size.len() - 1], 30),
+    _ => (size, 0),
+  };
+  digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Prints `message` and the usage message to standard error, and exits with a failure code
+///
+/// # Arguments:
+/// * `message`: a description of what went wrong
+fn exit_with(message: &str) -> ! {
+  eprintln!("rpeg: {}\n{}", message, USAGE);
+  std::process::exit(1);
+}
+
+/// Joins the rpeg files named in `args` into a single rpeg written to standard output,
+/// without decoding any of them
+///
+/// # Arguments:
+/// * `args`: the layout flag (and column count for a grid) followed by the file names
+fn run_mosaic(args: &[String]) {
+  let read_all = |files: &[String]| {
+    files.iter().map(|f| CompressedImage::read(Some(f))).collect::<Result<Vec<_>, String>>()
+  };
+  let result = match args.first().map(|s| s.as_str()) {
+    Some("-h") => read_all(&args[1..]).and_then(|images| mosaic::hstack(&images)),
+    Some("-v") => read_all(&args[1..]).and_then(|images| mosaic::vstack(&images)),
+    Some("-g") if args.len() > 1 => match args[1].parse() {
+      Ok(columns) => read_all(&args[2..]).and_then(|images| mosaic::grid(&images, columns)),
+      Err(_) => Err(format!("invalid column count: {}", args[1])),
+    },
+    _ => Err(USAGE.to_string()),
+  };
+  if let Err(e) = result.and_then(|img| img.write()) {
+    eprintln!("rpeg mosaic: {}", e);
+    std::process::exit(1);
   }
 }
@@ -0,0 +1,238 @@
+use array2::Array2;
+use crate::alpha::AlphaPlane;
+use crate::container::{Arrangement, CompressedImage};
+
+/// Returns a single compressed image made by placing `images` side by side from left to right.
+/// Every word describes its own 2 x 2 block, so the word grids are rearranged without decoding.
+/// Returns an error if the images do not all share the same height and quantization.
+///
+/// # Arguments:
+/// * `images`: the compressed images to join, in order from left to right
+pub fn hstack(images: &[CompressedImage]) -> Result<CompressedImage, String> {
+    let first = check_compatible(images)?;
+    for (idx, img) in images.iter().enumerate() {
+        if img.height != first.height {
+            return Err(format!("image {} has height {}, expected {}", idx, img.height, first.height));
+        }
+    }
+
+    let grids: Vec<&Array2<u64>> = images.iter().map(|img| &img.words).collect();
+    Ok(CompressedImage {
+        words: side_by_side(&grids),
+        width: images.iter().map(|img| img.width).sum(),
+        height: first.height,
+        model: first.model,
+        denominator: first.denominator,
+        alpha: join_alpha(images, true),
+        arrangement: Arrangement::Rows,
+    })
+}
+
+/// Returns a single compressed image made by placing `images` on top of each other from top to bottom.
+/// Returns an error if the images do not all share the same width and quantization.
+///
+/// # Arguments:
+/// * `images`: the compressed images to join, in order from top to bottom
+pub fn vstack(images: &[CompressedImage]) -> Result<CompressedImage, String> {
+    let first = check_compatible(images)?;
+    for (idx, img) in images.iter().enumerate() {
+        if img.width != first.width {
+            return Err(format!("image {} has width {}, expected {}", idx, img.width, first.width));
+        }
+    }
+
+    let grids: Vec<&Array2<u64>> = images.iter().map(|img| &img.words).collect();
+    Ok(CompressedImage {
+        words: on_top_of_each_other(&grids),
+        width: first.width,
+        height: images.iter().map(|img| img.height).sum(),
+        model: first.model,
+        denominator: first.denominator,
+        alpha: join_alpha(images, false),
+        arrangement: Arrangement::Rows,
+    })
+}
+
+/// Returns a single compressed image made by laying `images` out in a grid with `columns` tiles per row,
+/// filled in row major order. Every tile in a row must share a height, and every row must end up the same width.
+///
+/// # Arguments:
+/// * `images`: the compressed images to join, in row major order
+/// * `columns`: the number of images in each row of the grid
+pub fn grid(images: &[CompressedImage], columns: usize) -> Result<CompressedImage, String> {
+    if columns == 0 || !images.len().is_multiple_of(columns) {
+        return Err(format!("{} images cannot fill a grid with {} columns", images.len(), columns));
+    }
+
+    let rows = images.chunks(columns)
+        .enumerate()
+        .map(|(idx, row)| hstack(row).map_err(|e| format!("row {}: {}", idx, e)))
+        .collect::<Result<Vec<_>, String>>()?;
+    vstack(&rows)
+}
+
+/// Helper function that returns the first of `images`, or an error if there are none or if any of them
+/// were quantized differently, with another colour model, denominator or kind of alpha plane, since their words
+/// could not be decoded together
+///
+/// # Arguments:
+/// * `images`: the compressed images to join
+fn check_compatible(images: &[CompressedImage]) -> Result<&CompressedImage, String> {
+    let first = images.first().ok_or("cannot build a mosaic out of zero images")?;
+    let alpha_kind = |img: &CompressedImage| match img.alpha {
+        None => "no alpha",
+        Some(AlphaPlane::Transform(_)) => "a transformed alpha plane",
+        Some(AlphaPlane::Mask(_)) => "an alpha mask",
+    };
+    for (idx, img) in images.iter().enumerate() {
+        if alpha_kind(img) != alpha_kind(first) {
+            return Err(format!("image {} has {}, expected {}", idx, alpha_kind(img), alpha_kind(first)));
+        }
+        if img.model != first.model {
+            return Err(format!("image {} uses the {:?} colour model, expected {:?}", idx, img.model, first.model));
+        }
+        if img.denominator != first.denominator {
+            return Err(format!("image {} has denominator {}, expected {}", idx, img.denominator, first.denominator));
+        }
+    }
+    Ok(first)
+}
+
+/// Helper function that joins the alpha planes of `images`, which all have the same kind of plane
+///
+/// # Arguments:
+/// * `images`: the compressed images being joined
+/// * `horizontal`: true if the images are placed side by side, and false if they are placed on top of each other
+fn join_alpha(images: &[CompressedImage], horizontal: bool) -> Option<AlphaPlane> {
+    match images[0].alpha {
+        None => None,
+        Some(AlphaPlane::Transform(_)) => {
+            let grids: Vec<&Array2<u64>> = images.iter()
+                .filter_map(|img| match &img.alpha {
+                    Some(AlphaPlane::Transform(words)) => Some(words),
+                    _ => None,
+                })
+                .collect();
+            Some(AlphaPlane::Transform(if horizontal { side_by_side(&grids) } else { on_top_of_each_other(&grids) }))
+        }
+        Some(AlphaPlane::Mask(_)) => {
+            let grids: Vec<&Array2<bool>> = images.iter()
+                .filter_map(|img| match &img.alpha {
+                    Some(AlphaPlane::Mask(mask)) => Some(mask),
+                    _ => None,
+                })
+                .collect();
+            Some(AlphaPlane::Mask(if horizontal { side_by_side(&grids) } else { on_top_of_each_other(&grids) }))
+        }
+    }
+}
+
+/// Helper function that joins grids of the same height from left to right, by walking across every grid
+/// for each row before moving down to the next row
+///
+/// # Arguments:
+/// * `grids`: the grids to join, in order from left to right
+fn side_by_side<T: Clone>(grids: &[&Array2<T>]) -> Array2<T> {
+    let height = grids[0].height();
+    let mut values = Vec::new();
+    for row in 0..height {
+        for grid in grids {
+            for col in 0..grid.width() {
+                values.push(grid.get(row, col).clone());
+            }
+        }
+    }
+    Array2::from(values, height, grids.iter().map(|grid| grid.width()).sum())
+}
+
+/// Helper function that joins grids of the same width from top to bottom. The grids are stored in row major order,
+/// so each one simply follows the one above it
+///
+/// # Arguments:
+/// * `grids`: the grids to join, in order from top to bottom
+fn on_top_of_each_other<T: Clone>(grids: &[&Array2<T>]) -> Array2<T> {
+    let values = grids.iter()
+        .flat_map(|grid| grid.iter_row_maj().map(|(_, _, value)| value.clone()))
+        .collect();
+    Array2::from(values, grids.iter().map(|grid| grid.height()).sum(), grids[0].width())
+}
+
+#[cfg(test)]
+mod tests {
+    use array2::Array2;
+    use crate::alpha::AlphaPlane;
+    use crate::container::{ColourModel, CompressedImage};
+    use crate::mosaic::{grid, hstack, vstack};
+
+    fn words_of(img: &CompressedImage) -> Vec<u64> {
+        img.words.iter_row_maj().map(|(_, _, word)| *word).collect()
+    }
+
+    #[test]
+    fn test_hstack() {
+        let left = CompressedImage::from_words(vec![1, 2, 3, 4], 4, 4, ColourModel::Rgb, 255);
+        let right = CompressedImage::from_words(vec![5, 6], 2, 4, ColourModel::Rgb, 255);
+        let joined = hstack(&[left, right]).unwrap();
+        assert_eq!((joined.width, joined.height), (6, 4));
+        assert_eq!(words_of(&joined), vec![1, 2, 5, 3, 4, 6]);
+    }
+
+    #[test]
+    fn test_vstack() {
+        let top = CompressedImage::from_words(vec![1, 2], 4, 2, ColourModel::Rgb, 255);
+        let bottom = CompressedImage::from_words(vec![3, 4, 5, 6], 4, 4, ColourModel::Rgb, 255);
+        let joined = vstack(&[top, bottom]).unwrap();
+        assert_eq!((joined.width, joined.height), (4, 6));
+        assert_eq!(words_of(&joined), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_grid() {
+        let tiles: Vec<CompressedImage> = (1..=4).map(|n| CompressedImage::from_words(vec![n], 2, 2, ColourModel::Rgb, 255)).collect();
+        let joined = grid(&tiles, 2).unwrap();
+        assert_eq!((joined.width, joined.height), (4, 4));
+        assert_eq!(words_of(&joined), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_mismatched_dimensions() {
+        let tall = CompressedImage::from_words(vec![1, 2], 2, 4, ColourModel::Rgb, 255);
+        let short = CompressedImage::from_words(vec![3], 2, 2, ColourModel::Rgb, 255);
+        assert!(hstack(&[tall, short]).is_err());
+
+        let wide = CompressedImage::from_words(vec![1, 2], 4, 2, ColourModel::Rgb, 255);
+        let narrow = CompressedImage::from_words(vec![3], 2, 2, ColourModel::Rgb, 255);
+        assert!(vstack(&[wide, narrow]).is_err());
+
+        let tiles: Vec<CompressedImage> = (1..=3).map(|n| CompressedImage::from_words(vec![n], 2, 2, ColourModel::Rgb, 255)).collect();
+        assert!(grid(&tiles, 2).is_err());
+        assert!(hstack(&[]).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_models() {
+        let color = CompressedImage::from_words(vec![1], 2, 2, ColourModel::Rgb, 255);
+        let gray = CompressedImage::from_words(vec![2], 2, 2, ColourModel::Gray, 255);
+        assert!(hstack(&[color, gray]).is_err());
+
+        let shallow = CompressedImage::from_words(vec![1], 2, 2, ColourModel::Rgb, 255);
+        let deep = CompressedImage::from_words(vec![2], 2, 2, ColourModel::Rgb, 65535);
+        assert!(vstack(&[shallow, deep]).is_err());
+
+        let opaque = CompressedImage::from_words(vec![1], 2, 2, ColourModel::Rgb, 255);
+        let mut masked = CompressedImage::from_words(vec![2], 2, 2, ColourModel::Rgb, 255);
+        masked.alpha = Some(AlphaPlane::Mask(Array2::from(vec![true; 4], 2, 2)));
+        assert!(hstack(&[opaque, masked]).is_err());
+    }
+
+    #[test]
+    fn test_alpha_masks() {
+        let mut left = CompressedImage::from_words(vec![1], 2, 2, ColourModel::Rgb, 255);
+        left.alpha = Some(AlphaPlane::Mask(Array2::from(vec![true, false, true, false], 2, 2)));
+        let mut right = CompressedImage::from_words(vec![2], 2, 2, ColourModel::Rgb, 255);
+        right.alpha = Some(AlphaPlane::Mask(Array2::from(vec![false, false, true, true], 2, 2)));
+        let joined = hstack(&[left, right]).unwrap();
+        let expected = Array2::from(vec![true, false, false, false, true, false, true, true], 2, 4);
+        assert_eq!(joined.alpha, Some(AlphaPlane::Mask(expected)));
+    }
+}
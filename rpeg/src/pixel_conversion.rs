@@ -1,4 +1,5 @@
 use csc411_image::Rgb;
+use crate::simd::{self, Isa};
 
 
 /// Returns a tuple of three f64 values corresponding to the colors Red Green and Blue from `pixel`,
@@ -59,6 +60,98 @@ pub fn get_decompression_tuple(comp_vid_floats: (f64, f64, f64)) -> (f64, f64, f
     )
 }
 
+/// The red, green and blue of many pixels on a scale of 0.0 - 1.0, held as a structure of arrays with one vector per
+/// color, so that batches of pixels can be converted with vector instructions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RgbPlanes {
+    pub red: Vec<f64>,
+    pub green: Vec<f64>,
+    pub blue: Vec<f64>,
+}
+
+/// The luma and chroma of many pixels, held as a structure of arrays with one vector per component
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComponentPlanes {
+    pub y: Vec<f64>,
+    pub pb: Vec<f64>,
+    pub pr: Vec<f64>,
+}
+
+impl RgbPlanes {
+    /// Returns the planes of `pixels`, with each value divided by `denom` as in `rgb_to_float`
+    ///
+    /// # Arguments:
+    /// * `pixels`: the pixels to convert
+    /// * `denom`: the denominator of the RgbImage casted as a floating point value
+    pub fn from_pixels(pixels: &[Rgb], denom: f64) -> Self {
+        RgbPlanes {
+            red: pixels.iter().map(|p| p.red as f64 / denom).collect(),
+            green: pixels.iter().map(|p| p.green as f64 / denom).collect(),
+            blue: pixels.iter().map(|p| p.blue as f64 / denom).collect(),
+        }
+    }
+}
+
+/// Returns the luma and chroma of every pixel in `rgb`, the batch form of `get_compression_tuple`, using the widest
+/// vector instructions the processor supports. The results are identical to calling `get_compression_tuple`
+/// on each pixel.
+///
+/// # Arguments:
+/// * `rgb`: the planes of the pixels, which must all have the same length
+pub fn get_compression_planes(rgb: &RgbPlanes) -> ComponentPlanes {
+    get_compression_planes_with(Isa::detect(), rgb)
+}
+
+/// Returns the luma and chroma of every pixel in `rgb`, using the instruction set `isa`
+///
+/// # Arguments:
+/// * `isa`: the instruction set to use, which must be supported by the processor
+/// * `rgb`: the planes of the pixels, which must all have the same length
+pub(crate) fn get_compression_planes_with(isa: Isa, rgb: &RgbPlanes) -> ComponentPlanes {
+    let len = rgb.red.len();
+    assert!(rgb.green.len() == len && rgb.blue.len() == len, "the color planes have different lengths");
+    let mut out = ComponentPlanes { y: vec![0.0; len], pb: vec![0.0; len], pr: vec![0.0; len] };
+    let done = simd::compression(isa, rgb, &mut out);
+
+    // the pixels left over are converted by a plain loop over slices of equal length, which the compiler vectorizes
+    let (red, green, blue) = (&rgb.red[done..], &rgb.green[done..len], &rgb.blue[done..len]);
+    let (y, pb, pr) = (&mut out.y[done..], &mut out.pb[done..len], &mut out.pr[done..len]);
+    for i in 0..red.len() {
+        (y[i], pb[i], pr[i]) = get_compression_tuple((red[i], green[i], blue[i]));
+    }
+    out
+}
+
+/// Returns the red, green and blue of every pixel in `comp`, the batch form of `get_decompression_tuple`, using the
+/// widest vector instructions the processor supports. The results are identical to calling `get_decompression_tuple`
+/// on each pixel.
+///
+/// # Arguments:
+/// * `comp`: the planes of the pixels, which must all have the same length
+pub fn get_decompression_planes(comp: &ComponentPlanes) -> RgbPlanes {
+    get_decompression_planes_with(Isa::detect(), comp)
+}
+
+/// Returns the red, green and blue of every pixel in `comp`, using the instruction set `isa`
+///
+/// # Arguments:
+/// * `isa`: the instruction set to use, which must be supported by the processor
+/// * `comp`: the planes of the pixels, which must all have the same length
+pub(crate) fn get_decompression_planes_with(isa: Isa, comp: &ComponentPlanes) -> RgbPlanes {
+    let len = comp.y.len();
+    assert!(comp.pb.len() == len && comp.pr.len() == len, "the component planes have different lengths");
+    let mut out = RgbPlanes { red: vec![0.0; len], green: vec![0.0; len], blue: vec![0.0; len] };
+    let done = simd::decompression(isa, comp, &mut out);
+
+    // the pixels left over are converted by a plain loop over slices of equal length, which the compiler vectorizes
+    let (y, pb, pr) = (&comp.y[done..], &comp.pb[done..len], &comp.pr[done..len]);
+    let (red, green, blue) = (&mut out.red[done..], &mut out.green[done..len], &mut out.blue[done..len]);
+    for i in 0..y.len() {
+        (red[i], green[i], blue[i]) = get_decompression_tuple((y[i], pb[i], pr[i]));
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use crate::pixel_conversion;
@@ -107,4 +200,26 @@ mod tests {
         assert_eq!(new_red.green, 0);
         assert_eq!(new_red.blue, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn batches_match_scalar(){
+        use crate::pixel_conversion::{ComponentPlanes, RgbPlanes};
+        use crate::simd::Isa;
+
+        // 1003 values leave a tail after every vector width
+        let values: Vec<f64> = (0..1003_u32).map(|n| (n * 7919 % 1000) as f64 / 999.0).collect();
+        let rgb = RgbPlanes { red: values.clone(), green: values.iter().rev().copied().collect(), blue: values.iter().map(|v| 1.0 - v).collect() };
+        let comp = ComponentPlanes { y: values.clone(), pb: values.iter().map(|v| v - 0.5).collect(), pr: values.iter().map(|v| 0.5 - v).collect() };
+        for isa in [Isa::Scalar, Isa::Sse2, Isa::Avx2].into_iter().filter(|isa| isa.supported()) {
+            let out = pixel_conversion::get_compression_planes_with(isa, &rgb);
+            let back = pixel_conversion::get_decompression_planes_with(isa, &comp);
+            for i in 0..values.len() {
+                let expected = pixel_conversion::get_compression_tuple((rgb.red[i], rgb.green[i], rgb.blue[i]));
+                assert_eq!((out.y[i], out.pb[i], out.pr[i]), expected, "{:?} at {}", isa, i);
+                let expected = pixel_conversion::get_decompression_tuple((comp.y[i], comp.pb[i], comp.pr[i]));
+                assert_eq!((back.red[i], back.green[i], back.blue[i]), expected, "{:?} at {}", isa, i);
+            }
+        }
+        assert_eq!(RgbPlanes::from_pixels(&[Rgb{red: 255, green: 0, blue: 51}], 255.0).blue, vec![0.2]);
+    }
+}
@@ -0,0 +1,549 @@
+use crate::checksum;
+use crate::deflate;
+use crate::pnm::PnmImage;
+
+/// The eight bytes that start every PNG file
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The starting column, starting row, column step and row step of each of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+
+/// Parses a PNG file of any standard color type and bit depth, interlaced or not, into an image.
+/// Gray and truecolor images keep their bit depth as the maxval. Palette images become `RGB` with a maxval of 255,
+/// or `GRAYSCALE` when every color in the palette is gray. A `tRNS` chunk adds an alpha channel.
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+pub fn parse(bytes: &[u8]) -> Result<PnmImage, String> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err("not a PNG image: missing signature".to_string());
+    }
+
+    let (mut header, mut palette, mut transparency) = (None, None, None);
+    let mut data = Vec::new();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let len = bytes.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or("file ends before the IEND chunk")?;
+        let body = bytes.get(pos + 4..pos + 8 + len).ok_or("file ends in the middle of a chunk")?;
+        let crc = bytes.get(pos + 8 + len..pos + 12 + len)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or("file ends in the middle of a chunk")?;
+        let (kind, contents) = body.split_at(4);
+        let name = String::from_utf8_lossy(kind);
+        if checksum::crc32(body) != crc {
+            return Err(format!("{} chunk at byte {} fails its CRC check", name, pos));
+        }
+        pos += 12 + len;
+
+        match kind {
+            b"IHDR" => header = Some(Header::parse(contents)?),
+            _ if header.is_none() => return Err(format!("expected the IHDR chunk first, found {}", name)),
+            b"PLTE" => {
+                if contents.is_empty() || contents.len() % 3 != 0 || contents.len() > 256 * 3 {
+                    return Err(format!("PLTE chunk has {} bytes, which is not 1 to 256 colors", contents.len()));
+                }
+                palette = Some(contents.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect::<Vec<_>>());
+            }
+            b"tRNS" => transparency = Some(contents.to_vec()),
+            b"IDAT" => data.extend_from_slice(contents),
+            b"IEND" => break,
+            // chunks whose names start with a capital letter are critical to reading the image
+            _ if kind[0].is_ascii_uppercase() => return Err(format!("unknown critical chunk {}", name)),
+            _ => (),
+        }
+    }
+    let header = header.ok_or("missing IHDR chunk")?;
+    // the size of the filtered rows is known from the IHDR chunk, so inflating stops as soon as the data outgrows it
+    let needed = header.filtered_len().ok_or("image dimensions are too large")?;
+    let raw = deflate::zlib_decompress(&data, needed).map_err(|e| format!("image data: {}", e))?;
+    let values = header.read_values(&raw)?;
+    header.to_image(values, palette.as_deref(), transparency.as_deref())
+}
+
+/// Returns the contents of a PNG file holding `img`. Images with a maxval up to 255 are written with 8 bit
+/// samples and deeper images with 16 bit samples, rescaling any other maxval to fill the bit depth.
+/// The color type follows the depth of the image: gray, gray with alpha, truecolor, or truecolor with alpha.
+///
+/// # Arguments:
+/// * `img`: the image to write
+pub fn encode(img: &PnmImage) -> Result<Vec<u8>, String> {
+    let color_type = match (img.depth, img.has_alpha()) {
+        (1, false) => 0,
+        (2, true) => 4,
+        (3, false) => 2,
+        (4, true) => 6,
+        _ => return Err(format!("a PNG cannot hold an image of depth {} with tuple type {}", img.depth, img.tupltype)),
+    };
+    if img.width == 0 || img.height == 0 || img.width > i32::MAX as u32 || img.height > i32::MAX as u32 {
+        return Err(format!("a {} x {} image cannot be written as a PNG", img.width, img.height));
+    }
+    let (bit_depth, target) = if img.maxval > 255 { (16, 65535) } else { (8, 255) };
+    let scale = |sample: u16| ((sample as u32 * target + img.maxval as u32 / 2) / img.maxval as u32) as u16;
+
+    // every row of samples, in big-endian order
+    let bytes_per_pixel = img.depth as usize * bit_depth / 8;
+    let row_len = img.width as usize * bytes_per_pixel;
+    let raw: Vec<u8> = img.samples.iter()
+        .flat_map(|&sample| {
+            let value = scale(sample);
+            if bit_depth == 16 { value.to_be_bytes().to_vec() } else { vec![value as u8] }
+        })
+        .collect();
+
+    let mut filtered = Vec::with_capacity((row_len + 1) * img.height as usize);
+    let zeros = vec![0; row_len];
+    for (idx, row) in raw.chunks_exact(row_len).enumerate() {
+        let above = if idx == 0 { &zeros[..] } else { &raw[(idx - 1) * row_len..idx * row_len] };
+        let (filter, bytes) = best_filter(row, above, bytes_per_pixel);
+        filtered.push(filter);
+        filtered.extend(bytes);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&img.width.to_be_bytes());
+    header.extend_from_slice(&img.height.to_be_bytes());
+    // no interlacing, and the only compression and filter methods PNG defines
+    header.extend_from_slice(&[bit_depth as u8, color_type, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    push_chunk(&mut out, b"IHDR", &header);
+    push_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&filtered));
+    push_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+/// Returns the predictor PNG filter type `filter` uses for a byte, given the byte to its left `a`, the byte
+/// above it `b` and the byte above and to the left `c`
+///
+/// # Arguments:
+/// * `filter`: the filter type, between 0 and 4
+/// * `a`, `b`, `c`: the neighbors of the byte, which are 0 off the edge of the image
+pub fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => {
+            // Paeth picks whichever neighbor is closest to a + b - c
+            let p = a as i16 + b as i16 - c as i16;
+            let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+            if pa <= pb && pa <= pc {
+                a
+            } else if pb <= pc {
+                b
+            } else {
+                c
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Helper struct holding the fields of an IHDR chunk
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    /// Reads and checks the contents of an IHDR chunk
+    fn parse(contents: &[u8]) -> Result<Self, String> {
+        if contents.len() != 13 {
+            return Err(format!("IHDR chunk has {} bytes, expected 13", contents.len()));
+        }
+        let width = u32::from_be_bytes([contents[0], contents[1], contents[2], contents[3]]);
+        let height = u32::from_be_bytes([contents[4], contents[5], contents[6], contents[7]]);
+        let (bit_depth, color_type) = (contents[8], contents[9]);
+        if width == 0 || height == 0 {
+            return Err(format!("image dimensions must be positive, found {} x {}", width, height));
+        }
+        let allowed: &[u8] = match color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(format!("invalid color type {}", color_type)),
+        };
+        if !allowed.contains(&bit_depth) {
+            return Err(format!("bit depth {} is not allowed with color type {}", bit_depth, color_type));
+        }
+        if contents[10] != 0 || contents[11] != 0 {
+            return Err("unknown compression or filter method".to_string());
+        }
+        if contents[12] > 1 {
+            return Err(format!("unknown interlace method {}", contents[12]));
+        }
+        Ok(Header { width: width as usize, height: height as usize, bit_depth, color_type, interlaced: contents[12] == 1 })
+    }
+
+    /// Returns the number of samples in each pixel
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// Undoes the filters on the decompressed image data and returns every sample of the image, or palette
+    /// index, in row major order. Interlaced images are put back together from their seven passes.
+    fn read_values(&self, raw: &[u8]) -> Result<Vec<u16>, String> {
+        let channels = self.channels();
+        let bits_per_pixel = channels * self.bit_depth as usize;
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+        let passes = self.passes();
+
+        // the size of the image comes from the IHDR chunk alone, so the data is checked to be long enough to fill it
+        // before anything is allocated
+        let count = self.width.checked_mul(self.height)
+            .and_then(|n| n.checked_mul(channels))
+            .ok_or("image dimensions are too large")?;
+        let needed = self.filtered_len().ok_or("image dimensions are too large")?;
+        if raw.len() < needed {
+            return Err(format!("image data holds {} bytes, but a {} x {} image needs {}", raw.len(), self.width, self.height, needed));
+        }
+        let mut values = vec![0; count];
+        let mut pos = 0;
+        for &(x0, y0, dx, dy) in passes {
+            let (pass_width, pass_height) = ((self.width + dx - 1 - x0) / dx, (self.height + dy - 1 - y0) / dy);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let row_len = (pass_width * bits_per_pixel).div_ceil(8);
+            let mut above = vec![0; row_len];
+            for pass_row in 0..pass_height {
+                let y = y0 + pass_row * dy;
+                let filter = *raw.get(pos).ok_or(format!("image data ends before row {}", y))?;
+                let mut row = raw.get(pos + 1..pos + 1 + row_len).ok_or(format!("image data ends in row {}", y))?.to_vec();
+                if filter > 4 {
+                    return Err(format!("row {} uses unknown filter type {}", y, filter));
+                }
+                pos += row_len + 1;
+                for i in 0..row_len {
+                    let (a, c) = if i >= bytes_per_pixel { (row[i - bytes_per_pixel], above[i - bytes_per_pixel]) } else { (0, 0) };
+                    row[i] = row[i].wrapping_add(predict(filter, a, above[i], c));
+                }
+
+                for pass_col in 0..pass_width {
+                    let x = x0 + pass_col * dx;
+                    for channel in 0..channels {
+                        let idx = pass_col * channels + channel;
+                        values[(y * self.width + x) * channels + channel] = match self.bit_depth {
+                            16 => u16::from_be_bytes([row[idx * 2], row[idx * 2 + 1]]),
+                            8 => row[idx] as u16,
+                            // samples narrower than a byte are packed starting from the most significant bits
+                            bits => {
+                                let bit = idx * bits as usize;
+                                ((row[bit / 8] >> (8 - bits as usize - bit % 8)) & ((1 << bits) - 1)) as u16
+                            }
+                        };
+                    }
+                }
+                above = row;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns the starting column, starting row, column step and row step of each pass the image is stored in
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlaced { &ADAM7[..] } else { &[(0, 0, 1, 1)][..] }
+    }
+
+    /// Returns the number of bytes of filtered rows, each a filter type followed by its samples, in every pass of
+    /// the image, or None if that does not fit in a `usize`
+    fn filtered_len(&self) -> Option<usize> {
+        let bits_per_pixel = self.channels() * self.bit_depth as usize;
+        self.passes().iter().try_fold(0_usize, |total, &(x0, y0, dx, dy)| {
+            let (pass_width, pass_height) = ((self.width + dx - 1 - x0) / dx, (self.height + dy - 1 - y0) / dy);
+            if pass_width == 0 || pass_height == 0 {
+                return Some(total);
+            }
+            let row_len = pass_width.checked_mul(bits_per_pixel)?.div_ceil(8);
+            total.checked_add(pass_height.checked_mul(row_len.checked_add(1)?)?)
+        })
+    }
+
+    /// Builds the image out of its samples, looking up palette colors and adding alpha from a `tRNS` chunk
+    fn to_image(&self, values: Vec<u16>, palette: Option<&[[u8; 3]]>, transparency: Option<&[u8]>) -> Result<PnmImage, String> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let maxval = ((1_u32 << self.bit_depth) - 1) as u16;
+        if self.color_type == 3 {
+            let palette = palette.ok_or("palette image has no PLTE chunk")?;
+            let alpha = transparency.unwrap_or(&[]);
+            let gray = palette.iter().all(|&[r, g, b]| r == g && g == b);
+            let mut samples = Vec::new();
+            for (idx, &value) in values.iter().enumerate() {
+                let color = palette.get(value as usize).ok_or(format!(
+                    "the pixel at row {}, column {} uses color {}, but the palette has {}",
+                    idx / self.width, idx % self.width, value, palette.len()
+                ))?;
+                let colors = if gray { &color[..1] } else { &color[..] };
+                samples.extend(colors.iter().map(|&c| c as u16));
+                if transparency.is_some() {
+                    samples.push(*alpha.get(value as usize).unwrap_or(&255) as u16);
+                }
+            }
+            let (depth, tupltype) = match (gray, transparency.is_some()) {
+                (true, false) => (1, "GRAYSCALE"),
+                (true, true) => (2, "GRAYSCALE_ALPHA"),
+                (false, false) => (3, "RGB"),
+                (false, true) => (4, "RGB_ALPHA"),
+            };
+            return Ok(PnmImage { width, height, depth, maxval: 255, tupltype: tupltype.to_string(), samples });
+        }
+
+        let channels = self.channels() as u32;
+        let tupltype = match self.color_type {
+            0 => "GRAYSCALE",
+            2 => "RGB",
+            4 => "GRAYSCALE_ALPHA",
+            _ => "RGB_ALPHA",
+        };
+        let img = PnmImage { width, height, depth: channels, maxval, tupltype: tupltype.to_string(), samples: values };
+        match transparency {
+            // gray and truecolor images can name a single color as fully transparent
+            Some(key) if self.color_type == 0 || self.color_type == 2 => {
+                if key.len() != channels as usize * 2 {
+                    return Err(format!("tRNS chunk has {} bytes, expected {}", key.len(), channels * 2));
+                }
+                let key: Vec<u16> = key.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect();
+                let alpha: Vec<u16> = img.samples.chunks_exact(channels as usize)
+                    .map(|tuple| if tuple == key.as_slice() { 0 } else { maxval })
+                    .collect();
+                Ok(img.with_alpha(&alpha))
+            }
+            _ => Ok(img),
+        }
+    }
+}
+
+/// Helper function that filters `row` with every filter type and keeps the one whose bytes have the smallest
+/// sum when read as signed values, which tends to compress best. Returns the filter type and the filtered bytes.
+///
+/// # Arguments:
+/// * `row`: the bytes of the row
+/// * `above`: the bytes of the row above, which are all 0 for the first row
+/// * `bytes_per_pixel`: the distance to the byte of the same sample in the pixel to the left
+fn best_filter(row: &[u8], above: &[u8], bytes_per_pixel: usize) -> (u8, Vec<u8>) {
+    (0..5_u8)
+        .map(|filter| {
+            let bytes: Vec<u8> = (0..row.len())
+                .map(|i| {
+                    let (a, c) = if i >= bytes_per_pixel { (row[i - bytes_per_pixel], above[i - bytes_per_pixel]) } else { (0, 0) };
+                    row[i].wrapping_sub(predict(filter, a, above[i], c))
+                })
+                .collect();
+            (filter, bytes)
+        })
+        .min_by_key(|(_, bytes)| bytes.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+        .unwrap()
+}
+
+/// Helper function to append a chunk: its length, type, data and the CRC-32 of the type and data
+///
+/// # Arguments:
+/// * `out`: the file being written
+/// * `kind`: the four letter chunk type
+/// * `data`: the contents of the chunk
+fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = checksum::crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::{adler32, crc32};
+    use crate::deflate;
+    use crate::png::{encode, parse, predict, push_chunk, SIGNATURE};
+    use crate::pnm::PnmImage;
+
+    /// Builds the image the fixtures in `fixtures/png` were generated from, using the same formula as
+    /// `make_fixtures.py`: sample `c` of the pixel at (x, y) is (x * 37 + y * 59 + c * 101) % (maxval + 1)
+    fn expected(width: u32, height: u32, depth: u32, maxval: u16, tupltype: &str) -> PnmImage {
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                samples.extend((0..depth).map(|c| ((x * 37 + y * 59 + c * 101) % (maxval as u32 + 1)) as u16));
+            }
+        }
+        PnmImage { width, height, depth, maxval, tupltype: tupltype.to_string(), samples }
+    }
+
+    /// Builds the palette image the fixtures were generated from, where the pixel at (x, y) uses color
+    /// (x + 2 * y) % `colors`, with the alpha from `alpha` when it is given
+    fn expected_palette(colors: u32, alpha: Option<&[u16]>) -> PnmImage {
+        let mut samples = Vec::new();
+        for y in 0..5 {
+            for x in 0..7 {
+                let i = (x + 2 * y) % colors;
+                samples.extend([(i * 40 % 256) as u16, (i * 90 % 256) as u16, (i * 150 % 256) as u16]);
+                if let Some(alpha) = alpha {
+                    samples.push(*alpha.get(i as usize).unwrap_or(&255));
+                }
+            }
+        }
+        let (depth, tupltype) = if alpha.is_some() { (4, "RGB_ALPHA") } else { (3, "RGB") };
+        PnmImage { width: 7, height: 5, depth, maxval: 255, tupltype: tupltype.to_string(), samples }
+    }
+
+    /// Splits a PNG file into its chunks, checking the CRC of each one along the way
+    fn chunks(file: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&file[..8], &SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < file.len() {
+            let len = u32::from_be_bytes(file[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &file[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(file[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(body), crc);
+            chunks.push((String::from_utf8(body[..4].to_vec()).unwrap(), body[4..].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_chunk_structure() {
+        let img = PnmImage { width: 3, height: 2, depth: 3, maxval: 255, tupltype: "RGB".to_string(), samples: vec![7; 18] };
+        let chunks = chunks(&encode(&img).unwrap());
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, vec![0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert!(chunks[2].1.is_empty());
+    }
+
+    #[test]
+    fn test_color_types() {
+        let ihdr = |depth: u32, maxval: u16, tupltype: &str| {
+            let samples = vec![0; 4 * depth as usize];
+            let img = PnmImage { width: 2, height: 2, depth, maxval, tupltype: tupltype.to_string(), samples };
+            let header = chunks(&encode(&img).unwrap())[0].1.clone();
+            (header[8], header[9])
+        };
+        assert_eq!(ihdr(1, 255, "GRAYSCALE"), (8, 0));
+        assert_eq!(ihdr(1, 1, "BLACKANDWHITE"), (8, 0));
+        assert_eq!(ihdr(2, 255, "GRAYSCALE_ALPHA"), (8, 4));
+        assert_eq!(ihdr(3, 65535, "RGB"), (16, 2));
+        assert_eq!(ihdr(4, 1023, "RGB_ALPHA"), (16, 6));
+    }
+
+    #[test]
+    fn test_image_data() {
+        // a single black pixel is one unfiltered scanline of two zero bytes
+        let img = PnmImage { width: 1, height: 1, depth: 1, maxval: 255, tupltype: "GRAYSCALE".to_string(), samples: vec![0] };
+        let data = chunks(&encode(&img).unwrap())[1].1.clone();
+        assert_eq!((data[0] as u16 * 256 + data[1] as u16) % 31, 0);
+        assert_eq!(&data[data.len() - 4..], &adler32(&[0, 0]).to_be_bytes());
+    }
+
+    #[test]
+    fn test_predictors() {
+        assert_eq!(predict(0, 10, 20, 30), 0);
+        assert_eq!(predict(1, 10, 20, 30), 10);
+        assert_eq!(predict(2, 10, 20, 30), 20);
+        assert_eq!(predict(3, 255, 255, 0), 255);
+        // a + b - c is 0, closest to a
+        assert_eq!(predict(4, 10, 20, 30), 10);
+        assert_eq!(predict(4, 30, 20, 10), 30);
+    }
+
+    #[test]
+    fn test_gray_fixtures() {
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray1.png")).unwrap(), expected(7, 5, 1, 1, "GRAYSCALE"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray2.png")).unwrap(), expected(7, 5, 1, 3, "GRAYSCALE"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray4.png")).unwrap(), expected(7, 5, 1, 15, "GRAYSCALE"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray8.png")).unwrap(), expected(7, 5, 1, 255, "GRAYSCALE"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray16.png")).unwrap(), expected(7, 5, 1, 65535, "GRAYSCALE"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/graya8.png")).unwrap(), expected(7, 5, 2, 255, "GRAYSCALE_ALPHA"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/graya16.png")).unwrap(), expected(7, 5, 2, 65535, "GRAYSCALE_ALPHA"));
+    }
+
+    #[test]
+    fn test_truecolor_fixtures() {
+        let rgb8 = expected(7, 5, 3, 255, "RGB");
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8.png")).unwrap(), rgb8);
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb16.png")).unwrap(), expected(7, 5, 3, 65535, "RGB"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgba8.png")).unwrap(), expected(7, 5, 4, 255, "RGB_ALPHA"));
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgba16.png")).unwrap(), expected(7, 5, 4, 65535, "RGB_ALPHA"));
+
+        // the same pixels stored interlaced, in stored deflate blocks, across many IDAT chunks,
+        // and in a larger image compressed with dynamic Huffman codes
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8_interlaced.png")).unwrap(), rgb8);
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8_stored.png")).unwrap(), rgb8);
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8_split.png")).unwrap(), rgb8);
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8_large.png")).unwrap(), expected(120, 90, 3, 255, "RGB"));
+    }
+
+    #[test]
+    fn test_palette_fixtures() {
+        assert_eq!(parse(include_bytes!("../fixtures/png/palette4.png")).unwrap(), expected_palette(11, None));
+        assert_eq!(parse(include_bytes!("../fixtures/png/palette2_interlaced.png")).unwrap(), expected_palette(4, None));
+        let trns = parse(include_bytes!("../fixtures/png/palette8_trns.png")).unwrap();
+        assert_eq!(trns, expected_palette(11, Some(&[0, 128, 255, 64])));
+        assert_eq!(parse(include_bytes!("../fixtures/png/palette1.png")).unwrap(), expected_palette(2, None));
+    }
+
+    #[test]
+    fn test_transparent_color_fixtures() {
+        let gray = expected(7, 5, 1, 255, "GRAYSCALE");
+        let alpha: Vec<u16> = gray.samples.iter().map(|&v| if v == 59 { 0 } else { 255 }).collect();
+        assert_eq!(parse(include_bytes!("../fixtures/png/gray8_trns.png")).unwrap(), gray.with_alpha(&alpha));
+
+        let rgb = expected(7, 5, 3, 255, "RGB");
+        let alpha: Vec<u16> = rgb.samples.chunks_exact(3).map(|p| if p == [0, 101, 202] { 0 } else { 255 }).collect();
+        assert_eq!(parse(include_bytes!("../fixtures/png/rgb8_trns.png")).unwrap(), rgb.with_alpha(&alpha));
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        for img in [expected(9, 4, 3, 255, "RGB"), expected(5, 6, 2, 65535, "GRAYSCALE_ALPHA")] {
+            assert_eq!(parse(&encode(&img).unwrap()).unwrap(), img);
+        }
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let good = include_bytes!("../fixtures/png/rgb8.png");
+        assert!(parse(&good[..good.len() - 20]).is_err());
+        let mut corrupted = good.to_vec();
+        corrupted[20] ^= 1;
+        assert!(parse(&corrupted).is_err());
+        assert!(parse(b"\x89PNG\r\n\x1a\n").is_err());
+        assert!(parse(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        // an IHDR chunk for a 16 bit RGBA image, followed by `len` bytes of zeros as image data
+        let png = |width: u32, height: u32, len: usize| {
+            let mut header = width.to_be_bytes().to_vec();
+            header.extend_from_slice(&height.to_be_bytes());
+            header.extend_from_slice(&[16, 6, 0, 0, 0]);
+            let mut out = SIGNATURE.to_vec();
+            push_chunk(&mut out, b"IHDR", &header);
+            push_chunk(&mut out, b"IDAT", &deflate::zlib_compress(&vec![0; len]));
+            push_chunk(&mut out, b"IEND", &[]);
+            out
+        };
+        assert!(parse(&png(1, 1, 9)).is_ok());
+        assert_eq!(parse(&png(2, 1, 10)).unwrap_err(), "image data holds 10 bytes, but a 2 x 1 image needs 17");
+        // a huge image is rejected without allocating room for its samples
+        assert_eq!(parse(&png(200000, 200000, 10)).unwrap_err(), "image data holds 10 bytes, but a 200000 x 200000 image needs 320000200000");
+        // image data that inflates to more than the image needs is rejected as soon as it outgrows it
+        let oversized = png(1, 1, 1 << 20);
+        assert!(oversized.len() < 20_000);
+        assert_eq!(parse(&oversized).unwrap_err(), "image data: deflate stream holds more than the 9 bytes expected");
+    }
+}
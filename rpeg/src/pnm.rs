@@ -0,0 +1,620 @@
+use csc411_image::{Rgb, RgbImage};
+use std::io::Write;
+use crate::image_file;
+
+/// The Netpbm formats, each named after the magic number at the start of its files
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// `P1`: bitmap with samples written as ASCII digits
+    PlainPbm,
+    /// `P2`: graymap with samples written as ASCII numbers
+    PlainPgm,
+    /// `P3`: pixmap with samples written as ASCII numbers
+    PlainPpm,
+    /// `P4`: bitmap with 8 samples packed into each byte
+    RawPbm,
+    /// `P5`: graymap with binary samples
+    RawPgm,
+    /// `P6`: pixmap with binary samples
+    RawPpm,
+    /// `P7`: arbitrary map (PAM) with binary samples and a `TUPLTYPE`
+    Pam,
+}
+
+impl Format {
+    /// Returns the magic number that starts files in this format
+    ///
+    /// # Arguments:
+    /// * `self`: the format
+    pub fn magic(self) -> &'static str {
+        match self {
+            Format::PlainPbm => "P1",
+            Format::PlainPgm => "P2",
+            Format::PlainPpm => "P3",
+            Format::RawPbm => "P4",
+            Format::RawPgm => "P5",
+            Format::RawPpm => "P6",
+            Format::Pam => "P7",
+        }
+    }
+
+    /// Returns the binary format best suited to `img`: PBM, PGM or PPM when the image fits one of them,
+    /// and PAM otherwise
+    ///
+    /// # Arguments:
+    /// * `img`: the image to be written
+    pub fn for_image(img: &PnmImage) -> Self {
+        match (img.depth, img.maxval, img.tupltype.as_str()) {
+            (1, 1, "BLACKANDWHITE") => Format::RawPbm,
+            (1, _, "GRAYSCALE") => Format::RawPgm,
+            (3, _, "RGB") => Format::RawPpm,
+            _ => Format::Pam,
+        }
+    }
+}
+
+/// An image in any of the Netpbm formats. Every pixel is a tuple of `depth` samples between 0 and `maxval`,
+/// stored interleaved in row major order. Bitmaps follow the PAM convention, where 0 is black and 1 is white.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PnmImage {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub maxval: u16,
+    pub tupltype: String,
+    pub samples: Vec<u16>,
+}
+
+impl PnmImage {
+    /// Returns a color image holding the pixels of the image. Grayscale images copy each gray value into all three
+    /// colors, and any alpha channel is left out.
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the image
+    pub fn to_rgb_image(&self) -> RgbImage {
+        let depth = self.depth as usize;
+        let pixels = self.samples.chunks_exact(depth)
+            .map(|tuple| match depth {
+                1 | 2 => Rgb { red: tuple[0], green: tuple[0], blue: tuple[0] },
+                _ => Rgb { red: tuple[0], green: tuple[1], blue: tuple[2] },
+            })
+            .collect();
+        RgbImage {
+            pixels,
+            width: self.width,
+            height: self.height,
+            denominator: self.maxval,
+        }
+    }
+
+    /// Returns a PPM image holding the pixels of `img`
+    ///
+    /// # Arguments:
+    /// * `img`: a reference to a color image
+    pub fn from_rgb_image(img: &RgbImage) -> Self {
+        PnmImage {
+            width: img.width,
+            height: img.height,
+            depth: 3,
+            maxval: img.denominator,
+            tupltype: "RGB".to_string(),
+            samples: img.pixels.iter().flat_map(|p| [p.red, p.green, p.blue]).collect(),
+        }
+    }
+
+    /// Returns a PGM image holding the red value of every pixel in `img`, for images whose colors are all equal
+    ///
+    /// # Arguments:
+    /// * `img`: a reference to a color image with gray pixels
+    pub fn gray_from_rgb_image(img: &RgbImage) -> Self {
+        PnmImage {
+            width: img.width,
+            height: img.height,
+            depth: 1,
+            maxval: img.denominator,
+            tupltype: "GRAYSCALE".to_string(),
+            samples: img.pixels.iter().map(|p| p.red).collect(),
+        }
+    }
+
+    /// Returns true iff the tuple type of the image includes an alpha channel
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the image
+    pub fn has_alpha(&self) -> bool {
+        self.tupltype.ends_with("_ALPHA")
+    }
+
+    /// Returns the alpha value of every pixel in row major order, or None if the image has no alpha channel.
+    /// Alpha is always the last sample of each pixel.
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the image
+    pub fn alpha(&self) -> Option<Vec<u16>> {
+        if !self.has_alpha() {
+            return None;
+        }
+        Some(self.samples.chunks_exact(self.depth as usize).map(|tuple| tuple[tuple.len() - 1]).collect())
+    }
+
+    /// Returns the image with `alpha` added as a final sample to every pixel, turning a `GRAYSCALE` or `RGB`
+    /// image into a `GRAYSCALE_ALPHA` or `RGB_ALPHA` one
+    ///
+    /// # Arguments:
+    /// * `self`: the image without alpha
+    /// * `alpha`: the alpha value of every pixel in row major order
+    pub fn with_alpha(self, alpha: &[u16]) -> Self {
+        let depth = self.depth as usize;
+        let samples = self.samples.chunks_exact(depth)
+            .zip(alpha)
+            .flat_map(|(tuple, &value)| tuple.iter().copied().chain(std::iter::once(value)))
+            .collect();
+        PnmImage {
+            depth: self.depth + 1,
+            tupltype: format!("{}_ALPHA", self.tupltype),
+            samples,
+            ..self
+        }
+    }
+}
+
+/// Reads a Netpbm image from the file named `filename`, or standard input
+///
+/// # Arguments:
+/// * `filename`: the file name received from the user. Input is None iff the user does not enter
+///   a filename as a command line argument
+pub fn read(filename: Option<&str>) -> Result<PnmImage, String> {
+    parse(&image_file::read_bytes(filename)?)
+}
+
+/// Writes `img` in the format `format` to the file named `filename`, or standard output
+///
+/// # Arguments:
+/// * `img`: the image to write
+/// * `format`: the Netpbm format to write it in
+/// * `filename`: the file to write to, or None for standard output
+pub fn write(img: &PnmImage, format: Format, filename: Option<&str>) -> Result<(), String> {
+    let bytes = encode(img, format)?;
+    match filename {
+        Some(name) => std::fs::write(name, bytes).map_err(|e| format!("{}: {}", name, e)),
+        None => std::io::stdout().lock().write_all(&bytes).map_err(|e| e.to_string()),
+    }
+}
+
+/// Parses a Netpbm image in any of the formats `P1` through `P7` out of the contents of a file.
+/// Errors name the line of the header, or the row and column of the raster, where the problem was found.
+///
+/// # Arguments:
+/// * `bytes`: the full contents of the file
+pub fn parse(bytes: &[u8]) -> Result<PnmImage, String> {
+    let mut cursor = Cursor { bytes, pos: 0, line: 1 };
+    let format = match bytes.get(..2) {
+        Some(b"P1") => Format::PlainPbm,
+        Some(b"P2") => Format::PlainPgm,
+        Some(b"P3") => Format::PlainPpm,
+        Some(b"P4") => Format::RawPbm,
+        Some(b"P5") => Format::RawPgm,
+        Some(b"P6") => Format::RawPpm,
+        Some(b"P7") => Format::Pam,
+        _ => return Err("not a Netpbm image: missing magic number P1 through P7".to_string()),
+    };
+    cursor.pos = 2;
+
+    let (width, height, depth, maxval, tupltype) = match format {
+        Format::Pam => parse_pam_header(&mut cursor)?,
+        _ => {
+            let width = cursor.number("width")?;
+            let height = cursor.number("height")?;
+            let (depth, maxval, tupltype) = match format {
+                Format::PlainPbm | Format::RawPbm => (1, 1, "BLACKANDWHITE"),
+                Format::PlainPgm | Format::RawPgm => (1, cursor.number("maxval")?, "GRAYSCALE"),
+                _ => (3, cursor.number("maxval")?, "RGB"),
+            };
+            // a single whitespace character separates the header from a binary raster
+            if !matches!(format, Format::PlainPbm | Format::PlainPgm | Format::PlainPpm) {
+                match bytes.get(cursor.pos) {
+                    Some(c) if c.is_ascii_whitespace() => cursor.pos += 1,
+                    _ => return Err(format!("line {}: expected whitespace after the header", cursor.line)),
+                }
+            }
+            (width, height, depth, maxval, tupltype.to_string())
+        }
+    };
+
+    if width == 0 || height == 0 {
+        return Err(format!("image dimensions must be positive, found {} x {}", width, height));
+    }
+    if maxval == 0 || maxval > 65535 {
+        return Err(format!("maxval must be between 1 and 65535, found {}", maxval));
+    }
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(depth as usize))
+        .ok_or("image dimensions are too large")?;
+
+    let maxval = maxval as u16;
+    let raster = &bytes[cursor.pos..];
+    let samples = match format {
+        Format::PlainPbm => parse_plain_bits(&mut cursor, width, count)?,
+        Format::PlainPgm | Format::PlainPpm => {
+            let mut samples = Vec::with_capacity(count.min(raster.len()));
+            for idx in 0..count {
+                let value = cursor.number(&sample_name(idx, width, depth))?;
+                samples.push(check_sample(value, maxval, idx, width, depth)?);
+            }
+            samples
+        }
+        Format::RawPbm => {
+            let row_bytes = (width as usize).div_ceil(8);
+            let expected = row_bytes * height as usize;
+            if raster.len() < expected {
+                return Err(format!("raster is truncated: expected {} bytes, found {}", expected, raster.len()));
+            }
+            // a set bit is black, which is sample 0
+            (0..count)
+                .map(|idx| {
+                    let (row, col) = (idx / width as usize, idx % width as usize);
+                    let byte = raster[row * row_bytes + col / 8];
+                    ((byte >> (7 - col % 8)) & 1 == 0) as u16
+                })
+                .collect()
+        }
+        _ => {
+            let sample_bytes = if maxval < 256 { 1 } else { 2 };
+            let expected = count.checked_mul(sample_bytes).ok_or("image dimensions are too large")?;
+            if raster.len() < expected {
+                return Err(format!("raster is truncated: expected {} bytes, found {}", expected, raster.len()));
+            }
+            let mut samples = Vec::with_capacity(count);
+            for (idx, chunk) in raster[..expected].chunks_exact(sample_bytes).enumerate() {
+                let value = chunk.iter().fold(0_u32, |v, &b| (v << 8) | b as u32);
+                samples.push(check_sample(value, maxval, idx, width, depth)?);
+            }
+            samples
+        }
+    };
+
+    Ok(PnmImage { width, height, depth, maxval, tupltype, samples })
+}
+
+/// Returns the contents of a file holding `img` in the format `format`. Returns an error if the image
+/// does not fit the format, such as a color image written as a PGM.
+///
+/// # Arguments:
+/// * `img`: the image to write
+/// * `format`: the Netpbm format to write it in
+pub fn encode(img: &PnmImage, format: Format) -> Result<Vec<u8>, String> {
+    let expected_depth = match format {
+        Format::PlainPbm | Format::RawPbm | Format::PlainPgm | Format::RawPgm => 1,
+        Format::PlainPpm | Format::RawPpm => 3,
+        Format::Pam => img.depth,
+    };
+    if img.depth != expected_depth {
+        return Err(format!("a {} image needs a depth of {}, found {}", format.magic(), expected_depth, img.depth));
+    }
+    if matches!(format, Format::PlainPbm | Format::RawPbm) && img.maxval != 1 {
+        return Err(format!("a {} image needs a maxval of 1, found {}", format.magic(), img.maxval));
+    }
+
+    let mut out = match format {
+        Format::PlainPbm | Format::RawPbm => format!("{}\n{} {}\n", format.magic(), img.width, img.height),
+        Format::Pam => format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+            img.width, img.height, img.depth, img.maxval, img.tupltype
+        ),
+        _ => format!("{}\n{} {}\n{}\n", format.magic(), img.width, img.height, img.maxval),
+    }.into_bytes();
+
+    let row_len = (img.width * img.depth) as usize;
+    match format {
+        Format::PlainPbm | Format::PlainPgm | Format::PlainPpm => {
+            // plain formats keep their lines to at most 70 characters
+            for row in img.samples.chunks(row_len) {
+                let mut line = String::new();
+                for &sample in row {
+                    let value = match format {
+                        Format::PlainPbm => (sample == 0) as u16,
+                        _ => sample,
+                    }.to_string();
+                    if !line.is_empty() && line.len() + value.len() + 1 > 70 {
+                        out.extend_from_slice(line.as_bytes());
+                        out.push(b'\n');
+                        line.clear();
+                    }
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(&value);
+                }
+                out.extend_from_slice(line.as_bytes());
+                out.push(b'\n');
+            }
+        }
+        Format::RawPbm => {
+            for row in img.samples.chunks(row_len) {
+                for byte_samples in row.chunks(8) {
+                    let byte = byte_samples.iter()
+                        .enumerate()
+                        .fold(0_u8, |byte, (bit, &sample)| byte | (((sample == 0) as u8) << (7 - bit)));
+                    out.push(byte);
+                }
+            }
+        }
+        _ => {
+            for &sample in img.samples.iter() {
+                if img.maxval < 256 {
+                    out.push(sample as u8);
+                } else {
+                    out.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Helper struct for walking through a Netpbm header while keeping track of the current line
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Cursor<'_> {
+    /// Moves past any whitespace and comments, counting the lines passed along the way
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.bytes.get(self.pos) {
+            if c == b'#' {
+                while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                if c == b'\n' {
+                    self.line += 1;
+                }
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Returns the next unsigned number, or an error naming `what` was expected
+    ///
+    /// # Arguments:
+    /// * `what`: a description of the value being read, used in error messages
+    fn number(&mut self, what: &str) -> Result<u32, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|c| !c.is_ascii_whitespace() && *c != b'#') {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(format!("line {}: expected {}, found end of file", self.line, what));
+        }
+        let token = String::from_utf8_lossy(&self.bytes[start..self.pos]);
+        token.parse().map_err(|_| format!("line {}: expected {}, found '{}'", self.line, what, token))
+    }
+}
+
+/// Helper function to read the header of a PAM file, which is made of `KEYWORD value` lines ending in `ENDHDR`.
+/// Returns the tuple (`width`, `height`, `depth`, `maxval`, `tupltype`).
+///
+/// # Arguments:
+/// * `cursor`: a cursor positioned just after the magic number
+fn parse_pam_header(cursor: &mut Cursor) -> Result<(u32, u32, u32, u32, String), String> {
+    let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+    let mut tupltype: Vec<String> = Vec::new();
+    loop {
+        let rest = &cursor.bytes[cursor.pos..];
+        let len = rest.iter().position(|&b| b == b'\n')
+            .ok_or(format!("line {}: header ends before ENDHDR", cursor.line))?;
+        let line = String::from_utf8_lossy(&rest[..len]).trim().to_string();
+        let line_number = cursor.line;
+        cursor.pos += len + 1;
+        cursor.line += 1;
+
+        let (keyword, value) = line.split_once(char::is_whitespace).unwrap_or((line.as_str(), ""));
+        let value = value.trim();
+        let number = |name: &str| value.parse::<u32>()
+            .map_err(|_| format!("line {}: expected a number after {}, found '{}'", line_number, name, value));
+        match keyword {
+            "" => (),
+            _ if keyword.starts_with('#') => (),
+            "WIDTH" => width = Some(number("WIDTH")?),
+            "HEIGHT" => height = Some(number("HEIGHT")?),
+            "DEPTH" => depth = Some(number("DEPTH")?),
+            "MAXVAL" => maxval = Some(number("MAXVAL")?),
+            "TUPLTYPE" => tupltype.push(value.to_string()),
+            "ENDHDR" => break,
+            _ => return Err(format!("line {}: unknown PAM header keyword '{}'", line_number, keyword)),
+        }
+    }
+
+    let missing = |name: &str| format!("PAM header is missing {}", name);
+    let depth = depth.ok_or(missing("DEPTH"))?;
+    if depth == 0 {
+        return Err("PAM depth must be positive".to_string());
+    }
+    // the standard tuple types say how many samples each pixel has, and which of them is alpha, so a depth that
+    // disagrees would have its samples read as the wrong channels
+    let tupltype = tupltype.join(" ");
+    let expected = match tupltype.as_str() {
+        "BLACKANDWHITE" | "GRAYSCALE" => Some(1),
+        "BLACKANDWHITE_ALPHA" | "GRAYSCALE_ALPHA" => Some(2),
+        "RGB" => Some(3),
+        "RGB_ALPHA" => Some(4),
+        _ => None,
+    };
+    if let Some(expected) = expected.filter(|&expected| expected != depth) {
+        return Err(format!("PAM tuple type {} needs a depth of {}, found {}", tupltype, expected, depth));
+    }
+    Ok((
+        width.ok_or(missing("WIDTH"))?,
+        height.ok_or(missing("HEIGHT"))?,
+        depth,
+        maxval.ok_or(missing("MAXVAL"))?,
+        tupltype,
+    ))
+}
+
+/// Helper function to read the raster of a `P1` bitmap, where every sample is a single `0` or `1` digit
+/// that may or may not be separated from its neighbors by whitespace
+///
+/// # Arguments:
+/// * `cursor`: a cursor positioned at the start of the raster
+/// * `width`: the width of the image
+/// * `count`: the number of samples in the image
+fn parse_plain_bits(cursor: &mut Cursor, width: u32, count: usize) -> Result<Vec<u16>, String> {
+    // every sample takes at least one byte, so the header cannot make this reserve more than the file holds
+    let mut samples = Vec::with_capacity(count.min(cursor.bytes.len() - cursor.pos));
+    for idx in 0..count {
+        cursor.skip_whitespace();
+        match cursor.bytes.get(cursor.pos) {
+            // a 1 is black, which is sample 0
+            Some(b'0') => samples.push(1),
+            Some(b'1') => samples.push(0),
+            found => return Err(format!(
+                "line {}: expected {}, found {}",
+                cursor.line,
+                sample_name(idx, width, 1),
+                found.map_or("end of file".to_string(), |c| format!("'{}'", *c as char))
+            )),
+        }
+        cursor.pos += 1;
+    }
+    Ok(samples)
+}
+
+/// Helper function to describe where sample `idx` lies in the raster, for use in error messages
+///
+/// # Arguments:
+/// * `idx`: the index of the sample in the raster
+/// * `width`: the width of the image
+/// * `depth`: the number of samples in each pixel
+fn sample_name(idx: usize, width: u32, depth: u32) -> String {
+    let pixel = idx / depth as usize;
+    format!("the sample at row {}, column {}", pixel / width as usize, pixel % width as usize)
+}
+
+/// Helper function that returns `value` as a sample, or an error if it is larger than `maxval`
+///
+/// # Arguments:
+/// * `value`: the sample read from the raster
+/// * `maxval`: the largest sample allowed by the header
+/// * `idx`, `width`, `depth`: the position of the sample, used in error messages
+fn check_sample(value: u32, maxval: u16, idx: usize, width: u32, depth: u32) -> Result<u16, String> {
+    if value > maxval as u32 {
+        Err(format!("{} is {}, which exceeds the maxval of {}", sample_name(idx, width, depth), value, maxval))
+    } else {
+        Ok(value as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pnm::{encode, parse, Format, PnmImage};
+
+    fn sample_image(depth: u32, maxval: u16, tupltype: &str) -> PnmImage {
+        let samples = (0..3 * 2 * depth).map(|n| ((n * 37) % (maxval as u32 + 1)) as u16).collect();
+        PnmImage { width: 3, height: 2, depth, maxval, tupltype: tupltype.to_string(), samples }
+    }
+
+    #[test]
+    fn test_plain_formats() {
+        let pbm = parse(b"P1\n# a comment\n3 2\n101\n0 1 0\n").unwrap();
+        assert_eq!(pbm.samples, vec![0, 1, 0, 1, 0, 1]);
+        assert_eq!((pbm.maxval, pbm.tupltype.as_str()), (1, "BLACKANDWHITE"));
+
+        let pgm = parse(b"P2 2 1 #size\n 300\n299 0").unwrap();
+        assert_eq!((pgm.width, pgm.maxval, pgm.samples.clone()), (2, 300, vec![299, 0]));
+
+        let ppm = parse(b"P3\n1 1\n255\n1 2 3\n").unwrap();
+        assert_eq!((ppm.depth, ppm.samples.clone()), (3, vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_raw_formats() {
+        let pbm = parse(b"P4\n10 1\n\x80\x40").unwrap();
+        assert_eq!(pbm.samples, vec![0, 1, 1, 1, 1, 1, 1, 1, 1, 0]);
+
+        let pgm = parse(b"P5\n2 1\n65535\n\x01\x02\xff\xff").unwrap();
+        assert_eq!(pgm.samples, vec![0x0102, 0xffff]);
+
+        let ppm = parse(b"P6\n1 1\n255\n\x0a\x0b\x0c").unwrap();
+        assert_eq!(ppm.samples, vec![10, 11, 12]);
+    }
+
+    #[test]
+    fn test_pam() {
+        let pam = parse(b"P7\nWIDTH 1\nHEIGHT 1\n# comment\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n\x01\x02\x03\x04")
+            .unwrap();
+        assert_eq!((pam.depth, pam.tupltype.as_str(), pam.samples.clone()), (4, "RGB_ALPHA", vec![1, 2, 3, 4]));
+        assert!(pam.has_alpha());
+        assert_eq!(pam.alpha(), Some(vec![4]));
+    }
+
+    #[test]
+    fn test_with_alpha() {
+        let gray = sample_image(1, 255, "GRAYSCALE");
+        let with_alpha = gray.clone().with_alpha(&[9; 6]);
+        assert_eq!((with_alpha.depth, with_alpha.tupltype.as_str()), (2, "GRAYSCALE_ALPHA"));
+        assert_eq!(with_alpha.alpha(), Some(vec![9; 6]));
+        assert_eq!(with_alpha.to_rgb_image().pixels[1].red, gray.samples[1]);
+        assert_eq!(gray.alpha(), None);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let cases = [
+            (sample_image(1, 1, "BLACKANDWHITE"), Format::PlainPbm),
+            (sample_image(1, 1, "BLACKANDWHITE"), Format::RawPbm),
+            (sample_image(1, 255, "GRAYSCALE"), Format::PlainPgm),
+            (sample_image(1, 1000, "GRAYSCALE"), Format::RawPgm),
+            (sample_image(3, 255, "RGB"), Format::PlainPpm),
+            (sample_image(3, 65535, "RGB"), Format::RawPpm),
+            (sample_image(4, 255, "RGB_ALPHA"), Format::Pam),
+        ];
+        for (img, format) in cases {
+            let bytes = encode(&img, format).unwrap();
+            assert_eq!(parse(&bytes).unwrap(), img, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_format_for_image() {
+        assert_eq!(Format::for_image(&sample_image(1, 1, "BLACKANDWHITE")), Format::RawPbm);
+        assert_eq!(Format::for_image(&sample_image(3, 255, "RGB")), Format::RawPpm);
+        assert_eq!(Format::for_image(&sample_image(2, 255, "GRAYSCALE_ALPHA")), Format::Pam);
+        assert!(encode(&sample_image(3, 255, "RGB"), Format::RawPgm).is_err());
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(parse(b"P6\n12 x\n255\n").unwrap_err(), "line 2: expected height, found 'x'");
+        assert_eq!(parse(b"P2\n# c\n1 1\n3\n4").unwrap_err(), "the sample at row 0, column 0 is 4, which exceeds the maxval of 3");
+        assert_eq!(parse(b"P5\n2 2\n255\n\x00").unwrap_err(), "raster is truncated: expected 4 bytes, found 1");
+        assert_eq!(parse(b"P1\n2 1\n1 2").unwrap_err(), "line 3: expected the sample at row 0, column 1, found '2'");
+        assert_eq!(parse(b"P7\nWIDTH 1\nHEIGHT 1\nMAXVAL 1\nENDHDR\n").unwrap_err(), "PAM header is missing DEPTH");
+        assert_eq!(parse(b"P7\nWIDE 1\n").unwrap_err(), "line 2: unknown PAM header keyword 'WIDE'");
+        let pam = |depth: u32, tupltype: &str| parse(format!(
+            "P7\nWIDTH 1\nHEIGHT 1\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n\x01\x02\x03\x04", depth, tupltype
+        ).as_bytes());
+        assert_eq!(pam(3, "RGB_ALPHA").unwrap_err(), "PAM tuple type RGB_ALPHA needs a depth of 4, found 3");
+        assert_eq!(pam(1, "RGB_ALPHA").unwrap_err(), "PAM tuple type RGB_ALPHA needs a depth of 4, found 1");
+        assert_eq!(pam(4, "RGB").unwrap_err(), "PAM tuple type RGB needs a depth of 3, found 4");
+        assert_eq!(pam(1, "GRAYSCALE_ALPHA").unwrap_err(), "PAM tuple type GRAYSCALE_ALPHA needs a depth of 2, found 1");
+        assert_eq!(pam(2, "BLACKANDWHITE").unwrap_err(), "PAM tuple type BLACKANDWHITE needs a depth of 1, found 2");
+        assert_eq!(pam(2, "GRAYSCALE").unwrap_err(), "PAM tuple type GRAYSCALE needs a depth of 1, found 2");
+        assert!(parse(b"P6\n0 5\n255\n").is_err());
+        assert!(parse(b"JFIF").is_err());
+
+        // headers claiming huge images are rejected without reserving memory for them
+        assert_eq!(parse(b"P2\n65535 65535\n255\n1").unwrap_err(), "line 4: expected the sample at row 0, column 1, found end of file");
+        assert_eq!(parse(b"P1\n65535 65535\n1").unwrap_err(), "line 3: expected the sample at row 0, column 1, found end of file");
+        assert_eq!(
+            parse(b"P7\nWIDTH 4294967295\nHEIGHT 4294967295\nDEPTH 4294967295\nMAXVAL 255\nENDHDR\n").unwrap_err(),
+            "image dimensions are too large"
+        );
+    }
+}
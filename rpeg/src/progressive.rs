@@ -0,0 +1,231 @@
+use std::iter;
+use bitpack::bitpack;
+use crate::bits::{read_bits, BitWriter};
+use crate::quantize::WordLayout;
+
+/// The number of coefficients refined by every pass after the first: `b`, `c` and `d`
+const REFINED: usize = 3;
+
+/// Returns the number of passes in a progressive image whose words have the layout `layout`: the DC pass holding
+/// `a` and the chroma of every block, and then one pass for each bit of `b`, `c` and `d`, from the most significant
+///
+/// # Arguments:
+/// * `layout`: the layout of the words
+pub fn pass_count(layout: WordLayout) -> usize {
+    1 + layout.bcd_bits as usize
+}
+
+/// Returns the number of bytes in each pass of an image of `blocks` blocks, or None if they do not fit in a `usize`.
+/// Every pass starts on a byte boundary.
+///
+/// # Arguments:
+/// * `blocks`: the number of blocks in the image
+/// * `layout`: the layout of the words
+pub fn pass_lens(blocks: usize, layout: WordLayout) -> Option<Vec<usize>> {
+    let dc = blocks.checked_mul(dc_bits(layout) as usize)?.div_ceil(8);
+    let plane = blocks.checked_mul(REFINED)?.div_ceil(8);
+    Some(iter::once(dc).chain(iter::repeat_n(plane, layout.bcd_bits as usize)).collect())
+}
+
+/// Returns the number of bytes the passes of an image of `blocks` blocks take up, or None if that does not fit in
+/// a `usize`
+///
+/// # Arguments:
+/// * `blocks`: the number of blocks in the image
+/// * `layout`: the layout of the words
+pub fn encoded_len(blocks: usize, layout: WordLayout) -> Option<usize> {
+    pass_lens(blocks, layout)?.into_iter().try_fold(0_usize, |total, len| total.checked_add(len))
+}
+
+/// Returns the number of passes that are complete in the first `len` bytes of the passes of an image of `blocks` blocks
+///
+/// # Arguments:
+/// * `len`: the number of bytes of the passes that are present
+/// * `blocks`: the number of blocks in the image
+/// * `layout`: the layout of the words
+pub fn complete_passes(len: usize, blocks: usize, layout: WordLayout) -> usize {
+    let mut end = 0;
+    pass_lens(blocks, layout).unwrap_or_default().into_iter()
+        .take_while(|pass| {
+            end += pass;
+            end <= len
+        })
+        .count()
+}
+
+/// Appends the passes holding `words` to `out`
+///
+/// # Arguments:
+/// * `words`: the words of the image, in row major order
+/// * `layout`: the layout the words were packed with
+/// * `out`: the bytes of the file so far
+pub fn write(words: &[u64], layout: WordLayout, out: &mut Vec<u8>) {
+    let chroma = 2 * layout.chroma_bits;
+    let mut dc = BitWriter::new(out);
+    for &word in words {
+        dc.push(word >> (REFINED as u64 * layout.bcd_bits + chroma), layout.a_bits);
+        dc.push(word, chroma);
+    }
+    for bit in (0..layout.bcd_bits).rev() {
+        let mut plane = BitWriter::new(out);
+        for &word in words {
+            for lsb in field_lsbs(layout) {
+                plane.push(word >> (lsb + bit), 1);
+            }
+        }
+    }
+}
+
+/// Returns the words of an image of `blocks` blocks held in `bytes`, which may be any prefix of its passes.
+/// The bits of `b`, `c` and `d` that have not arrived are filled in to give the value closest to zero that the bits
+/// which have allow, and blocks whose DC has not arrived are a flat mid gray, so that every prefix is a valid image
+/// that gets closer to the whole one as more of it arrives.
+///
+/// # Arguments:
+/// * `bytes`: the passes, or the part of them that has arrived
+/// * `blocks`: the number of blocks in the image, whose passes must fit in a `usize`
+/// * `layout`: the layout the words were packed with
+pub fn read(bytes: &[u8], blocks: usize, layout: WordLayout) -> Vec<u64> {
+    let lens = pass_lens(blocks, layout).unwrap();
+    let (bcd, chroma) = (layout.bcd_bits, 2 * layout.chroma_bits);
+
+    let (pb, pr) = layout.chroma_to_indices(0.0, 0.0);
+    let gray = layout.pack(bitpack::maxu(layout.a_bits) / 2, 0, 0, 0, pb, pr);
+    let mut words = vec![gray; blocks];
+    let dc = &bytes[..bytes.len().min(lens[0])];
+    let dc_blocks = (dc.len() * 8 / dc_bits(layout) as usize).min(blocks);
+    for (idx, word) in words.iter_mut().enumerate().take(dc_blocks) {
+        let bits = read_bits(dc, idx as u64 * dc_bits(layout), dc_bits(layout));
+        *word = (bits >> chroma) << (REFINED as u64 * bcd + chroma) | (bits & bitpack::maxu(chroma));
+    }
+
+    // each block knows the bits of `b`, `c` and `d` from the planes that reached it
+    let mut known = vec![0; dc_blocks];
+    let mut start = lens[0];
+    for (plane, &len) in lens[1..].iter().enumerate().take_while(|_| dc_blocks == blocks) {
+        let pass = bytes.get(start..bytes.len().min(start + len)).unwrap_or_default();
+        let bit = bcd - 1 - plane as u64;
+        let refined = (pass.len() * 8 / REFINED).min(blocks);
+        for idx in 0..refined {
+            for (field, lsb) in field_lsbs(layout).into_iter().enumerate() {
+                words[idx] |= read_bits(pass, (idx * REFINED + field) as u64, 1) << (lsb + bit);
+            }
+            known[idx] += 1;
+        }
+        if refined < blocks {
+            break;
+        }
+        start += len;
+    }
+
+    for (word, known) in words.iter_mut().zip(known).filter(|&(_, known)| known < bcd) {
+        for lsb in field_lsbs(layout) {
+            let field = toward_zero((*word >> lsb) & bitpack::maxu(bcd), bcd, known);
+            *word = (*word & !(bitpack::maxu(bcd) << lsb)) | (field << lsb);
+        }
+    }
+    words
+}
+
+/// Helper function that returns the number of bits in the DC of every block: `a` and then both chroma indices
+///
+/// # Arguments:
+/// * `layout`: the layout of the words
+fn dc_bits(layout: WordLayout) -> u64 {
+    layout.a_bits + 2 * layout.chroma_bits
+}
+
+/// Helper function that returns the least significant bits of the `b`, `c` and `d` fields of a word
+///
+/// # Arguments:
+/// * `layout`: the layout of the words
+fn field_lsbs(layout: WordLayout) -> [u64; REFINED] {
+    let (bcd, chroma) = (layout.bcd_bits, 2 * layout.chroma_bits);
+    [2 * bcd + chroma, bcd + chroma, chroma]
+}
+
+/// Helper function that returns the `width` bit two's complement `field`, of which only the top `known` bits have
+/// arrived and the rest are 0, as the value closest to zero that those bits allow. That is the value itself for
+/// a positive field, and the one with every bit that has not arrived set for a negative field.
+///
+/// # Arguments:
+/// * `field`: the bits of the field
+/// * `width`: the number of bits in the field
+/// * `known`: the number of bits that have arrived
+fn toward_zero(field: u64, width: u64, known: u64) -> u64 {
+    match known {
+        0 => 0,
+        _ if field >> (width - 1) & 1 == 1 => field | bitpack::maxu(width - known),
+        _ => field,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progressive::{complete_passes, encoded_len, pass_count, pass_lens, read, write};
+    use crate::quantize::{GRAY_LAYOUT, RGB_LAYOUT};
+    use crate::testimages::Rng;
+
+    /// Returns the words of `blocks` random blocks with the RGB layout
+    fn random_words(blocks: usize) -> Vec<u64> {
+        let mut rng = Rng::new(7);
+        (0..blocks).map(|_| {
+            let mut coefficient = || rng.below(31) as i64 - 15;
+            let (b, c, d) = (coefficient(), coefficient(), coefficient());
+            RGB_LAYOUT.pack(rng.below(512), b, c, d, rng.below(16), rng.below(16))
+        }).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let words = random_words(21);
+        let mut bytes = Vec::new();
+        write(&words, RGB_LAYOUT, &mut bytes);
+        // 17 bits of DC and 3 bits of every plane for each block
+        assert_eq!(pass_lens(21, RGB_LAYOUT).unwrap(), vec![45, 8, 8, 8, 8, 8]);
+        assert_eq!(bytes.len(), encoded_len(21, RGB_LAYOUT).unwrap());
+        assert_eq!(read(&bytes, 21, RGB_LAYOUT), words);
+        assert_eq!(complete_passes(bytes.len(), 21, RGB_LAYOUT), pass_count(RGB_LAYOUT));
+        assert_eq!(complete_passes(bytes.len() - 1, 21, RGB_LAYOUT), 5);
+
+        let gray: Vec<u64> = (0..5).map(|n| GRAY_LAYOUT.pack(n * 100, -(n as i64), n as i64, 3, 0, 0)).collect();
+        let mut bytes = Vec::new();
+        write(&gray, GRAY_LAYOUT, &mut bytes);
+        assert_eq!(read(&bytes, 5, GRAY_LAYOUT), gray);
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let words = random_words(21);
+        let mut bytes = Vec::new();
+        write(&words, RGB_LAYOUT, &mut bytes);
+
+        // the DC alone gives every block its brightness and chroma, with flat luma
+        let dc = read(&bytes[..45], 21, RGB_LAYOUT);
+        for (&word, &whole) in dc.iter().zip(&words) {
+            let (a, b, c, d, pb, pr) = RGB_LAYOUT.unpack(word);
+            let (whole_a, _, _, _, whole_pb, whole_pr) = RGB_LAYOUT.unpack(whole);
+            assert_eq!((a, b, c, d, pb, pr), (whole_a, 0, 0, 0, whole_pb, whole_pr));
+        }
+
+        // every prefix is valid, and each coefficient moves toward its value as planes arrive
+        let mut previous = [0; 21];
+        for len in 45..=bytes.len() {
+            let partial = read(&bytes[..len], 21, RGB_LAYOUT);
+            for ((&word, &whole), error) in partial.iter().zip(&words).zip(previous.iter_mut()) {
+                let (_, b, c, d, _, _) = RGB_LAYOUT.unpack(word);
+                let (_, whole_b, whole_c, whole_d, _, _) = RGB_LAYOUT.unpack(whole);
+                let distance = (b - whole_b).abs() + (c - whole_c).abs() + (d - whole_d).abs();
+                assert!(len == 45 || distance <= *error);
+                *error = distance;
+            }
+        }
+
+        // blocks whose DC has not arrived are mid gray
+        let (pb, pr) = RGB_LAYOUT.chroma_to_indices(0.0, 0.0);
+        let partial = read(&bytes[..10], 21, RGB_LAYOUT);
+        assert_eq!(&partial[..4], &dc[..4]);
+        assert!(partial[4..].iter().all(|&word| word == RGB_LAYOUT.pack(255, 0, 0, 0, pb, pr)));
+        assert_eq!(read(&[], 21, RGB_LAYOUT), vec![RGB_LAYOUT.pack(255, 0, 0, 0, pb, pr); 21]);
+    }
+}
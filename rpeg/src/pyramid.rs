@@ -0,0 +1,295 @@
+use bitpack::bitpack;
+use crate::bits::{read_bits, BitReader, BitWriter};
+use crate::quantize::WordLayout;
+
+/// The most levels a pyramid may have, which is enough to reduce any image to a single block
+pub const MAX_LEVELS: u32 = 32;
+
+/// The words of an image of `rows` x `cols` blocks with their `a` fields held in a pyramid of `levels` levels.
+///
+/// The `a` plane is decomposed with the S-transform, the integer form of the Haar transform that `get_coefficients`
+/// applies to each block: every level splits the rows and then the columns of the low band left by the level before
+/// into averages and differences, which are exact so that the plane is rebuilt without loss. On smooth images most
+/// differences are close to zero, so every coefficient is written as a signed Exp-Golomb code, starting with the
+/// coarsest band, whose values are written as the change from the one before. The other fields of every word follow
+/// as fixed width bit fields. The codes are in order from the coarsest level to the finest, so any level of the
+/// pyramid can be rebuilt from the start of them alone.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pyramid {
+    pub rows: usize,
+    pub cols: usize,
+    pub levels: u32,
+}
+
+impl Pyramid {
+    /// Returns the dimensions of the low band left after `level` levels, which is the whole plane at level 0
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    /// * `level`: the number of levels applied, at most `levels`
+    pub fn band(&self, level: u32) -> (usize, usize) {
+        (0..level).fold((self.rows, self.cols), |(rows, cols), _| (rows.div_ceil(2), cols.div_ceil(2)))
+    }
+
+    /// Appends the words of the image, in row major order, to `out`
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    /// * `words`: the words of the image
+    /// * `layout`: the layout the words were packed with
+    /// * `out`: the bytes of the file so far
+    pub fn write(&self, words: &[u64], layout: WordLayout, out: &mut Vec<u8>) {
+        let shift = rest_bits(layout);
+        let mut plane: Vec<i64> = words.iter().map(|&word| (word >> shift) as i64).collect();
+        for level in 0..self.levels {
+            let (rows, cols) = self.band(level);
+            transform(&mut plane, rows, cols, self.cols, false);
+        }
+
+        let mut codes = BitWriter::new(out);
+        let mut previous = 0;
+        for (idx, pos) in self.order(0, self.cols).into_iter().enumerate() {
+            if idx < self.low_len() {
+                codes.push_signed(plane[pos] - previous);
+                previous = plane[pos];
+            } else {
+                codes.push_signed(plane[pos]);
+            }
+        }
+        let mut rest = BitWriter::new(out);
+        for &word in words {
+            rest.push(word, shift);
+        }
+    }
+
+    /// Returns the words of the image held in `bytes`, in row major order, or an error if they are cut short,
+    /// have bytes left over, or hold an `a` field that does not fit in the layout
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    /// * `bytes`: the codes of the pyramid followed by the other fields of every word
+    /// * `layout`: the layout the words were packed with
+    pub fn read(&self, bytes: &[u8], layout: WordLayout) -> Result<Vec<u64>, String> {
+        let (plane, used) = self.read_level(bytes, 0)?;
+        let shift = rest_bits(layout);
+        let rest = &bytes[used..];
+        let expected = (self.rows * self.cols * shift as usize).div_ceil(8);
+        if rest.len() != expected {
+            return Err(format!("expected {} bytes of fields after the pyramid, found {}", expected, rest.len()));
+        }
+        plane.iter().enumerate().map(|(idx, &a)| match u64::try_from(a) {
+            Ok(a) if a <= bitpack::maxu(layout.a_bits) => Ok((a << shift) | read_bits(rest, (idx * shift as usize) as u64, shift)),
+            _ => Err(format!("pyramid holds an a value of {}, which does not fit in {} bits", a, layout.a_bits)),
+        }).collect()
+    }
+
+    /// Returns the `a` plane at the resolution of the low band after `level` levels, in row major order, rebuilt
+    /// from the codes of that band and the coarser ones only, along with the number of bytes of codes that were read.
+    /// Each value is the average of the blocks it covers, rounded down at every level.
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    /// * `bytes`: the start of the codes of the pyramid
+    /// * `level`: the number of levels to leave applied, at most `levels`
+    pub fn read_level(&self, bytes: &[u8], level: u32) -> Result<(Vec<i64>, usize), String> {
+        let (rows, cols) = self.band(level);
+        let mut plane = vec![0; rows * cols];
+        let mut codes = BitReader::new(bytes);
+        let mut previous: i64 = 0;
+        for (idx, pos) in self.order(level, cols).into_iter().enumerate() {
+            let code = codes.read_signed().map_err(|e| format!("pyramid is damaged: {}", e))?;
+            plane[pos] = if idx < self.low_len() {
+                previous = previous.wrapping_add(code);
+                previous
+            } else {
+                code
+            };
+        }
+        for level in (level..self.levels).rev() {
+            let (rows, band_cols) = self.band(level);
+            transform(&mut plane, rows, band_cols, cols, true);
+        }
+        Ok((plane, codes.bytes_read()))
+    }
+
+    /// Helper function that returns the number of values in the coarsest band
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    fn low_len(&self) -> usize {
+        let (rows, cols) = self.band(self.levels);
+        rows * cols
+    }
+
+    /// Helper function that returns the positions of the coefficients in the order they are written, down to the
+    /// band left after `level` levels: the coarsest band, and then the differences of each level from the coarsest
+    /// to the finest, each in row major order. The positions index a plane whose rows are `stride` values apart.
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the pyramid
+    /// * `level`: the finest level to include
+    /// * `stride`: the number of values in each row of the plane
+    fn order(&self, level: u32, stride: usize) -> Vec<usize> {
+        let (low_rows, low_cols) = self.band(self.levels);
+        let mut order: Vec<usize> = (0..low_rows).flat_map(|row| (0..low_cols).map(move |col| row * stride + col)).collect();
+        for level in (level..self.levels).rev() {
+            let ((rows, cols), (low_rows, low_cols)) = (self.band(level), self.band(level + 1));
+            for row in 0..rows {
+                order.extend((0..cols).filter(|&col| row >= low_rows || col >= low_cols).map(|col| row * stride + col));
+            }
+        }
+        order
+    }
+}
+
+/// Helper function that returns the number of bits of every word below its `a` field
+///
+/// # Arguments:
+/// * `layout`: the layout of the words
+fn rest_bits(layout: WordLayout) -> u64 {
+    3 * layout.bcd_bits + 2 * layout.chroma_bits
+}
+
+/// Helper function that applies one level of the transform to each of the first `rows` rows and then each of the
+/// first `cols` columns of `plane`, whose rows are `stride` values apart, or undoes it on the columns and then the rows
+///
+/// # Arguments:
+/// * `plane`: the values
+/// * `rows`, `cols`: the dimensions of the band to transform
+/// * `stride`: the number of values in each row of the plane
+/// * `undo`: whether to undo the level rather than apply it
+fn transform(plane: &mut [i64], rows: usize, cols: usize, stride: usize, undo: bool) {
+    let step = if undo { inverse } else { forward };
+    // the rows come first when applying a level, so they come last when undoing it
+    for by_rows in [!undo, undo] {
+        if by_rows {
+            for row in 0..rows {
+                step(&mut plane[row * stride..row * stride + cols]);
+            }
+            continue;
+        }
+        let mut line = Vec::with_capacity(rows);
+        for col in 0..cols {
+            line.clear();
+            line.extend((0..rows).map(|row| plane[row * stride + col]));
+            step(&mut line);
+            for (row, &value) in line.iter().enumerate() {
+                plane[row * stride + col] = value;
+            }
+        }
+    }
+}
+
+/// Helper function that applies one level of the S-transform to `values`, leaving the averages of each pair in the
+/// first half and their differences in the second. An odd value at the end is kept as an average.
+///
+/// # Arguments:
+/// * `values`: the values to transform
+fn forward(values: &mut [i64]) {
+    let half = values.len().div_ceil(2);
+    let mut out = vec![0; values.len()];
+    for (pair, chunk) in values.chunks(2).enumerate() {
+        match *chunk {
+            [even, odd] => {
+                let difference = odd - even;
+                out[pair] = even + (difference >> 1);
+                out[half + pair] = difference;
+            }
+            [last] => out[pair] = last,
+            _ => unreachable!(),
+        }
+    }
+    values.copy_from_slice(&out);
+}
+
+/// Helper function that undoes `forward`. Crafted files can hold any values, so the arithmetic wraps rather than
+/// overflowing, and values that do not fit are rejected once the plane is rebuilt.
+///
+/// # Arguments:
+/// * `values`: the averages followed by the differences
+fn inverse(values: &mut [i64]) {
+    let half = values.len().div_ceil(2);
+    let mut out = vec![0; values.len()];
+    for pair in 0..half {
+        match values.get(half + pair) {
+            Some(&difference) => {
+                let even = values[pair].wrapping_sub(difference >> 1);
+                out[2 * pair] = even;
+                out[2 * pair + 1] = even.wrapping_add(difference);
+            }
+            None => out[2 * pair] = values[pair],
+        }
+    }
+    values.copy_from_slice(&out);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pyramid::Pyramid;
+    use crate::quantize::{GRAY_LAYOUT, RGB_LAYOUT};
+    use crate::testimages::Rng;
+
+    /// Returns the words of a smooth image of `rows` x `cols` blocks with random fields below `a`
+    fn smooth_words(rows: usize, cols: usize) -> Vec<u64> {
+        let mut rng = Rng::new(11);
+        (0..rows * cols).map(|idx| {
+            let a = 100 + ((idx / cols + idx % cols) / 2) as u64;
+            RGB_LAYOUT.pack(a, rng.below(31) as i64 - 15, 0, 1, rng.below(16), rng.below(16))
+        }).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (rows, cols, levels) in [(7, 5, 1), (7, 5, 3), (16, 16, 4), (1, 9, 32), (0, 0, 2), (6, 1, 5)] {
+            let words = smooth_words(rows, cols);
+            let pyramid = Pyramid { rows, cols, levels };
+            let mut bytes = Vec::new();
+            pyramid.write(&words, RGB_LAYOUT, &mut bytes);
+            assert_eq!(pyramid.read(&bytes, RGB_LAYOUT).unwrap(), words, "{} x {} in {} levels", rows, cols, levels);
+        }
+
+        let gray: Vec<u64> = (0..12).map(|n| GRAY_LAYOUT.pack(511 - n * 40, -3, 2, 0, 0, 0)).collect();
+        let pyramid = Pyramid { rows: 3, cols: 4, levels: 2 };
+        let mut bytes = Vec::new();
+        pyramid.write(&gray, GRAY_LAYOUT, &mut bytes);
+        assert_eq!(pyramid.read(&bytes, GRAY_LAYOUT).unwrap(), gray);
+    }
+
+    #[test]
+    fn test_smooth_planes_are_small() {
+        // the 9 bit a fields of a 32 x 32 gradient take a fraction of their fixed width
+        let words = smooth_words(32, 32);
+        let pyramid = Pyramid { rows: 32, cols: 32, levels: 5 };
+        let mut bytes = Vec::new();
+        pyramid.write(&words, RGB_LAYOUT, &mut bytes);
+        let fields = 32 * 32 * 23 / 8;
+        assert!(bytes.len() - fields < 32 * 32 * 9 / 8 / 3, "{} bytes of codes", bytes.len() - fields);
+    }
+
+    #[test]
+    fn test_levels() {
+        // each level of the plane is the average of the blocks it covers
+        let words: Vec<u64> = (0..16).map(|n| RGB_LAYOUT.pack(n * 10, 0, 0, 0, 0, 0)).collect();
+        let pyramid = Pyramid { rows: 4, cols: 4, levels: 2 };
+        let mut bytes = Vec::new();
+        pyramid.write(&words, RGB_LAYOUT, &mut bytes);
+        assert_eq!(pyramid.band(1), (2, 2));
+        assert_eq!(pyramid.read_level(&bytes, 1).unwrap().0, vec![25, 45, 105, 125]);
+        assert_eq!(pyramid.read_level(&bytes, 2).unwrap().0, vec![75]);
+        assert_eq!(pyramid.read_level(&bytes, 0).unwrap().0, (0..16).map(|n| n * 10).collect::<Vec<i64>>());
+    }
+
+    #[test]
+    fn test_damage() {
+        let words = smooth_words(4, 4);
+        let pyramid = Pyramid { rows: 4, cols: 4, levels: 2 };
+        let mut bytes = Vec::new();
+        pyramid.write(&words, RGB_LAYOUT, &mut bytes);
+        assert!(pyramid.read(&bytes[..bytes.len() - 1], RGB_LAYOUT).unwrap_err().starts_with("expected 46 bytes of fields"));
+        assert!(pyramid.read(&bytes[..3], RGB_LAYOUT).unwrap_err().starts_with("pyramid is damaged"));
+        // a first code of -1 gives an a value below zero
+        let mut negative = vec![0b0100_0000];
+        negative.extend_from_slice(&bytes[1..]);
+        assert!(pyramid.read(&negative, RGB_LAYOUT).is_err());
+    }
+}
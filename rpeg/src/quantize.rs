@@ -1,5 +1,6 @@
 use bitpack::bitpack;
 use csc411_arith;
+use crate::simd::{self, Isa};
 
 /// Returns a tuple of 4 coefficients based on the luma from a 2 X 2 matrix of pixels, `y1`, `y2`, `y3`, and `y4`,
 /// the coefficients represent the average brightness of the matrix, 
@@ -21,6 +22,85 @@ pub fn get_coefficients(y1: f64, y2: f64, y3: f64, y4: f64) -> (f64, f64, f64, f
     (a, b, c, d)
 }
 
+/// The luma of many 2 X 2 matrices of pixels, held as a structure of arrays with one vector per position in the matrix,
+/// so that batches of blocks can be transformed with vector instructions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LumaPlanes {
+    pub y1: Vec<f64>,
+    pub y2: Vec<f64>,
+    pub y3: Vec<f64>,
+    pub y4: Vec<f64>,
+}
+
+/// The coefficients `a`, `b`, `c` and `d` of many 2 X 2 matrices of pixels, held as a structure of arrays with one
+/// vector per coefficient
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoefficientPlanes {
+    pub a: Vec<f64>,
+    pub b: Vec<f64>,
+    pub c: Vec<f64>,
+    pub d: Vec<f64>,
+}
+
+/// Returns the coefficients of every block in `luma`, the batch form of `get_coefficients`, using the widest vector
+/// instructions the processor supports. The results are identical to calling `get_coefficients` on each block.
+///
+/// # Arguments:
+/// * `luma`: the planes of the blocks, which must all have the same length
+pub fn get_coefficient_planes(luma: &LumaPlanes) -> CoefficientPlanes {
+    get_coefficient_planes_with(Isa::detect(), luma)
+}
+
+/// Returns the coefficients of every block in `luma`, using the instruction set `isa`
+///
+/// # Arguments:
+/// * `isa`: the instruction set to use, which must be supported by the processor
+/// * `luma`: the planes of the blocks, which must all have the same length
+pub(crate) fn get_coefficient_planes_with(isa: Isa, luma: &LumaPlanes) -> CoefficientPlanes {
+    let len = luma.y1.len();
+    assert!([&luma.y2, &luma.y3, &luma.y4].iter().all(|p| p.len() == len), "the luma planes have different lengths");
+    let mut out = CoefficientPlanes { a: vec![0.0; len], b: vec![0.0; len], c: vec![0.0; len], d: vec![0.0; len] };
+    let done = simd::coefficients(isa, luma, &mut out);
+
+    // the blocks left over are transformed by a plain loop over slices of equal length, which the compiler vectorizes
+    let (y1, y2, y3, y4) = (&luma.y1[done..], &luma.y2[done..len], &luma.y3[done..len], &luma.y4[done..len]);
+    let (a, b, c, d) = (&mut out.a[done..], &mut out.b[done..len], &mut out.c[done..len], &mut out.d[done..len]);
+    for i in 0..y1.len() {
+        (a[i], b[i], c[i], d[i]) = get_coefficients(y1[i], y2[i], y3[i], y4[i]);
+    }
+    out
+}
+
+/// Returns the luma of every block in `coefficients`, the batch form of `get_luma`, using the widest vector
+/// instructions the processor supports. The results are identical to calling `get_luma` on each block.
+///
+/// # Arguments:
+/// * `coefficients`: the planes of the blocks, which must all have the same length
+pub fn get_luma_planes(coefficients: &CoefficientPlanes) -> LumaPlanes {
+    get_luma_planes_with(Isa::detect(), coefficients)
+}
+
+/// Returns the luma of every block in `coefficients`, using the instruction set `isa`
+///
+/// # Arguments:
+/// * `isa`: the instruction set to use, which must be supported by the processor
+/// * `coefficients`: the planes of the blocks, which must all have the same length
+pub(crate) fn get_luma_planes_with(isa: Isa, coefficients: &CoefficientPlanes) -> LumaPlanes {
+    let len = coefficients.a.len();
+    assert!([&coefficients.b, &coefficients.c, &coefficients.d].iter().all(|p| p.len() == len),
+        "the coefficient planes have different lengths");
+    let mut out = LumaPlanes { y1: vec![0.0; len], y2: vec![0.0; len], y3: vec![0.0; len], y4: vec![0.0; len] };
+    let done = simd::luma(isa, coefficients, &mut out);
+
+    // the blocks left over are transformed by a plain loop over slices of equal length, which the compiler vectorizes
+    let (a, b, c, d) = (&coefficients.a[done..], &coefficients.b[done..len], &coefficients.c[done..len], &coefficients.d[done..len]);
+    let (y1, y2, y3, y4) = (&mut out.y1[done..], &mut out.y2[done..len], &mut out.y3[done..len], &mut out.y4[done..len]);
+    for i in 0..a.len() {
+        (y1[i], y2[i], y3[i], y4[i]) = get_luma(a[i], b[i], c[i], d[i]);
+    }
+    out
+}
+
 /// Returns a tuple of floating point values which represent the brightness of each pixel in the 2 X 2 matrix of pixels,
 /// using the average brightness of the matrix and the degree of change in brightness in each direction of the matrix, 
 /// stored as (y1, y2, y3, y4)
@@ -140,11 +220,136 @@ pub fn parse_word(word: u32) -> (u64, i64, i64, i64, u64, u64){
     (a, b, c, d, pb, pr)
 }
 
+/// The widths of the fields packed into a word. From the most significant field down, a word holds
+/// `a`, then `b`, `c` and `d`, then `pb` and `pr`. A chroma width of 0 leaves the chroma out entirely,
+/// as in grayscale images.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WordLayout {
+    pub a_bits: u64,
+    pub bcd_bits: u64,
+    pub chroma_bits: u64,
+}
+
+/// The original 32 bit word used for color images with 8 bit samples
+pub const RGB_LAYOUT: WordLayout = WordLayout { a_bits: 9, bcd_bits: 5, chroma_bits: 4 };
+
+/// A 24 bit word holding only the luma coefficients of grayscale images with 8 bit samples
+pub const GRAY_LAYOUT: WordLayout = WordLayout { a_bits: 9, bcd_bits: 5, chroma_bits: 0 };
+
+/// A 48 bit word for color images with samples wider than 8 bits, with extra precision in `a` and the chroma
+pub const WIDE_RGB_LAYOUT: WordLayout = WordLayout { a_bits: 16, bcd_bits: 5, chroma_bits: 8 };
+
+/// A 32 bit word for grayscale images with samples wider than 8 bits, with extra precision in `a`
+pub const WIDE_GRAY_LAYOUT: WordLayout = WordLayout { a_bits: 16, bcd_bits: 5, chroma_bits: 0 };
+
+impl WordLayout {
+    /// Returns the number of bytes needed to store a word with this layout
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    pub fn bytes(self) -> usize {
+        (self.a_bits + 3 * self.bcd_bits + 2 * self.chroma_bits).div_ceil(8) as usize
+    }
+
+    /// Returns the unsigned integer repsentation of the coefficient `a`, scaled to fill the `a` field
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `a`: The average brightness of pixels in the matrix
+    pub fn coefficient_to_scaled_int(self, a: f64) -> u64{
+        (a.clamp(0.0, 1.0) * bitpack::maxu(self.a_bits) as f64).round() as u64
+    }
+
+    /// Returns the floating point repsentation of the average brightness of a matrix given `a_scale`
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `a_scale`: the unsigned integer repsentation of the average brightness of the matrix
+    pub fn scaled_int_to_coefficient(self, a_scale: u64) -> f64{
+        a_scale as f64 / bitpack::maxu(self.a_bits) as f64
+    }
+
+    /// Returns a tuple of unsigned integers representing the two chroma values `pb`, `pr`, stored as (pbidx, pridx).
+    /// 4 bit fields use the quantization from `csc411_arith`, and wider fields divide -0.5 to 0.5 into even steps.
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `pb`: a floating point value representing the difference between the brightness and the amount of blue
+    /// * `pr`: a floating point value representing the difference between the brightness and the amount of red
+    pub fn chroma_to_indices(self, pb: f64, pr: f64) -> (u64, u64){
+        match self.chroma_bits {
+            0 => (0, 0),
+            4 => chroma_to_indices(pb, pr),
+            bits => {
+                let steps = bitpack::maxu(bits) as f64;
+                let index = |chroma: f64| ((chroma.clamp(-0.5, 0.5) + 0.5) * steps).round() as u64;
+                (index(pb), index(pr))
+            }
+        }
+    }
+
+    /// Returns a tuple of floating point values representing the chromacity given their unsigned integer representation
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `pbidx`: the unsigned integer representation of the difference between brightness and blue
+    /// * `pridx`: the unsigned integer representation of the difference between brightness and red
+    pub fn indices_to_chroma(self, pbidx: u64, pridx: u64) -> (f64, f64){
+        match self.chroma_bits {
+            0 => (0.0, 0.0),
+            4 => indices_to_chroma(pbidx, pridx),
+            bits => {
+                let steps = bitpack::maxu(bits) as f64;
+                (pbidx as f64 / steps - 0.5, pridx as f64 / steps - 0.5)
+            }
+        }
+    }
+
+    /// Returns a word which holds the signed/unsigned integer repsentation of the brightness coefficients,
+    /// `a`, `b`, `c`, `d` and the chromacity `pb`, and `pr`, packed into the low bits of a u64
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `a`: the unsigned integer repsentation of the average brightness of the matrix
+    /// * `b`, `c`, `d`: the integer represenations of the degrees of change in brightness within the matrix
+    /// * `pb`, `pr`: the unsigned integer representations of the chroma, which are ignored if the layout has none
+    pub fn pack(self, a: u64, b: i64, c: i64, d: i64, pb: u64, pr: u64) -> u64{
+        let (bcd, chroma) = (self.bcd_bits, self.chroma_bits);
+        let mut word: u64 = 0;
+        word = bitpack::newu(word, self.a_bits, 3 * bcd + 2 * chroma, a).unwrap();
+        word = bitpack::news(word, bcd, 2 * bcd + 2 * chroma, b).unwrap();
+        word = bitpack::news(word, bcd, bcd + 2 * chroma, c).unwrap();
+        word = bitpack::news(word, bcd, 2 * chroma, d).unwrap();
+        if chroma > 0 {
+            word = bitpack::newu(word, chroma, chroma, pb).unwrap();
+            word = bitpack::newu(word, chroma, 0, pr).unwrap();
+        }
+        word
+    }
+
+    /// Returns a tuple of six signed/unsigned integers representing the brightness and chromacity of a 2 X 2 matrix
+    /// of pixels, from a `word` packed with this layout. Layouts without chroma return 0 for both chroma indices.
+    /// 
+    /// # Arguments:
+    /// * `self`: the layout of the word
+    /// * `word`: a value that holds the values returned in the tuple
+    pub fn unpack(self, word: u64) -> (u64, i64, i64, i64, u64, u64){
+        let (bcd, chroma) = (self.bcd_bits, self.chroma_bits);
+        let a = bitpack::getu(word, self.a_bits, 3 * bcd + 2 * chroma);
+        let b = bitpack::gets(word, bcd, 2 * bcd + 2 * chroma);
+        let c = bitpack::gets(word, bcd, bcd + 2 * chroma);
+        let d = bitpack::gets(word, bcd, 2 * chroma);
+        let pb = bitpack::getu(word, chroma, chroma);
+        let pr = bitpack::getu(word, chroma, 0);
+        (a, b, c, d, pb, pr)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::quantize::{get_coefficients, get_luma, coefficients_to_scaled_ints, scaled_ints_to_coefficients, 
         singular_coefficient_to_scaled_int, singular_scaled_int_to_coefficient, chroma_to_indices, indices_to_chroma,
-        construct_word, parse_word};
+        construct_word, parse_word, RGB_LAYOUT, GRAY_LAYOUT, WIDE_RGB_LAYOUT, WIDE_GRAY_LAYOUT};
 
     fn compare_floats(x: f64, y: f64) -> bool{
         if (x - y).abs() < 0.001{
@@ -245,5 +450,115 @@ mod tests {
         let answer: (u64, i64, i64, i64, u64, u64) = (256, -15, 15, 0, 15, 0);
         assert_eq!(parse_word(input), answer);
     }
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn test_layout_bytes(){
+        assert_eq!(RGB_LAYOUT.bytes(), 4);
+        assert_eq!(GRAY_LAYOUT.bytes(), 3);
+        assert_eq!(WIDE_RGB_LAYOUT.bytes(), 6);
+        assert_eq!(WIDE_GRAY_LAYOUT.bytes(), 4);
+    }
+
+    #[test]
+    fn test_rgb_layout_matches_construct_word(){
+        let word = construct_word(256, -15, 15, 0, 15, 0);
+        assert_eq!(RGB_LAYOUT.pack(256, -15, 15, 0, 15, 0), word as u64);
+        assert_eq!(RGB_LAYOUT.unpack(word as u64), parse_word(word));
+    }
+
+    #[test]
+    fn test_gray_layout(){
+        let answer: u64 = 0b100000000100010111100000;
+        assert_eq!(GRAY_LAYOUT.pack(256, -15, 15, 0, 0, 0), answer);
+        assert_eq!(GRAY_LAYOUT.unpack(answer), (256, -15, 15, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_wide_rgb_layout(){
+        let word = WIDE_RGB_LAYOUT.pack(65535, -16, 15, -1, 255, 0);
+        assert!(word < 1 << 48);
+        assert_eq!(WIDE_RGB_LAYOUT.unpack(word), (65535, -16, 15, -1, 255, 0));
+    }
+
+    #[test]
+    fn test_wide_coefficients(){
+        assert_eq!(WIDE_RGB_LAYOUT.coefficient_to_scaled_int(1.0), 65535);
+        assert_eq!(WIDE_RGB_LAYOUT.coefficient_to_scaled_int(0.5), 32768);
+        assert!(compare_floats(WIDE_RGB_LAYOUT.scaled_int_to_coefficient(32768), 0.5));
+    }
+
+    #[test]
+    fn test_wide_chroma(){
+        assert_eq!(WIDE_RGB_LAYOUT.chroma_to_indices(-0.5, 0.5), (0, 255));
+        let (pb, pr) = WIDE_RGB_LAYOUT.indices_to_chroma(WIDE_RGB_LAYOUT.chroma_to_indices(0.1, -0.2).0, 0);
+        assert!((pb - 0.1).abs() < 0.002 && compare_floats(pr, -0.5));
+        assert_eq!(GRAY_LAYOUT.indices_to_chroma(3, 7), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_planes_match_scalar(){
+        use crate::quantize::{get_coefficient_planes_with, get_luma_planes_with, CoefficientPlanes, LumaPlanes};
+        use crate::simd::Isa;
+
+        // 1003 blocks leave a tail after every vector width
+        let values: Vec<f64> = (0..1003_u32).map(|n| (n * 7919 % 1000) as f64 / 999.0).collect();
+        let reversed: Vec<f64> = values.iter().rev().copied().collect();
+        let luma = LumaPlanes { y1: values.clone(), y2: reversed.clone(), y3: values.iter().map(|v| v * v).collect(), y4: values.iter().map(|v| 1.0 - v).collect() };
+        let coefficients = CoefficientPlanes { a: values.clone(), b: values.iter().map(|v| v * 0.6 - 0.3).collect(), c: reversed.iter().map(|v| 0.3 - v * 0.6).collect(), d: values.iter().map(|v| v * v * 0.3).collect() };
+        for isa in [Isa::Scalar, Isa::Sse2, Isa::Avx2].into_iter().filter(|isa| isa.supported()) {
+            let out = get_coefficient_planes_with(isa, &luma);
+            let back = get_luma_planes_with(isa, &coefficients);
+            for i in 0..values.len() {
+                let expected = get_coefficients(luma.y1[i], luma.y2[i], luma.y3[i], luma.y4[i]);
+                assert_eq!((out.a[i], out.b[i], out.c[i], out.d[i]), expected, "{:?} at {}", isa, i);
+                let expected = get_luma(coefficients.a[i], coefficients.b[i], coefficients.c[i], coefficients.d[i]);
+                assert_eq!((back.y1[i], back.y2[i], back.y3[i], back.y4[i]), expected, "{:?} at {}", isa, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_word_round_trip_property(){
+        use crate::testimages::Rng;
+
+        let mut rng = Rng::new(3);
+        for _ in 0..100_000 {
+            let signed = |rng: &mut Rng| rng.below(32) as i64 - 16;
+            let (a, b, c, d) = (rng.below(512), signed(&mut rng), signed(&mut rng), signed(&mut rng));
+            let (pb, pr) = (rng.below(16), rng.below(16));
+            assert_eq!(parse_word(construct_word(a, b, c, d, pb, pr)), (a, b, c, d, pb, pr));
+
+            for layout in [RGB_LAYOUT, GRAY_LAYOUT, WIDE_RGB_LAYOUT, WIDE_GRAY_LAYOUT] {
+                let a = rng.below(1 << layout.a_bits);
+                let chroma = 1 << layout.chroma_bits;
+                let (pb, pr) = if layout.chroma_bits == 0 { (0, 0) } else { (rng.below(chroma), rng.below(chroma)) };
+                let word = layout.pack(a, b, c, d, pb, pr);
+                assert!(word < 1 << (8 * layout.bytes()));
+                assert_eq!(layout.unpack(word), (a, b, c, d, pb, pr), "{:?}", layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_round_trip_property(){
+        use crate::testimages::Rng;
+
+        let mut rng = Rng::new(4);
+        let mut unit = || rng.below(1 << 53) as f64 / (1_u64 << 53) as f64;
+        for _ in 0..100_000 {
+            let y = (unit(), unit(), unit(), unit());
+            let (a, b, c, d) = get_coefficients(y.0, y.1, y.2, y.3);
+            let back = get_luma(a, b, c, d);
+            for (x, x_back) in [(y.0, back.0), (y.1, back.1), (y.2, back.2), (y.3, back.3)] {
+                assert!((x - x_back).abs() < 1e-12, "{:?} and {:?}", y, back);
+            }
+
+            // scaling b, c and d to integers loses at most a fiftieth of each, within the range kept
+            let (b_int, c_int, d_int) = coefficients_to_scaled_ints(b, c, d);
+            let (b_back, c_back, d_back) = scaled_ints_to_coefficients(b_int, c_int, d_int);
+            for (x, x_back) in [(b, b_back), (c, c_back), (d, d_back)] {
+                assert!((x.clamp(-0.3, 0.3) - x_back).abs() <= 1.0 / 50.0 + 1e-12, "{} and {}", x, x_back);
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::ops::Range;
+use crate::checksum;
+
+/// The first two bytes of every restart marker, which are followed by the low 16 bits of the segment's index
+/// in Big-Endian order
+pub const MARKER: [u8; 2] = [0xff, 0xd0];
+
+/// The number of bytes in a restart marker
+const MARKER_BYTES: usize = 4;
+
+/// The number of bytes in the CRC-32 that follows the words of every segment
+const CHECKSUM_BYTES: usize = 4;
+
+/// The layout of the color words of an image split into segments of `interval` block rows. Each segment is written
+/// as a restart marker, the words of its block rows, and the CRC-32 of those words, so that a decoder can tell which
+/// segments are damaged and find the start of the next one. Every word depends only on its own block, so a segment
+/// can be decoded without any of the ones before it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Segments {
+    /// the number of block rows in every segment but the last, which may be shorter
+    pub interval: usize,
+    /// the number of block rows in the image
+    pub rows: usize,
+    /// the number of bytes of words in each block row
+    pub row_bytes: usize,
+}
+
+impl Segments {
+    /// Builds the layout of `rows` block rows of `row_bytes` bytes each, in segments of `interval` block rows. The
+    /// interval comes from the header or the user, so one longer than the image is clamped to a single segment
+    ///
+    /// # Arguments:
+    /// * `interval`: the number of block rows between restart markers, which must be positive
+    /// * `rows`: the number of block rows in the image
+    /// * `row_bytes`: the number of bytes of words in each block row
+    pub fn new(interval: usize, rows: usize, row_bytes: usize) -> Self {
+        Segments { interval: interval.min(rows.max(1)), rows, row_bytes }
+    }
+
+    /// Returns the number of segments
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    pub fn count(&self) -> usize {
+        self.rows.div_ceil(self.interval)
+    }
+
+    /// Returns the block rows covered by segment `k`
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `k`: the index of the segment
+    pub fn block_rows(&self, k: usize) -> Range<usize> {
+        k * self.interval..((k + 1) * self.interval).min(self.rows)
+    }
+
+    /// Returns the number of bytes the segments take up, or None if that does not fit in a `usize`
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    pub fn encoded_len(&self) -> Option<usize> {
+        self.rows.checked_mul(self.row_bytes)?.checked_add(self.count().checked_mul(MARKER_BYTES + CHECKSUM_BYTES)?)
+    }
+
+    /// Returns the index of the word that holds byte `offset` of the segments, for saying where a file was cut short
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `offset`: the number of bytes of the segments that are present
+    /// * `word_bytes`: the number of bytes in each word
+    pub fn word_at(&self, offset: usize, word_bytes: usize) -> usize {
+        let segment_bytes = self.interval.saturating_mul(self.row_bytes);
+        let stride = segment_bytes.saturating_add(MARKER_BYTES + CHECKSUM_BYTES);
+        let k = offset / stride;
+        let data = (offset % stride).saturating_sub(MARKER_BYTES).min(self.block_rows(k).len().saturating_mul(self.row_bytes));
+        k.saturating_mul(segment_bytes).saturating_add(data) / word_bytes
+    }
+
+    /// Appends the segments holding `words`, the bytes of every block row in order, to `out`
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `words`: the bytes of the words of the image
+    /// * `out`: the bytes of the file so far
+    pub fn write(&self, words: &[u8], out: &mut Vec<u8>) {
+        for (k, data) in words.chunks(self.interval.saturating_mul(self.row_bytes).max(1)).enumerate() {
+            out.extend_from_slice(&marker(k));
+            out.extend_from_slice(data);
+            out.extend_from_slice(&checksum::crc32(data).to_be_bytes());
+        }
+    }
+
+    /// Returns the bytes of the words held in `bytes`, which must be exactly `encoded_len` long, or an error naming
+    /// the first segment whose marker is missing or whose words do not match their CRC-32
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `bytes`: the segments
+    pub fn read(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let mut words = Vec::with_capacity(self.rows * self.row_bytes);
+        let mut pos = 0;
+        for k in 0..self.count() {
+            let len = self.block_rows(k).len() * self.row_bytes;
+            if bytes[pos..pos + MARKER_BYTES] != marker(k) {
+                return Err(format!("restart marker {} is missing", k));
+            }
+            let data = segment_at(bytes, pos, k, len).ok_or_else(|| {
+                let rows = self.block_rows(k);
+                format!("checksum mismatch in segment {}, covering pixel rows {} to {}", k, rows.start * 2, rows.end * 2 - 1)
+            })?;
+            words.extend_from_slice(data);
+            pos += MARKER_BYTES + len + CHECKSUM_BYTES;
+        }
+        Ok(words)
+    }
+
+    /// Returns the bytes of the words held in `bytes`, which may have been damaged, cut short or had bytes added or
+    /// removed, along with the block rows that had to be concealed. Every segment is looked for at the end of the
+    /// last one found, and then anywhere after it, and is kept if its words match their CRC-32. The block rows of
+    /// the segments that are not found are concealed by copying the block row above, or the first block row found
+    /// below for those at the top of the image. Returns an error if no segment is found at all.
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `bytes`: the segments
+    pub fn recover(&self, bytes: &[u8]) -> Result<(Vec<u8>, Vec<Range<usize>>), String> {
+        let mut words = vec![0; self.rows * self.row_bytes];
+        let mut damaged: Vec<Range<usize>> = Vec::new();
+        let mut pos = 0;
+        for k in 0..self.count() {
+            let len = self.block_rows(k).len() * self.row_bytes;
+            let found = (pos..bytes.len())
+                .filter(|&p| bytes[p] == MARKER[0])
+                .find_map(|p| segment_at(bytes, p, k, len).map(|data| (p, data)));
+            let rows = self.block_rows(k);
+            match found {
+                Some((at, data)) => {
+                    words[rows.start * self.row_bytes..rows.end * self.row_bytes].copy_from_slice(data);
+                    pos = at + MARKER_BYTES + len + CHECKSUM_BYTES;
+                }
+                // neighbouring damaged segments are reported as one region
+                None => match damaged.last_mut() {
+                    Some(last) if last.end == rows.start => last.end = rows.end,
+                    _ => damaged.push(rows),
+                },
+            }
+        }
+        if damaged.first().is_some_and(|first| *first == (0..self.rows)) {
+            return Err("every segment of the compressed image is damaged".to_string());
+        }
+
+        for region in &damaged {
+            for row in region.clone() {
+                // a region at the top is filled from the first row below it, and any other from the row above
+                let source = if region.start == 0 { region.end } else { row - 1 };
+                words.copy_within(source * self.row_bytes..(source + 1) * self.row_bytes, row * self.row_bytes);
+            }
+        }
+        Ok((words, damaged))
+    }
+}
+
+/// Helper function that returns the restart marker of segment `k`
+///
+/// # Arguments:
+/// * `k`: the index of the segment
+fn marker(k: usize) -> [u8; MARKER_BYTES] {
+    let [high, low] = (k as u16).to_be_bytes();
+    [MARKER[0], MARKER[1], high, low]
+}
+
+/// Helper function that returns the words of segment `k` if its marker starts at `pos` in `bytes` and its `len`
+/// bytes of words match the CRC-32 after them, and None otherwise
+///
+/// # Arguments:
+/// * `bytes`: the segments
+/// * `pos`: the position where the segment might start
+/// * `k`: the index of the segment
+/// * `len`: the number of bytes of words in the segment
+fn segment_at(bytes: &[u8], pos: usize, k: usize, len: usize) -> Option<&[u8]> {
+    let segment = bytes.get(pos..pos.checked_add(MARKER_BYTES + len + CHECKSUM_BYTES)?)?;
+    let (data, crc) = segment[MARKER_BYTES..].split_at(len);
+    (segment[..MARKER_BYTES] == marker(k) && checksum::crc32(data).to_be_bytes() == crc).then_some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::restart::Segments;
+
+    /// 5 block rows of 3 bytes, in segments of 2 rows
+    const SEGMENTS: Segments = Segments { interval: 2, rows: 5, row_bytes: 3 };
+
+    fn encode() -> (Vec<u8>, Vec<u8>) {
+        let words: Vec<u8> = (1..=15).collect();
+        let mut bytes = Vec::new();
+        SEGMENTS.write(&words, &mut bytes);
+        (words, bytes)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (words, bytes) = encode();
+        assert_eq!(SEGMENTS.count(), 3);
+        assert_eq!(bytes.len(), SEGMENTS.encoded_len().unwrap());
+        assert_eq!(&bytes[..4], &[0xff, 0xd0, 0, 0]);
+        assert_eq!(SEGMENTS.read(&bytes).unwrap(), words);
+        assert_eq!(SEGMENTS.recover(&bytes).unwrap(), (words, vec![]));
+    }
+
+    #[test]
+    fn test_damage_is_found() {
+        let (_, mut bytes) = encode();
+        bytes[16] ^= 1;
+        assert_eq!(SEGMENTS.read(&bytes).unwrap_err(), "restart marker 1 is missing");
+        bytes[16] ^= 1;
+        bytes[20] ^= 1;
+        assert_eq!(SEGMENTS.read(&bytes).unwrap_err(), "checksum mismatch in segment 1, covering pixel rows 4 to 7");
+    }
+
+    #[test]
+    fn test_recover() {
+        // a damaged middle segment is concealed by repeating the row above it
+        let (words, mut bytes) = encode();
+        bytes[20] ^= 1;
+        let (recovered, damaged) = SEGMENTS.recover(&bytes).unwrap();
+        assert_eq!(damaged, vec![2..4]);
+        assert_eq!(&recovered[6..12], &[4, 5, 6, 4, 5, 6]);
+        assert_eq!(&recovered[12..], &words[12..]);
+
+        // a lost byte shifts the segments after it, which are found again by their markers
+        let (_, mut bytes) = encode();
+        bytes.remove(5);
+        let (recovered, damaged) = SEGMENTS.recover(&bytes).unwrap();
+        assert_eq!(damaged, vec![0..2]);
+        assert_eq!(&recovered[..6], &[7, 8, 9, 7, 8, 9]);
+        assert_eq!(&recovered[6..], &words[6..]);
+
+        // a file cut short loses its last segments
+        let (_, bytes) = encode();
+        assert_eq!(SEGMENTS.recover(&bytes[..20]).unwrap().1, vec![2..5]);
+        assert!(SEGMENTS.recover(&bytes[..3]).is_err());
+    }
+
+    #[test]
+    fn test_word_at() {
+        // the second segment starts at byte 14, and its first word is word 2 of 5 when words are 3 bytes
+        assert_eq!(SEGMENTS.word_at(0, 3), 0);
+        assert_eq!(SEGMENTS.word_at(14, 3), 2);
+        assert_eq!(SEGMENTS.word_at(18, 3), 2);
+        assert_eq!(SEGMENTS.word_at(21, 3), 3);
+    }
+
+    #[test]
+    fn test_huge_interval() {
+        // an interval longer than the image is a single segment, rather than overflowing when it is multiplied out
+        let segments = Segments::new(usize::MAX, 5, 3);
+        assert_eq!(segments, Segments { interval: 5, rows: 5, row_bytes: 3 });
+        let words: Vec<u8> = (1..=15).collect();
+        let mut bytes = Vec::new();
+        segments.write(&words, &mut bytes);
+        assert_eq!(bytes.len(), segments.encoded_len().unwrap());
+        assert_eq!(segments.read(&bytes).unwrap(), words);
+
+        // the raw layout saturates instead of panicking
+        let unclamped = Segments { interval: usize::MAX, rows: 5, row_bytes: 3 };
+        assert_eq!(unclamped.word_at(10, 3), 2);
+        let mut bytes = Vec::new();
+        unclamped.write(&words, &mut bytes);
+        assert_eq!(segments.read(&bytes).unwrap(), words);
+    }
+}
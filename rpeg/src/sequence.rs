@@ -0,0 +1,220 @@
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use crate::container::{self, CompressedImage};
+use crate::limits::DecodeLimits;
+
+/// One frame of a sequence, as named by a `frame` line of its header
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameEntry {
+    /// the time the frame was captured, in milliseconds
+    pub timestamp: u64,
+    /// the offset of the rpeg file holding the frame from the end of the header, in bytes
+    pub offset: usize,
+    /// the length of the rpeg file holding the frame, in bytes
+    pub len: usize,
+}
+
+/// The header of an rpeg sequence, a file holding frames of the same size one after another, each of them a whole
+/// rpeg file. It starts with `Compressed sequence format 1` and the dimensions of the frames, followed by a
+/// `frame timestamp offset length` line for each frame and then a blank line, so that any frame can be read
+/// without reading the ones before it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SequenceHeader {
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<FrameEntry>,
+}
+
+impl SequenceHeader {
+    /// Reads a header from `reader`, leaving it positioned at the first frame, or returns an error if the frames it
+    /// records do not follow each other
+    ///
+    /// # Arguments:
+    /// * `reader`: the reader positioned at the start of the file
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<Self, String> {
+        let format = container::read_line(reader)?;
+        if format != "Compressed sequence format 1" {
+            return Err(format!("not an rpeg sequence: {}", format));
+        }
+        let dimensions = container::read_line(reader)?;
+        let (width, height) = match dimensions.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [w, h] => (
+                w.parse::<u32>().map_err(|_| format!("invalid width: {}", w))?,
+                h.parse::<u32>().map_err(|_| format!("invalid height: {}", h))?,
+            ),
+            _ => return Err(format!("invalid dimensions: {}", dimensions)),
+        };
+
+        let mut header = SequenceHeader { width, height, frames: Vec::new() };
+        let mut end = 0_usize;
+        loop {
+            let line = container::read_line(reader)?;
+            let entry = match line.split_once(' ') {
+                None if line.is_empty() => break,
+                Some(("frame", value)) => match value.split(' ').map(|n| n.parse::<u64>().ok()).collect::<Vec<_>>().as_slice() {
+                    &[Some(timestamp), Some(offset), Some(len)] => FrameEntry {
+                        timestamp,
+                        offset: usize::try_from(offset).map_err(|_| format!("invalid frame: {}", value))?,
+                        len: usize::try_from(len).map_err(|_| format!("invalid frame: {}", value))?,
+                    },
+                    _ => return Err(format!("invalid frame: {}", value)),
+                },
+                _ => return Err(format!("unknown header field: {}", line)),
+            };
+            if entry.offset != end {
+                let k = header.frames.len();
+                return Err(format!("frame {} is recorded at byte {}, but starts at byte {}", k, entry.offset, end));
+            }
+            end = end.checked_add(entry.len).ok_or("frames are too large")?;
+            header.frames.push(entry);
+        }
+        Ok(header)
+    }
+
+    /// Returns the text of the header
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let frames: String = self.frames.iter()
+            .map(|frame| format!("frame {} {} {}\n", frame.timestamp, frame.offset, frame.len))
+            .collect();
+        format!("Compressed sequence format 1\n{} {}\n{}\n", self.width, self.height, frames).into_bytes()
+    }
+}
+
+/// Builds an rpeg sequence one frame at a time, holding only the compressed frames
+pub struct SequenceWriter {
+    header: SequenceHeader,
+    payload: Vec<u8>,
+}
+
+impl SequenceWriter {
+    /// Returns a writer for a sequence of frames of `width` x `height` pixels
+    ///
+    /// # Arguments:
+    /// * `width`, `height`: the dimensions every frame must have
+    pub fn new(width: u32, height: u32) -> Self {
+        SequenceWriter { header: SequenceHeader { width, height, frames: Vec::new() }, payload: Vec::new() }
+    }
+
+    /// Appends `img` as the next frame, or returns an error if its dimensions differ from those of the sequence
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the writer
+    /// * `timestamp`: the time the frame was captured, in milliseconds
+    /// * `img`: the compressed frame
+    pub fn push(&mut self, timestamp: u64, img: &CompressedImage) -> Result<(), String> {
+        let (width, height) = (self.header.width, self.header.height);
+        if (img.width, img.height) != (width, height) {
+            let k = self.header.frames.len();
+            return Err(format!("frame {} is {} x {}, but the sequence is {} x {}", k, img.width, img.height, width, height));
+        }
+        let bytes = img.to_bytes();
+        self.header.frames.push(FrameEntry { timestamp, offset: self.payload.len(), len: bytes.len() });
+        self.payload.extend_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Returns the contents of the sequence file
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the writer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_bytes();
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+/// Returns frame `n` of an rpeg sequence and its timestamp, seeking past the frames before it without reading them.
+/// Each frame is a whole rpeg file, so it is checked against its own length and CRC-32.
+///
+/// # Arguments:
+/// * `input`: the reader holding the sequence, positioned at its start
+/// * `n`: the index of the frame, counting from 0
+/// * `limits`: the largest frame to accept
+///
+/// Returns an error if the sequence has no frame `n`, exceeds `limits`, is cut short, or holds a frame that is not
+/// a valid rpeg file of the size of the sequence
+pub fn read_frame<R: BufRead + Seek>(input: &mut R, n: usize, limits: DecodeLimits) -> Result<(u64, CompressedImage), String> {
+    let header = SequenceHeader::read(input)?;
+    limits.check_dimensions(header.width, header.height)?;
+    let frame = match header.frames.get(n) {
+        Some(&frame) => frame,
+        None => return Err(format!("frame {} is out of range, since the sequence has {} frames", n, header.frames.len())),
+    };
+
+    // the recorded length is not trusted for the allocation, so the buffer only grows as bytes arrive
+    let start = input.stream_position().map_err(|e| e.to_string())?;
+    let offset = start.checked_add(frame.offset as u64).ok_or_else(|| format!("frame {} is out of range", n))?;
+    input.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    let mut bytes = Vec::new();
+    input.take(frame.len as u64).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    if bytes.len() < frame.len {
+        return Err(format!("sequence is truncated in frame {}, after {} of {} bytes", n, bytes.len(), frame.len));
+    }
+    let img = CompressedImage::from_bytes_with_limits(&bytes, limits).map_err(|e| format!("frame {}: {}", n, e))?;
+    if (img.width, img.height) != (header.width, header.height) {
+        return Err(format!("frame {} is {} x {}, but the sequence is {} x {}", n, img.width, img.height, header.width, header.height));
+    }
+    Ok((frame.timestamp, img))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::container::{ColourModel, CompressedImage};
+    use crate::limits::DecodeLimits;
+    use crate::sequence::{read_frame, FrameEntry, SequenceHeader, SequenceWriter};
+
+    /// Returns a 4 x 2 frame whose words are all `word`
+    fn frame(word: u64) -> CompressedImage {
+        CompressedImage::from_words(vec![word; 2], 4, 2, ColourModel::Rgb, 255)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = SequenceWriter::new(4, 2);
+        for (timestamp, word) in [(1000, 0x0102), (1040, 0x0304), (1080, 0x0506)] {
+            writer.push(timestamp, &frame(word)).unwrap();
+        }
+        let bytes = writer.to_bytes();
+        for (n, (timestamp, word)) in [(1000, 0x0102), (1040, 0x0304), (1080, 0x0506)].into_iter().enumerate() {
+            let (found, img) = read_frame(&mut Cursor::new(&bytes), n, DecodeLimits::default()).unwrap();
+            assert_eq!(found, timestamp);
+            assert_eq!(img.to_bytes(), frame(word).to_bytes());
+        }
+        assert_eq!(
+            read_frame(&mut Cursor::new(&bytes), 3, DecodeLimits::default()).err().unwrap(),
+            "frame 3 is out of range, since the sequence has 3 frames"
+        );
+
+        let header = SequenceHeader::read(&mut Cursor::new(&bytes)).unwrap();
+        let len = frame(0).to_bytes().len();
+        assert_eq!(header.frames[2], FrameEntry { timestamp: 1080, offset: 2 * len, len });
+        let cut = &bytes[..bytes.len() - 3];
+        assert_eq!(
+            read_frame(&mut Cursor::new(cut), 2, DecodeLimits::default()).err().unwrap(),
+            format!("sequence is truncated in frame 2, after {} of {} bytes", len - 3, len)
+        );
+        assert!(writer.push(0, &CompressedImage::from_words(vec![0; 4], 4, 4, ColourModel::Rgb, 255)).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let header = SequenceHeader {
+            width: 4,
+            height: 2,
+            frames: vec![FrameEntry { timestamp: 5, offset: 0, len: 10 }, FrameEntry { timestamp: 9, offset: 10, len: 7 }],
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, b"Compressed sequence format 1\n4 2\nframe 5 0 10\nframe 9 10 7\n\n");
+        assert_eq!(SequenceHeader::read(&mut Cursor::new(&bytes)).unwrap(), header);
+
+        let err = |bytes: &[u8]| SequenceHeader::read(&mut Cursor::new(bytes)).err().unwrap();
+        assert_eq!(err(b"Compressed sequence format 1\n4 2\nframe 5 0 10\nframe 9 11 7\n\n"), "frame 1 is recorded at byte 11, but starts at byte 10");
+        assert_eq!(err(b"Compressed sequence format 1\n4 2\nframe 5 0\n\n"), "invalid frame: 5 0");
+        assert_eq!(err(b"Compressed image format 2\n4 2\n"), "not an rpeg sequence: Compressed image format 2");
+        assert_eq!(err(b"Compressed sequence format 1\n4 2\nframe 0 0 18446744073709551615\nframe 0 18446744073709551615 1\n\n"), "frames are too large");
+    }
+}
@@ -0,0 +1,170 @@
+use crate::pixel_conversion::{ComponentPlanes, RgbPlanes};
+use crate::quantize::{CoefficientPlanes, LumaPlanes};
+
+/// The instruction sets that batches of pixels and blocks can be processed with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Isa {
+    /// plain loops, left for the compiler to vectorize
+    Scalar,
+    /// 128 bit vectors of two f64 values, which every x86_64 processor has
+    Sse2,
+    /// 256 bit vectors of four f64 values
+    Avx2,
+}
+
+impl Isa {
+    /// Returns the widest instruction set supported by the running processor
+    pub fn detect() -> Self {
+        [Isa::Avx2, Isa::Sse2].into_iter().find(|isa| isa.supported()).unwrap_or(Isa::Scalar)
+    }
+
+    /// Returns true if the running processor supports this instruction set
+    ///
+    /// # Arguments:
+    /// * `self`: the instruction set
+    pub fn supported(self) -> bool {
+        match self {
+            Isa::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => false,
+        }
+    }
+}
+
+/// Generates the vector kernels for one instruction set. Every kernel performs exactly the same operations in the same
+/// order as the scalar function it mirrors, so that the results are identical, and returns how many values it
+/// processed, which is the length of the planes rounded down to a whole number of vectors. The caller finishes the rest.
+#[cfg(target_arch = "x86_64")]
+macro_rules! kernels {
+    ($module:ident, $feature:literal, $lanes:expr, $splat:ident, $load:ident, $store:ident,
+     $add:ident, $sub:ident, $mul:ident, $div:ident) => {
+        mod $module {
+            use std::arch::x86_64::*;
+            use crate::pixel_conversion::{ComponentPlanes, RgbPlanes};
+            use crate::quantize::{CoefficientPlanes, LumaPlanes};
+
+            /// The vector form of `pixel_conversion::get_compression_tuple`
+            #[target_feature(enable = $feature)]
+            pub unsafe fn compression(rgb: &RgbPlanes, out: &mut ComponentPlanes) -> usize {
+                let len = rgb.red.len() / $lanes * $lanes;
+                for i in (0..len).step_by($lanes) {
+                    let r = $load(rgb.red[i..].as_ptr());
+                    let g = $load(rgb.green[i..].as_ptr());
+                    let b = $load(rgb.blue[i..].as_ptr());
+                    let y = $add($add($mul($splat(0.299), r), $mul($splat(0.587), g)), $mul($splat(0.114), b));
+                    let pb = $add($sub($mul($splat(-0.168736), r), $mul($splat(0.331264), g)), $mul($splat(0.5), b));
+                    let pr = $sub($sub($mul($splat(0.5), r), $mul($splat(0.418688), g)), $mul($splat(0.081312), b));
+                    $store(out.y[i..].as_mut_ptr(), y);
+                    $store(out.pb[i..].as_mut_ptr(), pb);
+                    $store(out.pr[i..].as_mut_ptr(), pr);
+                }
+                len
+            }
+
+            /// The vector form of `pixel_conversion::get_decompression_tuple`
+            #[target_feature(enable = $feature)]
+            pub unsafe fn decompression(comp: &ComponentPlanes, out: &mut RgbPlanes) -> usize {
+                let len = comp.y.len() / $lanes * $lanes;
+                for i in (0..len).step_by($lanes) {
+                    let y = $mul($splat(1.0), $load(comp.y[i..].as_ptr()));
+                    let pb = $load(comp.pb[i..].as_ptr());
+                    let pr = $load(comp.pr[i..].as_ptr());
+                    let r = $add($add(y, $mul($splat(0.0), pb)), $mul($splat(1.402), pr));
+                    let g = $sub($sub(y, $mul($splat(0.344136), pb)), $mul($splat(0.714136), pr));
+                    let b = $add($add(y, $mul($splat(1.772), pb)), $mul($splat(0.0), pr));
+                    $store(out.red[i..].as_mut_ptr(), r);
+                    $store(out.green[i..].as_mut_ptr(), g);
+                    $store(out.blue[i..].as_mut_ptr(), b);
+                }
+                len
+            }
+
+            /// The vector form of `quantize::get_coefficients`
+            #[target_feature(enable = $feature)]
+            pub unsafe fn coefficients(luma: &LumaPlanes, out: &mut CoefficientPlanes) -> usize {
+                let len = luma.y1.len() / $lanes * $lanes;
+                let four = $splat(4.0);
+                for i in (0..len).step_by($lanes) {
+                    let y1 = $load(luma.y1[i..].as_ptr());
+                    let y2 = $load(luma.y2[i..].as_ptr());
+                    let y3 = $load(luma.y3[i..].as_ptr());
+                    let y4 = $load(luma.y4[i..].as_ptr());
+                    let (sum, diff) = ($add(y4, y3), $sub(y4, y3));
+                    $store(out.a[i..].as_mut_ptr(), $div($add($add(sum, y2), y1), four));
+                    $store(out.b[i..].as_mut_ptr(), $div($sub($sub(sum, y2), y1), four));
+                    $store(out.c[i..].as_mut_ptr(), $div($sub($add(diff, y2), y1), four));
+                    $store(out.d[i..].as_mut_ptr(), $div($add($sub(diff, y2), y1), four));
+                }
+                len
+            }
+
+            /// The vector form of `quantize::get_luma`
+            #[target_feature(enable = $feature)]
+            pub unsafe fn luma(coefficients: &CoefficientPlanes, out: &mut LumaPlanes) -> usize {
+                let len = coefficients.a.len() / $lanes * $lanes;
+                for i in (0..len).step_by($lanes) {
+                    let a = $load(coefficients.a[i..].as_ptr());
+                    let b = $load(coefficients.b[i..].as_ptr());
+                    let c = $load(coefficients.c[i..].as_ptr());
+                    let d = $load(coefficients.d[i..].as_ptr());
+                    let (low, high) = ($sub(a, b), $add(a, b));
+                    $store(out.y1[i..].as_mut_ptr(), $add($sub(low, c), d));
+                    $store(out.y2[i..].as_mut_ptr(), $sub($add(low, c), d));
+                    $store(out.y3[i..].as_mut_ptr(), $sub($sub(high, c), d));
+                    $store(out.y4[i..].as_mut_ptr(), $add($add(high, c), d));
+                }
+                len
+            }
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+kernels!(sse2, "sse2", 2, _mm_set1_pd, _mm_loadu_pd, _mm_storeu_pd, _mm_add_pd, _mm_sub_pd, _mm_mul_pd, _mm_div_pd);
+
+#[cfg(target_arch = "x86_64")]
+kernels!(avx2, "avx2", 4, _mm256_set1_pd, _mm256_loadu_pd, _mm256_storeu_pd,
+    _mm256_add_pd, _mm256_sub_pd, _mm256_mul_pd, _mm256_div_pd);
+
+/// Generates a function that runs a kernel with the instruction set `isa`, returning how many values were processed.
+/// The scalar instruction set processes none, leaving every value to the caller.
+macro_rules! dispatch {
+    ($name:ident, $input:ty, $output:ty) => {
+        /// Runs the kernel of the same name with the instruction set `isa`, which must be supported by the running
+        /// processor, and returns how many values it processed
+        pub(crate) fn $name(isa: Isa, input: &$input, out: &mut $output) -> usize {
+            assert!(isa.supported(), "{:?} is not supported by this processor", isa);
+            match isa {
+                // SAFETY: the processor supports the instruction set, and the kernels only touch whole vectors
+                // within the planes, which the callers check have equal lengths
+                #[cfg(target_arch = "x86_64")]
+                Isa::Avx2 => unsafe { avx2::$name(input, out) },
+                #[cfg(target_arch = "x86_64")]
+                Isa::Sse2 => unsafe { sse2::$name(input, out) },
+                _ => 0,
+            }
+        }
+    };
+}
+
+dispatch!(compression, RgbPlanes, ComponentPlanes);
+dispatch!(decompression, ComponentPlanes, RgbPlanes);
+dispatch!(coefficients, LumaPlanes, CoefficientPlanes);
+dispatch!(luma, CoefficientPlanes, LumaPlanes);
+
+#[cfg(test)]
+mod tests {
+    use crate::simd::Isa;
+
+    #[test]
+    fn test_detect() {
+        assert!(Isa::Scalar.supported());
+        assert!(Isa::detect().supported());
+        #[cfg(target_arch = "x86_64")]
+        assert_ne!(Isa::detect(), Isa::Scalar);
+    }
+}
@@ -0,0 +1,336 @@
+use std::io::{BufRead, Read, Write};
+use csc411_image::Rgb;
+use crate::{checksum, codec};
+use crate::container::{ColourModel, Header, CHECKSUM_BYTES};
+use crate::limits::DecodeLimits;
+
+/// Reads the rows of a binary PGM (`P5`) or PPM (`P6`) one at a time, so that only a single row
+/// of the image is ever held in memory
+pub struct RowReader<R> {
+    reader: R,
+    pub width: u32,
+    pub height: u32,
+    pub maxval: u16,
+    pub model: ColourModel,
+    row_len: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead> RowReader<R> {
+    /// Reads the header of the image, leaving `reader` positioned at the first row
+    ///
+    /// # Arguments:
+    /// * `reader`: the reader positioned at the start of the file
+    pub fn new(mut reader: R) -> Result<Self, String> {
+        let model = match next_token(&mut reader)?.as_str() {
+            "P5" => ColourModel::Gray,
+            "P6" => ColourModel::Rgb,
+            magic => return Err(format!("only binary PGM and PPM files can be streamed, found magic number '{}'", magic)),
+        };
+        let mut number = |what: &str| {
+            let token = next_token(&mut reader)?;
+            token.parse::<u32>().map_err(|_| format!("expected {}, found '{}'", what, token))
+        };
+        let (width, height, maxval) = (number("width")?, number("height")?, number("maxval")?);
+        if width == 0 || height == 0 {
+            return Err(format!("image dimensions must be positive, found {} x {}", width, height));
+        }
+        if maxval == 0 || maxval > 65535 {
+            return Err(format!("maxval must be between 1 and 65535, found {}", maxval));
+        }
+
+        let channels = if model == ColourModel::Gray { 1 } else { 3 };
+        let sample_bytes = if maxval < 256 { 1 } else { 2 };
+        let row_len = (width as usize).checked_mul(channels * sample_bytes).ok_or("image dimensions are too large")?;
+        Ok(RowReader { reader, width, height, maxval: maxval as u16, model, row_len, buffer: Vec::new() })
+    }
+
+    /// Returns the pixels of the next row. Gray pixels have their value copied into all three colors.
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the reader
+    pub fn read_row(&mut self) -> Result<Vec<Rgb>, String> {
+        // the buffer only grows as the bytes of the row arrive, so a header claiming a huge width cannot make it
+        // allocate more than the input holds
+        self.buffer.clear();
+        let read = self.reader.by_ref().take(self.row_len as u64).read_to_end(&mut self.buffer).map_err(|e| e.to_string())?;
+        if read != self.row_len {
+            return Err(format!("image ends early: expected {} bytes in a row, found {}", self.row_len, read));
+        }
+        let sample_bytes = if self.maxval < 256 { 1 } else { 2 };
+        let samples: Vec<u16> = self.buffer.chunks_exact(sample_bytes)
+            .map(|b| b.iter().fold(0_u16, |v, &byte| (v << 8) | byte as u16))
+            .collect();
+        if let Some(pos) = samples.iter().position(|&v| v > self.maxval) {
+            return Err(format!("sample {} of a row is {}, which exceeds the maxval of {}", pos, samples[pos], self.maxval));
+        }
+        Ok(match self.model {
+            ColourModel::Gray => samples.iter().map(|&v| Rgb { red: v, green: v, blue: v }).collect(),
+            ColourModel::Rgb => samples.chunks_exact(3).map(|p| Rgb { red: p[0], green: p[1], blue: p[2] }).collect(),
+        })
+    }
+}
+
+/// Compresses a binary PGM or PPM read from `input` into `output`, two rows of pixels at a time. Each pair of rows
+/// is written out as a row of words as soon as it is read, so memory use does not grow with the height of the image.
+/// The length of the words is known from the header of the image, and the CRC-32 is kept up to date as each row is
+/// written, so the output is byte for byte the same as compressing the whole image at once.
+///
+/// # Arguments:
+/// * `input`: the reader holding the image
+/// * `output`: the writer receiving the rpeg file
+pub fn compress_stream<R: BufRead, W: Write>(input: R, output: &mut W) -> Result<(), String> {
+    let mut rows = RowReader::new(input)?;
+    // trim the width and height so that they are rounded down to the nearest even number
+    let (width, height) = (rows.width & !1, rows.height & !1);
+    let mut header = Header {
+        width, height, model: rows.model, denominator: rows.maxval, alpha: None, restart: None, tiles: None,
+        progressive: false, pyramid: None, length: None, checksum: true
+    };
+    let (layout, denominator) = (header.layout(), rows.maxval as f64);
+    header.length = Some(
+        (width as usize / 2).checked_mul(height as usize / 2 * layout.bytes()).ok_or("image dimensions are too large")?
+    );
+    let header_bytes = header.to_bytes();
+    output.write_all(&header_bytes).map_err(|e| e.to_string())?;
+    let mut crc = checksum::crc32(&header_bytes);
+
+    let mut bytes = Vec::new();
+    for _ in 0..height / 2 {
+        let (top, bottom) = (rows.read_row()?, rows.read_row()?);
+        bytes.clear();
+        for col in (0..width as usize).step_by(2) {
+            let word = codec::encode_block([&top[col], &top[col + 1], &bottom[col], &bottom[col + 1]], denominator, layout);
+            bytes.extend_from_slice(&word.to_be_bytes()[8 - layout.bytes()..]);
+        }
+        output.write_all(&bytes).map_err(|e| e.to_string())?;
+        crc = checksum::crc32_update(crc, &bytes);
+    }
+    output.write_all(&crc.to_be_bytes()).map_err(|e| e.to_string())?;
+    output.flush().map_err(|e| e.to_string())
+}
+
+/// Decompresses an rpeg file read from `input` into a binary PGM or PPM written to `output`, one row of words
+/// at a time. The output is byte for byte the same as decompressing the whole image at once.
+/// Images with an alpha plane cannot be streamed, since the plane follows all of the color words, and neither can
+/// images with restart markers, tiles, progressive passes or a pyramid.
+/// The CRC-32 of a file with a `checksum` can only be compared once every row has been written, so a mismatch
+/// is returned as an error after the output is complete.
+///
+/// # Arguments:
+/// * `input`: the reader holding the rpeg file
+/// * `output`: the writer receiving the image
+pub fn decompress_stream<R: BufRead, W: Write>(input: R, output: &mut W) -> Result<(), String> {
+    decompress_stream_with_limits(input, output, DecodeLimits::default())
+}
+
+/// Decompresses an rpeg file like `decompress_stream`, rejecting it before anything is written if its dimensions
+/// exceed `limits`. Only one row of words is held at a time, so the memory limit does not apply.
+///
+/// # Arguments:
+/// * `input`: the reader holding the rpeg file
+/// * `output`: the writer receiving the image
+/// * `limits`: the largest image to accept
+pub fn decompress_stream_with_limits<R: BufRead, W: Write>(input: R, output: &mut W, limits: DecodeLimits) -> Result<(), String> {
+    let mut input = CrcReader { inner: input, crc: 0 };
+    let header = Header::read(&mut input)?;
+    limits.check_dimensions(header.width, header.height)?;
+    if header.alpha.is_some() {
+        return Err("images with an alpha plane cannot be decompressed as a stream".to_string());
+    }
+    if header.restart.is_some() {
+        return Err("images with restart markers cannot be decompressed as a stream".to_string());
+    }
+    if header.tiles.is_some() {
+        return Err("tiled images cannot be decompressed as a stream".to_string());
+    }
+    if header.progressive {
+        return Err("progressive images cannot be decompressed as a stream".to_string());
+    }
+    if header.pyramid.is_some() {
+        return Err("images with a pyramid cannot be decompressed as a stream".to_string());
+    }
+    let (width, height) = (header.width as usize / 2 * 2, header.height as usize / 2 * 2);
+    let (layout, denominator) = (header.layout(), header.denominator as f64);
+    let magic = if header.model == ColourModel::Gray { "P5" } else { "P6" };
+    write!(output, "{}\n{} {}\n{}\n", magic, width, height, header.denominator).map_err(|e| e.to_string())?;
+
+    // as in `RowReader::read_row`, the buffer only grows as the bytes of a row arrive
+    let row_len = (width / 2).checked_mul(layout.bytes()).ok_or("image dimensions are too large")?;
+    let expected = row_len.checked_mul(height / 2).ok_or("image dimensions are too large")?;
+    if let Some(n) = header.length.filter(|&n| n != expected) {
+        return Err(format!("header records {} bytes of words, but the image needs {}", n, expected));
+    }
+    let mut words = Vec::new();
+    let (mut top, mut bottom) = (Vec::new(), Vec::new());
+    for word_row in 0..height / 2 {
+        words.clear();
+        let read = input.by_ref().take(row_len as u64).read_to_end(&mut words).map_err(|e| e.to_string())?;
+        if read != row_len {
+            let word = word_row * (width / 2) + read / layout.bytes();
+            return Err(format!("compressed image is truncated at word {} of {}", word, width / 2 * (height / 2)));
+        }
+        top.clear();
+        bottom.clear();
+        for chunk in words.chunks_exact(layout.bytes()) {
+            let word = chunk.iter().fold(0_u64, |word, &byte| (word << 8) | byte as u64);
+            let [top_left, top_right, bottom_left, bottom_right] = codec::decode_block(word, denominator, layout);
+            top.extend([top_left, top_right]);
+            bottom.extend([bottom_left, bottom_right]);
+        }
+        for row in [&top, &bottom] {
+            output.write_all(&row_bytes(row, header.model, header.denominator)).map_err(|e| e.to_string())?;
+        }
+    }
+    output.flush().map_err(|e| e.to_string())?;
+
+    if header.checksum {
+        let computed = input.crc;
+        let mut trailer = [0; CHECKSUM_BYTES];
+        input.read_exact(&mut trailer).map_err(|_| "compressed image is truncated in its checksum".to_string())?;
+        let recorded = u32::from_be_bytes(trailer);
+        if recorded != computed {
+            return Err(format!("checksum mismatch: the file records {:08x}, but its contents give {:08x}", recorded, computed));
+        }
+    }
+    Ok(())
+}
+
+/// Helper struct that passes reads through to `inner` while keeping the CRC-32 of every byte read so far
+struct CrcReader<R> {
+    inner: R,
+    crc: u32,
+}
+
+impl<R: BufRead> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = checksum::crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CrcReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the bytes being consumed are still at the front of the inner buffer
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.crc = checksum::crc32_update(self.crc, &buf[..amt]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Helper function that returns a row of pixels as the bytes of a binary PGM or PPM raster.
+/// Gray images keep only the red value of each pixel.
+///
+/// # Arguments:
+/// * `row`: the pixels of the row
+/// * `model`: the colour model of the image
+/// * `maxval`: the largest sample value, which decides between 1 and 2 bytes per sample
+fn row_bytes(row: &[Rgb], model: ColourModel, maxval: u16) -> Vec<u8> {
+    let samples = row.iter().flat_map(|p| match model {
+        ColourModel::Gray => vec![p.red],
+        ColourModel::Rgb => vec![p.red, p.green, p.blue],
+    });
+    if maxval < 256 {
+        samples.map(|v| v as u8).collect()
+    } else {
+        samples.flat_map(|v| v.to_be_bytes()).collect()
+    }
+}
+
+/// Helper function that returns the next whitespace separated token of a Netpbm header, skipping comments.
+/// The single whitespace character after the token is consumed with it.
+///
+/// # Arguments:
+/// * `reader`: the reader positioned inside the header
+fn next_token<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    let mut token = String::new();
+    let mut comment = false;
+    loop {
+        let byte = match reader.fill_buf().map_err(|e| e.to_string())?.first() {
+            Some(&b) => b,
+            None if token.is_empty() => return Err("header ends early".to_string()),
+            None => return Ok(token),
+        };
+        reader.consume(1);
+        match byte {
+            b'\n' if comment => comment = false,
+            _ if comment => (),
+            b'#' if token.is_empty() => comment = true,
+            _ if byte.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Ok(token);
+                }
+            }
+            _ => token.push(byte as char),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec;
+    use crate::pnm::{self, Format};
+    use crate::limits::DecodeLimits;
+    use crate::stream::{compress_stream, decompress_stream, decompress_stream_with_limits};
+    use crate::testimages;
+
+
+    #[test]
+    fn test_matches_whole_image() {
+        for (img, format) in [
+            (testimages::noise(9, 7, 2), Format::RawPpm),
+            (testimages::zone_plate(9, 7), Format::RawPgm),
+            (testimages::rescale(&testimages::gradient(9, 7), 65535), Format::RawPpm),
+        ] {
+            let compressed = codec::compress_image(&img);
+            let mut streamed = Vec::new();
+            compress_stream(&pnm::encode(&img, format).unwrap()[..], &mut streamed).unwrap();
+            assert_eq!(streamed, compressed.to_bytes());
+
+            let decompressed = codec::decompress_image(&compressed);
+            let mut streamed_back = Vec::new();
+            decompress_stream(&streamed[..], &mut streamed_back).unwrap();
+            assert_eq!(streamed_back, pnm::encode(&decompressed, format).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_header_comments() {
+        let mut streamed = Vec::new();
+        compress_stream(&b"P5\n# a comment\n2 2 # another\n255\n\x00\x40\x80\xff"[..], &mut streamed).unwrap();
+        assert_eq!(&streamed[..27], b"Compressed image format 3\n2");
+    }
+
+    #[test]
+    fn test_bad_streams() {
+        let mut out = Vec::new();
+        assert!(compress_stream(&b"P3\n2 2\n255\n"[..], &mut out).is_err());
+        assert!(compress_stream(&b"P6\n2 2\n255\n\x00\x00\x00"[..], &mut out).is_err());
+        assert!(compress_stream(&b"P5\n2 2\n15\n\x00\x10\x00\x00"[..], &mut out).is_err());
+
+        let compressed = codec::compress_image(&testimages::gradient(9, 7)).to_bytes();
+        assert!(decompress_stream(&compressed[..compressed.len() - 1], &mut out).is_err());
+        // 12 words of 4 bytes and a 4 byte checksum follow the header
+        assert_eq!(
+            decompress_stream(&compressed[..compressed.len() - 35], &mut out).unwrap_err(),
+            "compressed image is truncated at word 4 of 12"
+        );
+        let mut flipped = compressed.clone();
+        flipped[compressed.len() - 20] ^= 0x10;
+        assert!(decompress_stream(&flipped[..], &mut out).unwrap_err().starts_with("checksum mismatch"));
+        let with_alpha = codec::compress_image(&testimages::gradient(9, 7).with_alpha(&[128; 63])).to_bytes();
+        assert!(decompress_stream(&with_alpha[..], &mut out).is_err());
+
+        // a header claiming a huge width must not reserve a row of that size before the bytes arrive
+        assert!(compress_stream(&b"P6\n4000000000 2\n65535\n\x00"[..], &mut out).is_err());
+        let wide = b"Compressed image format 2\n4000000000 2\n\x00";
+        assert!(decompress_stream_with_limits(&wide[..], &mut out, DecodeLimits::unlimited()).is_err());
+        assert_eq!(decompress_stream(&wide[..], &mut out).unwrap_err(), "image width 4000000000 exceeds the limit of 65535");
+    }
+}
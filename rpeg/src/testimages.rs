@@ -0,0 +1,239 @@
+use crate::pnm::PnmImage;
+
+/// A small pseudo random number generator (xorshift64*) that produces the same numbers from the same seed on every
+/// platform, for generating noise and test inputs
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Returns a generator seeded with `seed`. Every seed, including 0, gives a different sequence.
+    ///
+    /// # Arguments:
+    /// * `seed`: the seed
+    pub fn new(seed: u64) -> Self {
+        // one round of splitmix64 spreads the bits of small seeds out, and never leaves the state at 0
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng { state: (z ^ (z >> 31)) | 1 }
+    }
+
+    /// Returns the next number in the sequence
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the generator
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a number from 0 up to but not including `limit`
+    ///
+    /// # Arguments:
+    /// * `&mut self`: a mutable reference to the generator
+    /// * `limit`: one more than the largest number to return, which must be positive
+    pub fn below(&mut self, limit: u64) -> u64 {
+        ((self.next_u64() as u128 * limit as u128) >> 64) as u64
+    }
+}
+
+/// Helper function that returns an 8 bit image with `N` samples per pixel, which is a `GRAYSCALE` image when `N` is 1
+/// and an `RGB` image when it is 3
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+/// * `pixel`: returns the samples of the pixel at a column and row
+fn generate<const N: usize>(width: u32, height: u32, mut pixel: impl FnMut(u32, u32) -> [u16; N]) -> PnmImage {
+    let mut samples = Vec::with_capacity(width as usize * height as usize * N);
+    for y in 0..height {
+        for x in 0..width {
+            samples.extend(pixel(x, y));
+        }
+    }
+    let tupltype = if N == 1 { "GRAYSCALE" } else { "RGB" };
+    PnmImage { width, height, depth: N as u32, maxval: 255, tupltype: tupltype.to_string(), samples }
+}
+
+/// Returns a color image where red increases from left to right, green from top to bottom,
+/// and blue from the top left corner to the bottom right
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+pub fn gradient(width: u32, height: u32) -> PnmImage {
+    let (w, h) = (width.max(2) - 1, height.max(2) - 1);
+    generate(width, height, |x, y| [(x * 255 / w) as u16, (y * 255 / h) as u16, ((x + y) * 255 / (w + h)) as u16])
+}
+
+/// Returns a grayscale image of black and white squares, with a black square in the top left corner
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+/// * `square`: the width and height of each square, which must be positive
+pub fn checkerboard(width: u32, height: u32, square: u32) -> PnmImage {
+    generate(width, height, |x, y| [if (x / square + y / square).is_multiple_of(2) { 0 } else { 255 }])
+}
+
+/// Returns a color image of eight vertical bars, in the order white, yellow, cyan, green, magenta, red, blue, black
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+pub fn colour_bars(width: u32, height: u32) -> PnmImage {
+    const BARS: [[u16; 3]; 8] = [
+        [255, 255, 255], [255, 255, 0], [0, 255, 255], [0, 255, 0], [255, 0, 255], [255, 0, 0], [0, 0, 255], [0, 0, 0]
+    ];
+    generate(width, height, |x, _| BARS[(x as usize * BARS.len()) / width as usize])
+}
+
+/// Returns a color image where every sample is drawn independently from `Rng::new(seed)`
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+/// * `seed`: the seed of the noise, where the same seed always gives the same image
+pub fn noise(width: u32, height: u32, seed: u64) -> PnmImage {
+    let mut rng = Rng::new(seed);
+    generate(width, height, |_, _| [0; 3].map(|_| rng.below(256) as u16))
+}
+
+/// Returns a grayscale zone plate: rings around the center whose frequency rises with the square of the distance
+/// from it, reaching the highest frequency the pixels can hold at the left and right edges
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of the image
+pub fn zone_plate(width: u32, height: u32) -> PnmImage {
+    // the phase at a squared distance r2 from the center is r2 / (2 * width) turns, which is worked out exactly
+    // in integers before the cosine
+    let period = 2 * width.max(1) as i64;
+    let (cx, cy) = (width as i64 / 2, height as i64 / 2);
+    generate(width, height, |x, y| {
+        let r2 = (x as i64 - cx).pow(2) + (y as i64 - cy).pow(2);
+        let turns = (r2 % period) as f64 / period as f64;
+        [((1.0 + cosine(turns)) * 127.5).round() as u16]
+    })
+}
+
+/// Helper function that returns the cosine of an angle of `turns` full turns, where `turns` is from 0 up to 1.
+/// It uses only addition, subtraction, multiplication and division, which give the same result on every platform,
+/// unlike the cosine of the platform's math library.
+///
+/// # Arguments:
+/// * `turns`: the angle, as a fraction of a full turn
+fn cosine(turns: f64) -> f64 {
+    // move the angle into -pi to pi, where the series converges quickly
+    let x = (if turns > 0.5 { turns - 1.0 } else { turns }) * 2.0 * std::f64::consts::PI;
+    let (mut term, mut sum) = (1.0, 1.0);
+    for n in 1..=20 {
+        term = -term * x * x / ((2 * n - 1) * (2 * n)) as f64;
+        sum += term;
+    }
+    sum
+}
+
+/// Returns `img` with its samples scaled from its maxval to `maxval`, rounding to the nearest value
+///
+/// # Arguments:
+/// * `img`: the image to scale
+/// * `maxval`: the new largest sample value, which must be positive
+pub fn rescale(img: &PnmImage, maxval: u16) -> PnmImage {
+    let (from, to) = (img.maxval as u64, maxval as u64);
+    let samples = img.samples.iter().map(|&v| ((v as u64 * to + from / 2) / from) as u16).collect();
+    PnmImage { maxval, samples, ..img.clone() }
+}
+
+/// Returns the peak signal to noise ratio of `decoded` against `original` in decibels, comparing the pixels that
+/// `decoded` covers, which may be fewer than `original` has after trimming. Identical images give infinity.
+///
+/// # Arguments:
+/// * `original`: the image before compression
+/// * `decoded`: the image after compression and decompression, with the same depth and maxval
+pub fn psnr(original: &PnmImage, decoded: &PnmImage) -> f64 {
+    let depth = decoded.depth as usize;
+    let mut squared_error = 0.0;
+    for row in 0..decoded.height as usize {
+        let start = row * original.width as usize * depth;
+        let original_row = &original.samples[start..start + decoded.width as usize * depth];
+        let decoded_row = &decoded.samples[row * decoded.width as usize * depth..][..decoded.width as usize * depth];
+        squared_error += original_row.iter().zip(decoded_row).map(|(&a, &b)| (a as f64 - b as f64).powi(2)).sum::<f64>();
+    }
+    let mean = squared_error / decoded.samples.len() as f64;
+    10.0 * (decoded.maxval as f64 * decoded.maxval as f64 / mean).log10()
+}
+
+/// Returns every generated image of the given size with a name for each, covering smooth and sharp edges,
+/// grayscale and color, and 8 and 16 bit samples
+///
+/// # Arguments:
+/// * `width`, `height`: the dimensions of each image
+pub fn all(width: u32, height: u32) -> Vec<(&'static str, PnmImage)> {
+    vec![
+        ("gradient", gradient(width, height)),
+        ("gradient16", rescale(&gradient(width, height), 65535)),
+        ("checkerboard", checkerboard(width, height, 4)),
+        ("colour_bars", colour_bars(width, height)),
+        ("noise", noise(width, height, 1)),
+        ("zone_plate", zone_plate(width, height)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testimages::{all, checkerboard, colour_bars, cosine, gradient, noise, psnr, rescale, zone_plate, Rng};
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(7);
+        let first: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+        let mut again = Rng::new(7);
+        assert_eq!(first, (0..4).map(|_| again.next_u64()).collect::<Vec<_>>());
+        assert_ne!(Rng::new(0).next_u64(), Rng::new(1).next_u64());
+        assert!((0..1000).all(|_| rng.below(10) < 10));
+    }
+
+    #[test]
+    fn test_shapes() {
+        for (name, img) in all(13, 9) {
+            assert_eq!((img.width, img.height), (13, 9), "{}", name);
+            assert_eq!(img.samples.len(), (13 * 9 * img.depth) as usize, "{}", name);
+            assert!(img.samples.iter().all(|&v| v <= img.maxval), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        let img = gradient(5, 3);
+        assert_eq!(&img.samples[..3], &[0, 0, 0]);
+        assert_eq!(&img.samples[img.samples.len() - 3..], &[255, 255, 255]);
+
+        assert_eq!(checkerboard(4, 2, 2).samples, vec![0, 0, 255, 255, 0, 0, 255, 255]);
+        assert_eq!(&colour_bars(8, 1).samples[9..12], &[0, 255, 0]);
+
+        assert_eq!(noise(6, 6, 3), noise(6, 6, 3));
+        assert_ne!(noise(6, 6, 3), noise(6, 6, 4));
+
+        // the center of a zone plate is white, and its rings are symmetric about the center
+        let img = zone_plate(9, 9);
+        assert_eq!(img.samples[4 * 9 + 4], 255);
+        assert_eq!(img.samples[4 * 9], img.samples[4 * 9 + 8]);
+
+        assert_eq!(rescale(&gradient(5, 3), 65535).samples[3], 63 * 257);
+    }
+
+    #[test]
+    fn test_cosine() {
+        for (turns, expected) in [(0.0, 1.0), (0.25, 0.0), (0.5, -1.0), (0.125, 0.5_f64.sqrt()), (0.9, 0.8090169943749475)] {
+            assert!((cosine(turns) - expected).abs() < 1e-12, "cos of {} turns is {}", turns, cosine(turns));
+        }
+    }
+
+    #[test]
+    fn test_psnr() {
+        let img = gradient(6, 4);
+        assert_eq!(psnr(&img, &img), f64::INFINITY);
+        let mut off_by_one = img.clone();
+        off_by_one.samples.iter_mut().for_each(|v| *v ^= 1);
+        assert!((psnr(&img, &off_by_one) - 48.13).abs() < 0.01);
+    }
+}
@@ -0,0 +1,186 @@
+use std::ops::Range;
+use array2::Array2;
+
+/// The tiles of a compressed image, as named by the `tiles` and `index` lines of its header
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TileIndex {
+    /// the width of every tile but those in the last column, in pixels
+    pub width: u32,
+    /// the height of every tile but those in the last row, in pixels
+    pub height: u32,
+    /// the offset of the words of each tile from the end of the header, in bytes, with the tiles in row major order
+    pub offsets: Vec<usize>,
+}
+
+/// The layout of the words of an image split into tiles of `tile_columns` x `tile_rows` blocks. The words of each
+/// tile are stored together in row major order, and the tiles follow each other in row major order, so that a
+/// region of the image can be decoded by reading only the tiles it covers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tiles {
+    /// the number of blocks across every tile but those in the last column, which may be narrower
+    pub tile_columns: usize,
+    /// the number of blocks down every tile but those in the last row, which may be shorter
+    pub tile_rows: usize,
+    /// the number of blocks across the image
+    pub columns: usize,
+    /// the number of blocks down the image
+    pub rows: usize,
+}
+
+impl Tiles {
+    /// Returns the layout described by `index` for an image of `width` x `height` pixels, or an error if its
+    /// offsets are not those of tiles of `word_bytes` byte words stored one after another
+    ///
+    /// # Arguments:
+    /// * `index`: the tiles named by the header
+    /// * `width`, `height`: the even dimensions of the image
+    /// * `word_bytes`: the number of bytes in each word
+    pub fn from_index(index: &TileIndex, width: u32, height: u32, word_bytes: usize) -> Result<Self, String> {
+        let tiles = Tiles {
+            tile_columns: index.width as usize / 2,
+            tile_rows: index.height as usize / 2,
+            columns: width as usize / 2,
+            rows: height as usize / 2,
+        };
+        if index.offsets.len() != tiles.count() {
+            return Err(format!("tile index has {} entries, but the image has {} tiles", index.offsets.len(), tiles.count()));
+        }
+        let expected = tiles.offsets(word_bytes);
+        match index.offsets.iter().zip(&expected).position(|(found, expected)| found != expected) {
+            Some(k) => Err(format!("tile {} is recorded at byte {}, but starts at byte {}", k, index.offsets[k], expected[k])),
+            None => Ok(tiles),
+        }
+    }
+
+    /// Returns the number of tiles across the image
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    pub fn across(&self) -> usize {
+        self.columns.div_ceil(self.tile_columns)
+    }
+
+    /// Returns the number of tiles
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    pub fn count(&self) -> usize {
+        self.across() * self.rows.div_ceil(self.tile_rows)
+    }
+
+    /// Returns the block rows and block columns covered by tile `k`
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `k`: the index of the tile, in row major order
+    pub fn tile(&self, k: usize) -> (Range<usize>, Range<usize>) {
+        let (row, col) = (k / self.across() * self.tile_rows, k % self.across() * self.tile_columns);
+        (row..(row + self.tile_rows).min(self.rows), col..(col + self.tile_columns).min(self.columns))
+    }
+
+    /// Returns the offset of the words of every tile, in bytes
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `word_bytes`: the number of bytes in each word
+    pub fn offsets(&self, word_bytes: usize) -> Vec<usize> {
+        let mut offset = 0;
+        (0..self.count()).map(|k| {
+            let start = offset;
+            let (rows, cols) = self.tile(k);
+            offset += rows.len() * cols.len() * word_bytes;
+            start
+        }).collect()
+    }
+
+    /// Returns the tiles that hold any of the blocks in `rows` and `cols`, in row major order
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `rows`, `cols`: the block rows and block columns of the region
+    pub fn covering(&self, rows: Range<usize>, cols: Range<usize>) -> Vec<usize> {
+        if rows.is_empty() || cols.is_empty() {
+            return Vec::new();
+        }
+        let (tile_rows, tile_cols) = (rows.start / self.tile_rows..=(rows.end - 1) / self.tile_rows,
+                                      cols.start / self.tile_columns..=(cols.end - 1) / self.tile_columns);
+        tile_rows.flat_map(|row| tile_cols.clone().map(move |col| row * self.across() + col)).collect()
+    }
+
+    /// Appends the low `word_bytes` bytes of every word in `words` to `out`, in Big-Endian order, tile by tile
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `words`: the grid of words of the image
+    /// * `word_bytes`: the number of bytes in each word
+    /// * `out`: the bytes of the file so far
+    pub fn write(&self, words: &Array2<u64>, word_bytes: usize, out: &mut Vec<u8>) {
+        for k in 0..self.count() {
+            let (rows, cols) = self.tile(k);
+            for row in rows {
+                for col in cols.clone() {
+                    out.extend_from_slice(&words.get(row, col).to_be_bytes()[8 - word_bytes..]);
+                }
+            }
+        }
+    }
+
+    /// Returns the words held tile by tile in `words`, rearranged into row major order over the whole image
+    ///
+    /// # Arguments:
+    /// * `&self`: an immutable reference to the layout
+    /// * `words`: the words of every tile, one after another
+    pub fn untile(&self, words: &[u64]) -> Vec<u64> {
+        let mut image = vec![0; self.rows * self.columns];
+        let mut words = words.iter();
+        for k in 0..self.count() {
+            let (rows, cols) = self.tile(k);
+            for row in rows {
+                for (dest, word) in image[row * self.columns..][cols.clone()].iter_mut().zip(&mut words) {
+                    *dest = *word;
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use array2::Array2;
+    use crate::tiles::{TileIndex, Tiles};
+
+    /// 5 x 3 blocks in tiles of 2 x 2 blocks
+    const TILES: Tiles = Tiles { tile_columns: 2, tile_rows: 2, columns: 5, rows: 3 };
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(TILES.count(), 6);
+        assert_eq!(TILES.tile(2), (0..2, 4..5));
+        assert_eq!(TILES.tile(4), (2..3, 2..4));
+        assert_eq!(TILES.offsets(1), vec![0, 4, 8, 10, 12, 14]);
+        assert_eq!(TILES.covering(1..3, 3..5), vec![1, 2, 4, 5]);
+        assert_eq!(TILES.covering(0..1, 0..1), vec![0]);
+        assert_eq!(TILES.covering(0..0, 0..5), vec![]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let words: Vec<u64> = (0..15).collect();
+        let mut bytes = Vec::new();
+        TILES.write(&Array2::from(words.clone(), 3, 5), 1, &mut bytes);
+        assert_eq!(bytes, vec![0, 1, 5, 6, 2, 3, 7, 8, 4, 9, 10, 11, 12, 13, 14]);
+        let tiled: Vec<u64> = bytes.iter().map(|&b| b as u64).collect();
+        assert_eq!(TILES.untile(&tiled), words);
+    }
+
+    #[test]
+    fn test_index() {
+        let index = TileIndex { width: 4, height: 4, offsets: vec![0, 8, 16, 20, 24, 28] };
+        assert_eq!(Tiles::from_index(&index, 10, 6, 2).unwrap(), TILES);
+        let short = TileIndex { offsets: vec![0, 8], ..index.clone() };
+        assert_eq!(Tiles::from_index(&short, 10, 6, 2).unwrap_err(), "tile index has 2 entries, but the image has 6 tiles");
+        let wrong = TileIndex { offsets: vec![0, 8, 16, 21, 24, 28], ..index };
+        assert_eq!(Tiles::from_index(&wrong, 10, 6, 2).unwrap_err(), "tile 3 is recorded at byte 21, but starts at byte 20");
+    }
+}